@@ -43,53 +43,101 @@
     clippy::used_underscore_binding,
 )]
 
-use std::{env, path::PathBuf};
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Result;
 use bevy::{
+    asset::io::file::FileAssetReader,
     prelude::*,
     window::{PrimaryWindow, WindowResolution},
     {tasks::AsyncComputeTaskPool, tasks::Task},
 };
-use bevy_egui::{
-    egui::{self, TextureHandle},
-    EguiContexts, EguiPlugin,
-};
+use bevy_egui::{egui, EguiContexts, EguiPlugin};
 use bevy_simple_tilemap::prelude::*;
 use futures_lite::future;
 use rfd::FileDialog;
 
-use shared::tilemap::MapScreen;
+use shared::tilemap::{ldtk, tiled, Diagnostic, MapScreen, MapTile};
 use shared::{
+    animation::animate_tiles,
+    autotile,
+    error::MapError,
     settings::{GameSettings, SettingsFile},
     tilemap::{top_left_to_coord, TileCoords},
+    tileset::{frame_at, TilesetMeta},
 };
 
+// the sliced tiles of one of the map's tilesets, in atlas index order
+struct Palette {
+    tileset_id: u32,
+    name: String,
+    tile_handles: Vec<egui::TextureHandle>,
+}
+
 #[derive(Resource, Default)]
 struct UiState {
     current_map: MapScreen,
-    tile_handles: Option<Vec<egui::TextureHandle>>,
+    palettes: Vec<Palette>,
+    active_palette: usize,
+    // sidecars for the map's tilesets, keyed by tileset id
+    tileset_metas: HashMap<u32, TilesetMeta>,
     tile_source: Option<PathBuf>,
     tile_size: [usize; 2],
-    selected_tile: Option<TextureHandle>,
+    // index into the active palette
+    selected_tile: Option<usize>,
+    // a terrain from the active palette's sidecar, painted instead of a
+    // single tile
+    selected_terrain: Option<String>,
     cursor_pos: Option<Vec2>,
     current_tile: Option<TileCoords>,
+    // the map has changed since it was last drawn
+    map_dirty: bool,
+    // what went wrong importing or exporting, and the window title for it
+    file_error: Option<(&'static str, String)>,
+    // from the last time the map was checked. None when the problems panel
+    // is closed.
+    problems: Option<Vec<Diagnostic>>,
 }
 
+// the drawn tile maps, so they can be replaced when the map changes
+#[derive(Component)]
+struct EditorTileMap;
+
 #[derive(Resource, Default)]
 struct FileDialogState {
     chosen_file: Option<PathBuf>,
     error_message: Option<String>,
     dialog_open: bool,
     new_map: bool,
+    // the dialog is adding a tileset to the current map, not creating one
+    adding_tileset: bool,
+    add_tileset: bool,
 }
 
 #[derive(Component)]
 struct SelectedFile(Task<Option<PathBuf>>);
 
+// a map picked to import from another editor
+#[derive(Component)]
+struct ImportFile(Task<Option<PathBuf>>);
+
+#[derive(Debug, Clone, Copy)]
+enum ExportFormat {
+    Tiled,
+    Ldtk,
+}
+
+// where to write the current map for another editor
+#[derive(Component)]
+struct ExportFile(Task<Option<PathBuf>>, ExportFormat);
+
 fn main() -> Result<()> {
     let settings_file = env::var("CONFIG_FILE").unwrap_or("settings.ron".to_string());
-    let sf = SettingsFile::new_from_file(&settings_file)?;
+    let sf = SettingsFile::load(&settings_file)?;
     let settings = GameSettings::new_from_sf(&sf, true);
 
     println!(
@@ -118,15 +166,20 @@ fn main() -> Result<()> {
         .insert_resource(settings)
         .init_resource::<FileDialogState>()
         .init_resource::<UiState>()
-        .add_systems(Startup, (setup_camera, draw_map.pipe(error_handler)))
+        .add_systems(Startup, setup_camera)
         .add_systems(
             Update,
             (
                 poll_file_dialog,
+                poll_import.pipe(error_handler),
+                poll_export,
                 draw_ui.pipe(error_handler),
                 mouse_button_input.pipe(error_handler),
-            ),
+                draw_map.pipe(error_handler),
+            )
+                .chain(),
         )
+        .add_systems(Update, animate_tiles)
         .run();
     Ok(())
 }
@@ -156,6 +209,112 @@ fn poll_file_dialog(
     }
 }
 
+// where bevy loads assets from, which map tileset paths are relative to
+fn assets_root() -> PathBuf {
+    FileAssetReader::get_base_path().join("assets")
+}
+
+// swaps in a map from tiled or ldtk once one has been picked. a map that
+// can't be imported leaves the current one alone.
+fn poll_import(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut ImportFile)>,
+    mut ui_state: ResMut<UiState>,
+    mut contexts: EguiContexts,
+) -> Result<()> {
+    for (entity, mut import_file) in tasks.iter_mut() {
+        let Some(result) = future::block_on(future::poll_once(&mut import_file.0)) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        let Some(path) = result else {
+            continue;
+        };
+
+        let root = assets_root();
+        // the editor holds one map at a time, so an ldtk project opens
+        // its first level
+        let imported = match path.extension().and_then(|e| e.to_str()) {
+            Some("ldtk") => ldtk::import(&path, &root).and_then(|maps| {
+                maps.into_iter().next().ok_or_else(|| MapError::Invalid {
+                    path: path.clone(),
+                    message: "has no levels".into(),
+                })
+            }),
+            _ => tiled::import(&path, &root),
+        };
+        let ms = match imported {
+            Ok(ms) => ms,
+            Err(e) => {
+                ui_state.file_error = Some(("Import failed", e.to_string()));
+                continue;
+            }
+        };
+        let metas = ms.load_tileset_metas(&root)?;
+
+        let ctx = contexts.ctx_mut();
+        let mut palettes = vec![];
+        for ts in &ms.tilesets {
+            let meta = metas.get(&ts.id).cloned().unwrap_or_default();
+            let (tile_handles, _, _) =
+                load_palette(ctx, &root.join(&ts.path), &meta, ui_state.tile_size)?;
+            palettes.push(Palette {
+                tileset_id: ts.id,
+                name: ts
+                    .path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                tile_handles,
+            });
+        }
+
+        info!("imported {} from {}", ms.map_name, path.to_string_lossy());
+        ui_state.current_map = ms;
+        ui_state.tileset_metas = metas;
+        ui_state.palettes = palettes;
+        ui_state.active_palette = 0;
+        ui_state.selected_tile = None;
+        ui_state.selected_terrain = None;
+        ui_state.file_error = None;
+        ui_state.map_dirty = true;
+    }
+    Ok(())
+}
+
+fn poll_export(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut ExportFile)>,
+    settings: Res<GameSettings>,
+    mut ui_state: ResMut<UiState>,
+) {
+    for (entity, mut export_file) in tasks.iter_mut() {
+        let Some(result) = future::block_on(future::poll_once(&mut export_file.0)) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        let Some(path) = result else {
+            continue;
+        };
+
+        let tile_size = (settings.tile_width as u32, settings.tile_height as u32);
+        let export = match export_file.1 {
+            ExportFormat::Tiled => tiled::export,
+            ExportFormat::Ldtk => ldtk::export,
+        };
+        match export(
+            &ui_state.current_map,
+            &ui_state.tileset_metas,
+            tile_size,
+            &path,
+            &assets_root(),
+        ) {
+            Ok(()) => info!("exported map to {}", path.to_string_lossy()),
+            Err(e) => ui_state.file_error = Some(("Export failed", e.to_string())),
+        }
+    }
+}
+
 fn load_image_from_path(path: &std::path::Path) -> Result<egui::ColorImage, image::ImageError> {
     let image = image::io::Reader::open(path)?.decode()?;
     let size = [image.width() as _, image.height() as _];
@@ -167,56 +326,133 @@ fn load_image_from_path(path: &std::path::Path) -> Result<egui::ColorImage, imag
     ))
 }
 
+// slices a sprite sheet into one texture per tile, row by row so the
+// position in the list matches the atlas index. the sidecar's tile size
+// and margins win over `tile_size`. also returns the sheet's size in tiles.
+fn load_palette(
+    ctx: &egui::Context,
+    texture_path: &Path,
+    meta: &TilesetMeta,
+    tile_size: [usize; 2],
+) -> Result<(Vec<egui::TextureHandle>, u32, u32)> {
+    let tile_map_image = load_image_from_path(texture_path)?;
+    let tile_size = meta.tile_size_or((tile_size[0] as u32, tile_size[1] as u32));
+    let (columns, rows) = meta.grid_size(
+        (tile_map_image.size[0] as u32, tile_map_image.size[1] as u32),
+        tile_size,
+    );
+    let mut handles = vec![];
+    for row in 0..rows {
+        for col in 0..columns {
+            let (left, top) = meta.tile_origin(col, row, tile_size);
+            let right = left + tile_size.0;
+            let bottom = top + tile_size.1;
+            let rect = egui::Rect {
+                min: egui::pos2(left as f32, top as f32),
+                max: egui::pos2(right as f32, bottom as f32),
+            };
+            let handle = ctx.load_texture(
+                format!(
+                    "tile_{}_{left}_{top}_{right}_{bottom}",
+                    texture_path.to_string_lossy()
+                ),
+                tile_map_image.region(&rect, None),
+                Default::default(),
+            );
+            handles.push(handle);
+        }
+    }
+    Ok((handles, columns, rows))
+}
+
+// what the sidecar says about a tile, for hovering over it in the palette
+fn describe_tile(meta: Option<&TilesetMeta>, tile_index: usize) -> String {
+    let tile = meta.and_then(|m| m.tile(tile_index as u32));
+    let mut lines = vec![match tile.and_then(|t| t.name.as_ref()) {
+        Some(name) => format!("{name} ({tile_index})"),
+        None => format!("tile {tile_index}"),
+    }];
+    if let Some(tile) = tile {
+        if !tile.tags.is_empty() {
+            lines.push(format!("tags: {}", tile.tags.join(", ")));
+        }
+        if let Some(tile_type) = &tile.tile_type {
+            lines.push(format!("type: {:?}", tile_type));
+        }
+        if !tile.animation.is_empty() {
+            lines.push(format!("animated, {} frames", tile.animation.len()));
+        }
+    }
+    lines.join("\n")
+}
+
 fn draw_ui(
     mut commands: Commands,
     settings: Res<GameSettings>,
+    time: Res<Time>,
     mut ui_state: ResMut<UiState>,
     mut fds: ResMut<FileDialogState>,
     mut contexts: EguiContexts,
 ) -> Result<()> {
     let ctx = contexts.ctx_mut();
 
-    if fds.new_map {
-        if let Some(texture_path) = &ui_state.tile_source {
-            let tile_map_image = load_image_from_path(texture_path)?;
-            let tile_map_texture = ctx.load_texture(
-                "tile_map_texture",
-                tile_map_image.clone(),
-                Default::default(),
-            );
-            let tile_map_size = tile_map_texture.size();
-            let rows = tile_map_size[0] / ui_state.tile_size[0];
-            let cols = tile_map_size[1] / ui_state.tile_size[1];
-            let mut handles = vec![];
-            for row in 0..rows {
-                for col in 0..cols {
-                    let left = row * ui_state.tile_size[0];
-                    let top = col * ui_state.tile_size[1];
-                    let right = left + ui_state.tile_size[0];
-                    let bottom = top + ui_state.tile_size[1];
-                    let rect = egui::Rect {
-                        min: egui::pos2(left as f32, top as f32),
-                        max: egui::pos2(right as f32, bottom as f32),
-                    };
-                    let handle = ctx.load_texture(
-                        format!("tile_{left}_{top}_{right}_{bottom}"),
-                        tile_map_image.region(&rect, None),
-                        Default::default(),
-                    );
-                    handles.push(handle);
-                }
+    if fds.new_map || fds.add_tileset {
+        if let Some(texture_path) = ui_state.tile_source.clone() {
+            // the dialog hands back full paths, so there's no root to add
+            let meta = TilesetMeta::load_for(&texture_path, Path::new(""))?;
+            let (tile_handles, columns, rows) =
+                load_palette(ctx, &texture_path, &meta, ui_state.tile_size)?;
+            if fds.new_map {
+                // only the name typed into the dialog carries over
+                let name = ui_state.current_map.map_name.clone();
+                ui_state.current_map = MapScreen::new(
+                    settings.game_area_tile_y_max as u32,
+                    settings.game_area_tile_x_max as u32,
+                    Some(&name),
+                );
+                ui_state.palettes.clear();
+                ui_state.tileset_metas.clear();
+                ui_state.selected_tile = None;
+                ui_state.selected_terrain = None;
+                ui_state.problems = None;
             }
-            ui_state.tile_handles = Some(handles);
+            // the map keeps the path the game will load it by, when it can
+            let tileset_path = texture_path
+                .strip_prefix(assets_root())
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|_| texture_path.clone());
+            let tileset_id = ui_state
+                .current_map
+                .add_tileset(tileset_path, columns, rows);
+            ui_state.tileset_metas.insert(tileset_id, meta);
+            ui_state.map_dirty = true;
+            ui_state.palettes.push(Palette {
+                tileset_id,
+                name: texture_path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                tile_handles,
+            });
+            ui_state.active_palette = ui_state.palettes.len() - 1;
         }
         fds.new_map = false;
+        fds.add_tileset = false;
     }
 
     if fds.dialog_open {
-        egui::Window::new("Create new map").show(ctx, |ui| {
-            ui.horizontal_top(|ui| {
-                ui.label("map name:");
-                ui.text_edit_singleline(&mut ui_state.current_map.map_name);
-            });
+        let title = if fds.adding_tileset {
+            "Add tileset"
+        } else {
+            "Create new map"
+        };
+        egui::Window::new(title).show(ctx, |ui| {
+            if !fds.adding_tileset {
+                ui.horizontal_top(|ui| {
+                    ui.label("map name:");
+                    ui.text_edit_singleline(&mut ui_state.current_map.map_name);
+                });
+            }
             ui.horizontal_top(|ui| {
                 let tile_map_name = match &fds.chosen_file {
                     Some(tm) => tm.to_string_lossy().into_owned(),
@@ -241,15 +477,22 @@ fn draw_ui(
                 });
             }
             ui.horizontal_top(|ui| {
-                if ui.button("new map").clicked() {
-                    ui_state.current_map.map_id = uuid::Uuid::new_v4();
+                let confirm = if fds.adding_tileset {
+                    "add tileset"
+                } else {
+                    "new map"
+                };
+                if ui.button(confirm).clicked() {
                     if let Some(map_file) = &fds.chosen_file {
-                        ui_state.current_map.tile_map = Some(map_file.to_path_buf());
                         ui_state.tile_source = Some(map_file.to_path_buf());
                         fds.dialog_open = false;
                         fds.chosen_file = None;
                         fds.error_message = None;
-                        fds.new_map = true;
+                        if fds.adding_tileset {
+                            fds.add_tileset = true;
+                        } else {
+                            fds.new_map = true;
+                        }
                     } else {
                         fds.chosen_file = None;
                         fds.error_message = Some(String::from("No valid file was chosen!"));
@@ -262,11 +505,52 @@ fn draw_ui(
         });
     }
 
+    if let Some((title, error_message)) = ui_state.file_error.clone() {
+        egui::Window::new(title).show(ctx, |ui| {
+            ui.label(egui::RichText::new(error_message).color(egui::Color32::RED));
+            if ui.button("ok").clicked() {
+                ui_state.file_error = None;
+            }
+        });
+    }
+
+    if let Some(problems) = ui_state.problems.clone() {
+        egui::Window::new("Problems").show(ctx, |ui| {
+            if problems.is_empty() {
+                ui.label("nothing wrong with this map");
+            }
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for problem in &problems {
+                    let color = if problem.is_error() {
+                        egui::Color32::RED
+                    } else {
+                        egui::Color32::YELLOW
+                    };
+                    ui.label(egui::RichText::new(problem.to_string()).color(color));
+                }
+            });
+            ui.horizontal_top(|ui| {
+                if ui.button("check again").clicked() {
+                    ui_state.problems =
+                        Some(ui_state.current_map.validate(&settings, &assets_root()));
+                }
+                if ui.button("close").clicked() {
+                    ui_state.problems = None;
+                }
+            });
+        });
+    }
+
     egui::TopBottomPanel::top("top_panel")
         .default_height(settings.top_margin)
         .show(ctx, |ui| {
             ui.horizontal_top(|ui| {
                 if ui.button("new map").clicked() {
+                    fds.adding_tileset = false;
+                    fds.dialog_open = true;
+                }
+                if ui.button("add tileset").clicked() {
+                    fds.adding_tileset = true;
                     fds.dialog_open = true;
                 }
                 if ui.button("save map").clicked() {
@@ -275,17 +559,57 @@ fn draw_ui(
                 if ui.button("load map").clicked() {
                     info!("load map");
                 }
+                if ui.button("import…").clicked() {
+                    let dir = std::env::current_dir().unwrap_or("/".into());
+                    let thread_pool = AsyncComputeTaskPool::get();
+                    let task = thread_pool.spawn(async move {
+                        FileDialog::new()
+                            .add_filter("tiled maps", &["tmx", "tmj", "json"])
+                            .add_filter("ldtk projects", &["ldtk"])
+                            .set_directory(dir)
+                            .pick_file()
+                    });
+                    commands.spawn(ImportFile(task));
+                }
+                for (label, format, extension) in [
+                    ("export tiled…", ExportFormat::Tiled, "tmj"),
+                    ("export ldtk…", ExportFormat::Ldtk, "ldtk"),
+                ] {
+                    if ui.button(label).clicked() {
+                        let dir = std::env::current_dir().unwrap_or("/".into());
+                        let file_name = format!("{}.{}", ui_state.current_map.map_name, extension);
+                        let thread_pool = AsyncComputeTaskPool::get();
+                        let task = thread_pool.spawn(async move {
+                            FileDialog::new()
+                                .add_filter(extension, &[extension])
+                                .set_directory(dir)
+                                .set_file_name(file_name)
+                                .save_file()
+                        });
+                        commands.spawn(ExportFile(task, format));
+                    }
+                }
             });
             ui.horizontal_top(|ui| {
                 ui.label("map name");
                 ui.text_edit_singleline(&mut ui_state.current_map.map_name);
+                if ui.button("check map").clicked() {
+                    ui_state.problems =
+                        Some(ui_state.current_map.validate(&settings, &assets_root()));
+                }
             });
             ui.label(format!("map id: {}", ui_state.current_map.map_id));
-            let tile_map_name = match &ui_state.current_map.tile_map {
-                Some(tm) => tm.to_string_lossy().into_owned(),
-                None => "".to_owned(),
-            };
-            ui.label(format!("tile set: {tile_map_name}"));
+            ui.add(
+                egui::Slider::new(&mut ui_state.current_map.ambient_light, 0.0..=1.0)
+                    .text("ambient light"),
+            );
+            let tileset_names: Vec<String> = ui_state
+                .current_map
+                .tilesets
+                .iter()
+                .map(|ts| ts.path.to_string_lossy().into_owned())
+                .collect();
+            ui.label(format!("tile sets: {}", tileset_names.join(", ")));
             ui.allocate_rect(ui.available_rect_before_wrap(), egui::Sense::hover());
         });
 
@@ -300,27 +624,67 @@ fn draw_ui(
         .default_width(settings.left_margin)
         .show(ctx, |ui| {
             egui::ScrollArea::vertical().show(ui, |ui| {
-                let tm_name = match &ui_state.tile_source {
-                    Some(ts) => ts
-                        .to_string_lossy()
-                        .into_owned()
-                        .split('/')
-                        .last()
-                        .unwrap_or("")
-                        .to_owned(),
-                    None => "No tile source selected".to_owned(),
-                };
-                ui.with_layout(
-                    egui::Layout::left_to_right(egui::Align::TOP).with_main_justify(true),
-                    |ui| {
-                        ui.label(
-                            egui::RichText::new(tm_name)
+                if ui_state.palettes.is_empty() {
+                    ui.with_layout(
+                        egui::Layout::left_to_right(egui::Align::TOP).with_main_justify(true),
+                        |ui| {
+                            ui.label(
+                                egui::RichText::new("No tile source selected")
+                                    .size(16.)
+                                    .strong()
+                                    .color(egui::Color32::BLACK),
+                            )
+                        },
+                    );
+                }
+
+                ui.horizontal_wrapped(|ui| {
+                    let mut active = ui_state.active_palette;
+                    for (i, palette) in ui_state.palettes.iter().enumerate() {
+                        let label = egui::RichText::new(&palette.name)
+                            .size(16.)
+                            .strong()
+                            .color(egui::Color32::BLACK);
+                        if ui
+                            .selectable_label(i == active, label)
+                            .on_hover_text(format!("tileset {}", palette.tileset_id))
+                            .clicked()
+                        {
+                            active = i;
+                        }
+                    }
+                    if active != ui_state.active_palette {
+                        ui_state.active_palette = active;
+                        ui_state.selected_tile = None;
+                        ui_state.selected_terrain = None;
+                    }
+                });
+
+                let mut terrains: Vec<String> = ui_state
+                    .palettes
+                    .get(ui_state.active_palette)
+                    .and_then(|p| ui_state.tileset_metas.get(&p.tileset_id))
+                    .map(|m| m.terrains.keys().cloned().collect())
+                    .unwrap_or_default();
+                terrains.sort();
+                if !terrains.is_empty() {
+                    ui.horizontal_wrapped(|ui| {
+                        for name in terrains {
+                            let selected = ui_state.selected_terrain.as_ref() == Some(&name);
+                            let label = egui::RichText::new(&name)
                                 .size(16.)
-                                .strong()
-                                .color(egui::Color32::BLACK),
-                        )
-                    },
-                );
+                                .color(egui::Color32::BLACK);
+                            if ui
+                                .selectable_label(selected, label)
+                                .on_hover_text("terrain brush")
+                                .clicked()
+                            {
+                                ui_state.selected_terrain = Some(name);
+                                ui_state.selected_tile = None;
+                            }
+                        }
+                    });
+                }
 
                 ui.with_layout(
                     egui::Layout::left_to_right(egui::Align::TOP).with_main_wrap(true),
@@ -347,8 +711,20 @@ fn draw_ui(
 
                         ui.style_mut().visuals.window_fill = egui::Color32::RED;
 
-                        if let Some(tile_handles) = ui_state.tile_handles.clone() {
-                            tile_handles.iter().for_each(|h| {
+                        if let Some((tileset_id, tile_handles)) = ui_state
+                            .palettes
+                            .get(ui_state.active_palette)
+                            .map(|p| (p.tileset_id, p.tile_handles.clone()))
+                        {
+                            let meta = ui_state.tileset_metas.get(&tileset_id).cloned();
+                            let elapsed = time.elapsed().as_millis() as u64;
+                            tile_handles.iter().enumerate().for_each(|(i, h)| {
+                                // animated tiles play in the palette too
+                                let h = meta
+                                    .as_ref()
+                                    .and_then(|m| frame_at(m.animation(i as u32), elapsed))
+                                    .and_then(|f| tile_handles.get(f as usize))
+                                    .unwrap_or(h);
                                 let size = h.size_vec2();
                                 let scaled =
                                     egui::vec2(size.x * settings.scale, size.y * settings.scale);
@@ -356,12 +732,17 @@ fn draw_ui(
                                 let tilemap_button = egui::widgets::ImageButton::new(
                                     egui::load::SizedTexture::new(h.id(), scaled),
                                 )
-                                .selected(Some(h) == ui_state.selected_tile.as_ref())
+                                .selected(Some(i) == ui_state.selected_tile)
                                 .frame(true);
 
-                                if ui.add(tilemap_button).clicked() {
+                                if ui
+                                    .add(tilemap_button)
+                                    .on_hover_text(describe_tile(meta.as_ref(), i))
+                                    .clicked()
+                                {
                                     bevy::log::trace!("clicked on {:?}", h.name());
-                                    ui_state.selected_tile = Some(h.clone());
+                                    ui_state.selected_tile = Some(i);
+                                    ui_state.selected_terrain = None;
                                 }
                             })
                         }
@@ -374,29 +755,93 @@ fn draw_ui(
     Ok(())
 }
 
+// redraws the whole map whenever it changes
 fn draw_map(
     settings: Res<GameSettings>,
-    ui_state: Res<UiState>,
+    mut ui_state: ResMut<UiState>,
     asset_server: Res<AssetServer>,
     mut commands: Commands,
-    texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    drawn: Query<Entity, With<EditorTileMap>>,
 ) -> Result<()> {
-    bevy::log::debug!("Settings: {:?}", settings);
-    if ui_state.current_map.tile_map.is_some() {
-        commands.spawn(
-            ui_state
-                .current_map
-                .get_tilemap(&settings, &asset_server, texture_atlases),
+    if !ui_state.map_dirty {
+        return Ok(());
+    }
+    ui_state.map_dirty = false;
+
+    for entity in &drawn {
+        commands.entity(entity).despawn();
+    }
+    if !ui_state.current_map.tilesets.is_empty() {
+        let (tilemaps, problems) = ui_state.current_map.get_tilemap(
+            &settings,
+            &ui_state.tileset_metas,
+            &assets_root(),
+            &asset_server,
+            &mut texture_atlases,
+        );
+        // the map is still drawn, with placeholders, but say what's wrong
+        if !problems.is_empty() {
+            let problems: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+            ui_state.file_error = Some(("Map problems", problems.join("\n")));
+        }
+        commands.spawn_batch(
+            tilemaps
+                .into_iter()
+                .map(|(t, placed)| (t, placed, EditorTileMap)),
         );
     }
     Ok(())
 }
 
+// puts the selected palette tile at `coords` on the ground layer, along
+// with the type its tileset says it should have
+fn paint_tile(ui_state: &mut UiState, coords: TileCoords) -> Result<()> {
+    let Some(tileset_id) = ui_state
+        .palettes
+        .get(ui_state.active_palette)
+        .map(|p| p.tileset_id)
+    else {
+        return Ok(());
+    };
+    if let Some(terrain) = ui_state.selected_terrain.clone() {
+        autotile::paint(
+            &mut ui_state.current_map,
+            0,
+            coords,
+            tileset_id,
+            &terrain,
+            &ui_state.tileset_metas,
+        )?;
+        ui_state.map_dirty = true;
+        return Ok(());
+    }
+    let Some(tile_index) = ui_state.selected_tile else {
+        return Ok(());
+    };
+    let metadata = ui_state
+        .tileset_metas
+        .get(&tileset_id)
+        .and_then(|m| m.tile_type(tile_index as u32));
+    ui_state.current_map.set(
+        0,
+        coords,
+        MapTile {
+            tileset_id,
+            tile_index: tile_index as u32,
+            metadata,
+        },
+    )?;
+    ui_state.map_dirty = true;
+    Ok(())
+}
+
 fn mouse_button_input(
     q_windows: Query<&Window, With<PrimaryWindow>>,
     buttons: Res<Input<MouseButton>>,
     settings: Res<GameSettings>,
     mut ui_state: ResMut<UiState>,
+    mut contexts: EguiContexts,
 ) -> Result<()> {
     let position = q_windows.single().cursor_position();
 
@@ -413,18 +858,40 @@ fn mouse_button_input(
         }
     }
 
+    // clicks on the panels aren't meant for the map
+    if contexts.ctx_mut().wants_pointer_input() {
+        return Ok(());
+    }
+    let Some(coords) = ui_state
+        .current_tile
+        .filter(|c| ui_state.current_map.contains(*c))
+    else {
+        return Ok(());
+    };
+
     if buttons.just_pressed(MouseButton::Left) {
         bevy::log::info!(
             "pressed left mouse button at {:?}, tile: {:?}",
             ui_state.cursor_pos,
             ui_state.current_tile
         );
+        paint_tile(&mut ui_state, coords)?;
     } else if buttons.just_pressed(MouseButton::Right) {
         bevy::log::info!(
             "pressed right mouse button at {:?}, tile: {:?}",
             ui_state.cursor_pos,
             ui_state.current_tile
         );
+        if ui_state.current_map.clear(0, coords).is_some() {
+            // terrain next to the hole needs an edge now
+            let UiState {
+                current_map,
+                tileset_metas,
+                ..
+            } = &mut *ui_state;
+            autotile::update(current_map, 0, coords, tileset_metas);
+            ui_state.map_dirty = true;
+        }
     }
 
     Ok(())
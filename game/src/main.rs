@@ -1,16 +1,105 @@
-use anyhow::Result;
-use bevy::{prelude::*, sprite::collide_aabb::collide, window::WindowResolution};
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use bevy::{
+    app::AppExit,
+    asset::{io::file::FileAssetReader, LoadState},
+    prelude::*,
+    window::WindowResolution,
+};
 use bevy_simple_tilemap::prelude::*;
 
+use shared::animation::animate_tiles;
+use shared::collision::CollisionGrid;
 use shared::components::*;
+use shared::fov::FogOfWar;
+use shared::lighting::{Falloff, LightSource, Lighting};
+use shared::save::SaveData;
 use shared::settings::{GameSettings, SettingsFile};
-use shared::tilemap::{coord_to_screen_pos, MapScreen};
+use shared::tilemap::{
+    coord_to_screen_pos, nearest_coord, LayerTiles, MapAssetPlugin, MapScreen, TileChanged,
+    TileCoords,
+};
+use shared::tileset::{TileRect, TilesetMeta};
+use shared::world::{Edge, Transition, World};
+
+// how long it takes to slide from one screen to the next
+const SCROLL_SECONDS: f32 = 0.6;
+
+// relative to the assets folder, like everything else the game loads
+const WORLD_FILE: &str = "data/forest.world.ron";
+
+const SAVE_FILE: &str = "save.ron";
+
+// how far the hero can see on maps with fog of war, in tiles
+const SIGHT_RADIUS: u32 = 6;
+
+// how bright tiles are that have been seen before but can't be now
+const REMEMBERED: f32 = 0.35;
+
+// the hero's lantern only shows on maps darker than full daylight
+const LANTERN_RADIUS: u32 = 4;
+const LANTERN_COLOR: (f32, f32, f32) = (1.0, 0.85, 0.6);
+
+// the hero only bumps into things with their feet, so they can walk up
+// behind tree trunks and fence posts
+const HERO_HITBOX: TileRect = TileRect {
+    x: 0.25,
+    y: 0.,
+    w: 0.5,
+    h: 0.5,
+};
 
 #[derive(Debug, Resource)]
 struct MoveTimer(Timer);
 
+#[derive(Debug, Resource)]
+struct WorldHandle(Handle<World>);
+
+#[derive(Debug, Resource)]
+struct GameWorld(World);
+
+#[derive(Debug, Resource)]
+struct CurrentMap(MapScreen);
+
+// every map in the world, loading in the background from the start
+#[derive(Debug, Resource)]
+struct MapHandles(HashMap<uuid::Uuid, Handle<MapScreen>>);
+
+// the map the hero is on their way to, until it has loaded
+#[derive(Debug, Resource)]
+struct PendingMap(Transition);
+
+// the sidecars for the current map's tilesets
+#[derive(Debug, Resource)]
+struct TilesetMetas(HashMap<u32, TilesetMeta>);
+
+// everything spawned from the current map, so it can be cleared out when
+// the hero moves to another one
+#[derive(Debug, Component)]
+struct MapEntity;
+
+// map entities that are scrolling off screen and get despawned once the
+// new map is in place
+#[derive(Debug, Component)]
+struct Outgoing;
+
+#[derive(Debug, Event)]
+struct MapTransition(Transition);
+
+// an edge transition in progress. both maps slide by `offset` over the
+// course of the timer while the hero walks across to the far edge.
+#[derive(Debug, Resource)]
+struct ScreenScroll {
+    timer: Timer,
+    offset: Vec3,
+    progress: f32,
+    hero_from: Vec3,
+    hero_to: Vec3,
+}
+
 fn main() -> Result<()> {
-    let sf = SettingsFile::new_from_file("settings.ron")?;
+    let sf = SettingsFile::load("settings.ron")?;
     let settings = GameSettings::new_from_sf(&sf, false);
 
     App::new()
@@ -28,18 +117,64 @@ fn main() -> Result<()> {
                 })
                 .set(ImagePlugin::default_nearest()),
         )
-        .add_plugins(SimpleTileMapPlugin)
+        .add_plugins((SimpleTileMapPlugin, MapAssetPlugin))
         .insert_resource(settings)
         .insert_resource(MoveTimer(Timer::from_seconds(
             sf.input_debounce,
             TimerMode::Repeating,
         )))
+        .add_event::<MapTransition>()
+        .add_event::<TileChanged>()
         .add_systems(Startup, (setup_camera, setup.pipe(error_handler)))
-        .add_systems(Update, move_hero)
+        .add_systems(
+            Update,
+            (
+                load_world,
+                move_hero.run_if(
+                    resource_exists::<CurrentMap>()
+                        .and_then(not(resource_exists::<ScreenScroll>()))
+                        .and_then(not(resource_exists::<PendingMap>())),
+                ),
+                change_map.pipe(error_handler),
+                reload_map
+                    .pipe(error_handler)
+                    .run_if(resource_exists::<CurrentMap>()),
+                enter_map.pipe(error_handler).run_if(
+                    resource_exists::<PendingMap>()
+                        .and_then(not(resource_exists::<ScreenScroll>())),
+                ),
+                scroll_screen.run_if(resource_exists::<ScreenScroll>()),
+            )
+                .chain(),
+        )
+        .add_systems(
+            Update,
+            (
+                send_tile_changes,
+                apply_tile_changes.run_if(resource_exists::<Lighting>()),
+                update_fov.run_if(
+                    resource_exists::<FogOfWar>().and_then(not(resource_exists::<ScreenScroll>())),
+                ),
+                update_lighting.run_if(
+                    resource_exists::<Lighting>().and_then(not(resource_exists::<ScreenScroll>())),
+                ),
+                shade_tiles.run_if(resource_exists::<Lighting>()),
+            )
+                .chain()
+                .run_if(resource_exists::<CurrentMap>())
+                .after(move_hero),
+        )
+        .add_systems(Update, animate_tiles)
+        .add_systems(Last, save_on_exit.pipe(error_handler))
         .run();
     Ok(())
 }
 
+// where bevy loads assets from, wherever the game is run from
+fn assets_root() -> PathBuf {
+    FileAssetReader::get_base_path().join("assets")
+}
+
 fn error_handler(In(result): In<Result<()>>) {
     if let Err(err) = result {
         println!("encountered an error {:?}", err);
@@ -50,15 +185,83 @@ fn setup_camera(mut commands: Commands) {
     commands.spawn(Camera2dBundle::default());
 }
 
+// logs anything wrong with a map, and refuses it if it can't be played
+fn check_map(ms: &MapScreen, settings: &GameSettings) -> Result<()> {
+    let found = ms.validate(settings, &assets_root());
+    for diagnostic in &found {
+        if diagnostic.is_error() {
+            error!("{}: {}", ms.map_name, diagnostic);
+        } else {
+            warn!("{}: {}", ms.map_name, diagnostic);
+        }
+    }
+    let errors = found.iter().filter(|d| d.is_error()).count();
+    if errors > 0 {
+        return Err(anyhow!("{} has {} errors", ms.map_name, errors));
+    }
+    Ok(())
+}
+
+// `offset` shifts the whole map, for placing it just off screen
+fn spawn_map(
+    ms: &MapScreen,
+    metas: &HashMap<u32, TilesetMeta>,
+    settings: &GameSettings,
+    asset_server: &Res<AssetServer>,
+    commands: &mut Commands,
+    texture_atlases: &mut Assets<TextureAtlas>,
+    offset: Vec3,
+) {
+    let (tilemaps, problems) = ms.get_tilemap(
+        settings,
+        metas,
+        &assets_root(),
+        asset_server,
+        texture_atlases,
+    );
+    for problem in problems {
+        warn!("{}: {}", ms.map_name, problem);
+    }
+    for (mut tilemap, placed) in tilemaps {
+        tilemap.transform.translation += offset;
+        commands.spawn((tilemap, placed, MapEntity));
+    }
+
+    // everything else placed on the map. walls live in the collision grid.
+    let shift = move |mut spatial: SpatialBundle| {
+        spatial.transform.translation += offset;
+        spatial
+    };
+    commands.spawn_batch(
+        ms.get_doormap(settings)
+            .into_iter()
+            .map(move |(b, c)| (shift(b), c, MapEntity)),
+    );
+    commands.spawn_batch(
+        ms.get_itemmap(settings)
+            .into_iter()
+            .map(move |(b, c)| (shift(b), c, MapEntity)),
+    );
+    commands.spawn_batch(
+        ms.get_enemymap(settings)
+            .into_iter()
+            .map(move |(b, c)| (shift(b), c, MapEntity)),
+    );
+    commands.spawn_batch(
+        ms.get_npcmap(settings)
+            .into_iter()
+            .map(move |(b, c)| (shift(b), c, MapEntity)),
+    );
+}
+
 fn setup(
     settings: Res<GameSettings>,
     asset_server: Res<AssetServer>,
     mut commands: Commands,
-    texture_atlases: ResMut<Assets<TextureAtlas>>,
 ) -> Result<()> {
-    // tile map
-    let ms = MapScreen::new_from_file("assets/data/test.ron")?;
-    commands.spawn(ms.get_tilemap(&settings, &asset_server, texture_atlases));
+    // the world, then its maps, load in the background. see `load_world`.
+    commands.insert_resource(WorldHandle(asset_server.load(WORLD_FILE)));
+    commands.insert_resource(SaveData::load_or_default(SAVE_FILE)?);
 
     // ui
     commands
@@ -96,81 +299,436 @@ fn setup(
                 });
         });
 
-    // hero
-    commands.spawn((
-        SpriteBundle {
-            texture: asset_server.load("icons/todd.png"),
-            transform: Transform {
-                translation: coord_to_screen_pos(1, 16, 1.0, &settings),
-                scale: Vec3::splat(settings.scale),
-                ..default()
-            },
-            ..Default::default()
-        },
-        Hero,
-    ));
+    Ok(())
+}
 
-    // walls
-    commands.spawn_batch(ms.get_wallmap(&settings));
+// takes up the world once it has loaded, and again whenever it changes on
+// disk. the first time, the hero is put on the start map.
+fn load_world(
+    settings: Res<GameSettings>,
+    asset_server: Res<AssetServer>,
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<World>>,
+    worlds: Res<Assets<World>>,
+    world_handle: Res<WorldHandle>,
+    game_world: Option<Res<GameWorld>>,
+) {
+    let id = world_handle.0.id();
+    let changed = events
+        .read()
+        .filter(|e| e.is_added(id) || e.is_modified(id))
+        .count();
+    let Some(world) = worlds.get(id).filter(|_| changed > 0) else {
+        return;
+    };
+    let world = world.clone();
+    // map paths come out relative to the assets folder
+    let handles = world
+        .maps
+        .iter()
+        .filter_map(|m| Some((m.map_id, asset_server.load(world.get_map_path(m.map_id)?))))
+        .collect();
 
-    Ok(())
+    if game_world.is_none() {
+        commands.spawn((
+            SpriteBundle {
+                texture: asset_server.load("icons/todd.png"),
+                transform: Transform {
+                    translation: coord_to_screen_pos(
+                        world.start.0,
+                        world.start.1,
+                        settings.game_z,
+                        &settings,
+                    ),
+                    scale: Vec3::splat(settings.scale),
+                    ..default()
+                },
+                ..Default::default()
+            },
+            Hero,
+            Hitbox(HERO_HITBOX),
+        ));
+        commands.insert_resource(PendingMap(Transition {
+            to_map: world.start_map,
+            target: world.start,
+            edge: None,
+        }));
+    }
+    commands.insert_resource(MapHandles(handles));
+    commands.insert_resource(GameWorld(world));
 }
 
+#[allow(clippy::too_many_arguments)]
 fn move_hero(
     settings: Res<GameSettings>,
+    world: Res<GameWorld>,
+    current_map: Res<CurrentMap>,
+    collision: Res<CollisionGrid>,
+    mut transitions: EventWriter<MapTransition>,
     time: Res<Time>,
     mut timer: ResMut<MoveTimer>,
     keyboard_input: Res<Input<KeyCode>>,
-    mut hero_query: Query<&mut Transform, With<Hero>>,
-    wall_query: Query<&Transform, (With<Wall>, Without<Hero>)>,
+    mut hero_query: Query<(&mut Transform, &Hitbox), With<Hero>>,
 ) {
     if timer.0.tick(time.delta()).just_finished() {
-        let mut hero_transform = hero_query.single_mut();
-        let mut direction = (0.0, 0.0);
+        let (mut hero_transform, hitbox) = hero_query.single_mut();
+        let mut direction = (0, 0);
 
         if keyboard_input.pressed(KeyCode::Left) {
-            direction = (-1.0, 0.0);
+            direction = (-1, 0);
         }
 
         if keyboard_input.pressed(KeyCode::Right) {
-            direction = (1.0, 0.0);
+            direction = (1, 0);
         }
 
         if keyboard_input.pressed(KeyCode::Up) {
-            direction = (0.0, 1.0);
+            direction = (0, 1);
         }
 
         if keyboard_input.pressed(KeyCode::Down) {
-            direction = (0.0, -1.0);
+            direction = (0, -1);
         }
 
-        let new_x =
-            hero_transform.translation.x + direction.0 * (settings.tile_width * settings.scale);
-        let new_y =
-            hero_transform.translation.y + direction.1 * (settings.tile_height * settings.scale);
+        if direction == (0, 0) {
+            return;
+        }
 
-        let hero_size = hero_transform.scale.truncate();
+        let TileCoords(x, y) = nearest_coord(hero_transform.translation, &settings);
+        let new_coords = TileCoords(x + direction.0, y + direction.1);
 
-        for transform in &wall_query {
-            let collision = collide(
-                Vec3 {
-                    x: new_x,
-                    y: new_y,
-                    z: 1.0,
-                },
-                hero_size,
-                transform.translation,
-                transform.scale.truncate(),
+        if collision.hits(new_coords, &hitbox.0) {
+            return;
+        }
+
+        if let Some(transition) = world.0.exit_at(&current_map.0, new_coords) {
+            transitions.send(MapTransition(transition));
+            return;
+        }
+
+        // off the edge with nowhere to go
+        if !collision.contains(new_coords) {
+            return;
+        }
+
+        hero_transform.translation = coord_to_screen_pos(
+            new_coords.0,
+            new_coords.1,
+            hero_transform.translation.z,
+            &settings,
+        );
+    }
+}
+
+// heads for the map the hero just walked into. it's swapped in by
+// `enter_map` once it has loaded.
+fn change_map(
+    mut commands: Commands,
+    mut transitions: EventReader<MapTransition>,
+    fog: Option<Res<FogOfWar>>,
+    mut save: ResMut<SaveData>,
+) -> Result<()> {
+    let Some(MapTransition(transition)) = transitions.read().last() else {
+        return Ok(());
+    };
+    remember_explored(fog.as_deref(), &mut save)?;
+    commands.insert_resource(PendingMap(*transition));
+    Ok(())
+}
+
+// a map saved while it's being played is swapped in where the hero stands
+#[allow(clippy::too_many_arguments)]
+fn reload_map(
+    settings: Res<GameSettings>,
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<MapScreen>>,
+    handles: Res<MapHandles>,
+    current_map: Res<CurrentMap>,
+    hero_query: Query<&Transform, With<Hero>>,
+    fog: Option<Res<FogOfWar>>,
+    mut save: ResMut<SaveData>,
+) -> Result<()> {
+    let Some(current) = handles.0.get(&current_map.0.map_id) else {
+        return Ok(());
+    };
+    let modified = events
+        .read()
+        .filter(|e| e.is_modified(current.id()))
+        .count();
+    if modified == 0 {
+        return Ok(());
+    }
+    info!("{} changed on disk, reloading it", current_map.0.map_name);
+    remember_explored(fog.as_deref(), &mut save)?;
+    commands.insert_resource(PendingMap(Transition {
+        to_map: current_map.0.map_id,
+        target: nearest_coord(hero_query.single().translation, &settings),
+        edge: None,
+    }));
+    Ok(())
+}
+
+// swaps the current map for the pending one once it's loaded. doors and
+// reloads switch straight away, edges scroll the new screen in.
+#[allow(clippy::too_many_arguments)]
+fn enter_map(
+    settings: Res<GameSettings>,
+    asset_server: Res<AssetServer>,
+    maps: Res<Assets<MapScreen>>,
+    handles: Res<MapHandles>,
+    pending: Res<PendingMap>,
+    mut commands: Commands,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut hero_query: Query<&mut Transform, With<Hero>>,
+    map_entities: Query<Entity, With<MapEntity>>,
+    save: Res<SaveData>,
+) -> Result<()> {
+    let transition = pending.0;
+    let Some(handle) = handles.0.get(&transition.to_map) else {
+        commands.remove_resource::<PendingMap>();
+        return Err(anyhow!(
+            "map {} is not part of the world",
+            transition.to_map
+        ));
+    };
+    let Some(ms) = maps.get(handle) else {
+        if asset_server.load_state(handle) == LoadState::Failed {
+            commands.remove_resource::<PendingMap>();
+            return Err(anyhow!("map {} failed to load", transition.to_map));
+        }
+        return Ok(());
+    };
+    commands.remove_resource::<PendingMap>();
+
+    let ms = ms.clone();
+    if ms.map_id != transition.to_map {
+        return Err(anyhow!(
+            "{} has id {}, but the world lists it as {}",
+            ms.map_name,
+            ms.map_id,
+            transition.to_map
+        ));
+    }
+    check_map(&ms, &settings)?;
+    let metas = ms.tileset_metas.clone();
+    let mut hero_transform = hero_query.single_mut();
+    let hero_to = coord_to_screen_pos(
+        transition.target.0,
+        transition.target.1,
+        settings.game_z,
+        &settings,
+    );
+
+    match transition.edge {
+        Some(edge) => {
+            let offset = match edge {
+                Edge::North => Vec3::new(0.0, settings.game_area_y_res, 0.0),
+                Edge::South => Vec3::new(0.0, -settings.game_area_y_res, 0.0),
+                Edge::East => Vec3::new(settings.game_area_x_res, 0.0, 0.0),
+                Edge::West => Vec3::new(-settings.game_area_x_res, 0.0, 0.0),
+            };
+            for entity in &map_entities {
+                commands.entity(entity).insert(Outgoing);
+            }
+            spawn_map(
+                &ms,
+                &metas,
+                &settings,
+                &asset_server,
+                &mut commands,
+                &mut texture_atlases,
+                offset,
             );
+            commands.insert_resource(ScreenScroll {
+                timer: Timer::from_seconds(SCROLL_SECONDS, TimerMode::Once),
+                offset,
+                progress: 0.0,
+                hero_from: hero_transform.translation,
+                hero_to,
+            });
+        }
+        None => {
+            for entity in &map_entities {
+                commands.entity(entity).despawn();
+            }
+            spawn_map(
+                &ms,
+                &metas,
+                &settings,
+                &asset_server,
+                &mut commands,
+                &mut texture_atlases,
+                Vec3::ZERO,
+            );
+            hero_transform.translation = hero_to;
+        }
+    }
+    if ms.fog_of_war {
+        let mut fog = FogOfWar::new(&ms, save.explored.get(&ms.map_id).cloned());
+        fog.update(transition.target, SIGHT_RADIUS);
+        commands.insert_resource(fog);
+    } else {
+        commands.remove_resource::<FogOfWar>();
+    }
+    let mut lighting = Lighting::new(&ms);
+    lighting.carry(Some(lantern(transition.target)));
+    commands.insert_resource(lighting);
+    commands.insert_resource(CollisionGrid::from_map(&ms, &metas));
+    commands.insert_resource(TilesetMetas(metas));
+    commands.insert_resource(CurrentMap(ms));
+
+    Ok(())
+}
+
+fn update_fov(
+    settings: Res<GameSettings>,
+    mut fog: ResMut<FogOfWar>,
+    hero_query: Query<&Transform, With<Hero>>,
+) {
+    let coords = nearest_coord(hero_query.single().translation, &settings);
+    // only count as changed when the hero has moved, so tiles aren't
+    // reshaded every frame
+    if fog.bypass_change_detection().update(coords, SIGHT_RADIUS) {
+        fog.set_changed();
+    }
+}
+
+// gameplay code only changes the map. this passes on what it changed.
+fn send_tile_changes(mut current_map: ResMut<CurrentMap>, mut changes: EventWriter<TileChanged>) {
+    changes.send_batch(current_map.bypass_change_detection().0.take_changes());
+}
 
-            if collision.is_some() {
-                return;
+// brings the tilemaps, collision, lighting and fog up to date with the
+// map after it's been changed
+fn apply_tile_changes(
+    mut changes: EventReader<TileChanged>,
+    current_map: Res<CurrentMap>,
+    metas: Res<TilesetMetas>,
+    mut collision: ResMut<CollisionGrid>,
+    mut lighting: ResMut<Lighting>,
+    fog: Option<ResMut<FogOfWar>>,
+    mut tilemaps: Query<(&mut TileMap, &mut LayerTiles), Without<Outgoing>>,
+) {
+    if changes.is_empty() {
+        return;
+    }
+    let ms = &current_map.0;
+    let default_meta = TilesetMeta::default();
+    for &TileChanged { layer, coords } in changes.read() {
+        let tile = ms.get(layer, coords);
+        for (mut tilemap, mut placed) in &mut tilemaps {
+            if placed.layer != layer {
+                continue;
             }
+            let drawn = tile
+                .filter(|t| t.tileset_id == placed.tileset_id)
+                .map(|t| ms.layers[layer].tile_for(t));
+            let meta = metas.0.get(&placed.tileset_id).unwrap_or(&default_meta);
+            placed.replace(&mut tilemap, coords, drawn, meta);
         }
+        collision.refresh(ms, &metas.0, coords);
+    }
+    // walls may have come or gone. this also gets the new tiles shaded.
+    lighting.refresh(ms);
+    if let Some(mut fog) = fog {
+        fog.refresh(ms);
+    }
+}
+
+fn lantern(coords: TileCoords) -> LightSource {
+    LightSource {
+        coords,
+        radius: LANTERN_RADIUS,
+        color: LANTERN_COLOR,
+        falloff: Falloff::Linear,
+    }
+}
+
+fn update_lighting(
+    settings: Res<GameSettings>,
+    mut lighting: ResMut<Lighting>,
+    hero_query: Query<&Transform, With<Hero>>,
+) {
+    let coords = nearest_coord(hero_query.single().translation, &settings);
+    if lighting
+        .bypass_change_detection()
+        .carry(Some(lantern(coords)))
+    {
+        lighting.set_changed();
+    }
+}
+
+// lights each tile, then darkens what the hero can't see and hides what
+// they've never seen
+fn shade_tiles(
+    lighting: Res<Lighting>,
+    fog: Option<Res<FogOfWar>>,
+    mut tilemaps: Query<(&mut TileMap, &mut LayerTiles), Without<Outgoing>>,
+) {
+    let fog_changed = fog.as_ref().is_some_and(|f| f.is_changed());
+    for (mut tilemap, mut placed) in &mut tilemaps {
+        if !lighting.is_changed() && !fog_changed && !placed.is_added() {
+            continue;
+        }
+        placed.tint(&mut tilemap, |coords| {
+            let light = lighting.light_at(coords);
+            match &fog {
+                Some(fog) if !fog.is_visible(coords) => {
+                    if fog.is_explored(coords) {
+                        light * REMEMBERED
+                    } else {
+                        Color::BLACK
+                    }
+                }
+                _ => light,
+            }
+        });
+    }
+}
+
+// keeps what's been seen of the current map in the save file
+fn remember_explored(fog: Option<&FogOfWar>, save: &mut SaveData) -> Result<()> {
+    let Some(fog) = fog else {
+        return Ok(());
+    };
+    save.explored.insert(fog.map_id, fog.explored().clone());
+    Ok(save.save_to_file(SAVE_FILE)?)
+}
+
+fn save_on_exit(
+    mut exits: EventReader<AppExit>,
+    fog: Option<Res<FogOfWar>>,
+    mut save: ResMut<SaveData>,
+) -> Result<()> {
+    if exits.read().last().is_none() {
+        return Ok(());
+    }
+    remember_explored(fog.as_deref(), &mut save)
+}
 
-        hero_transform.translation.x =
-            new_x.clamp(settings.game_area_x_min, settings.game_area_x_max);
-        hero_transform.translation.y =
-            new_y.clamp(settings.game_area_y_min, settings.game_area_y_max);
+fn scroll_screen(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut scroll: ResMut<ScreenScroll>,
+    mut map_query: Query<&mut Transform, (With<MapEntity>, Without<Hero>)>,
+    mut hero_query: Query<&mut Transform, With<Hero>>,
+    outgoing: Query<Entity, With<Outgoing>>,
+) {
+    scroll.timer.tick(time.delta());
+    let progress = scroll.timer.percent();
+    let step = scroll.offset * (progress - scroll.progress);
+    scroll.progress = progress;
+
+    for mut transform in &mut map_query {
+        transform.translation -= step;
+    }
+    let mut hero_transform = hero_query.single_mut();
+    hero_transform.translation = scroll.hero_from.lerp(scroll.hero_to, progress);
+
+    if scroll.timer.finished() {
+        for entity in &outgoing {
+            commands.entity(entity).despawn();
+        }
+        commands.remove_resource::<ScreenScroll>();
     }
 }
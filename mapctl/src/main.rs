@@ -0,0 +1,291 @@
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use shared::settings::{GameSettings, SettingsFile};
+use shared::tilemap::{
+    detect_version, validate_file, MapScreen, TileCoords, CURRENT_FORMAT_VERSION,
+};
+
+mod render;
+
+#[derive(Debug, Parser)]
+#[command(about = "works with maps without opening a window")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    #[command(about = "draws a map to a png")]
+    Render {
+        map: PathBuf,
+        #[arg(short, long, help = "defaults to the map file with .png on the end")]
+        output: Option<PathBuf>,
+        #[arg(long, help = "shades walls, doors, items, enemies and npcs")]
+        overlay: bool,
+        #[arg(
+            long,
+            help = "tileset paths are relative to this. defaults to the assets folder the map is in"
+        )]
+        assets: Option<PathBuf>,
+        #[arg(
+            long,
+            default_value_t = 16,
+            help = "in pixels, for tilesets without a sidecar"
+        )]
+        tile_size: u32,
+    },
+    #[command(about = "checks maps for anything that would stop them being played")]
+    Validate {
+        #[arg(required = true)]
+        maps: Vec<PathBuf>,
+        #[arg(
+            long,
+            help = "tileset paths are relative to this. defaults to the assets folder the map is in"
+        )]
+        assets: Option<PathBuf>,
+        #[arg(
+            long,
+            env = "CONFIG_FILE",
+            default_value = "settings.ron",
+            help = "the game's settings, for the tile and screen size"
+        )]
+        settings: PathBuf,
+    },
+    #[command(about = "rewrites maps saved in an older format")]
+    Migrate {
+        #[arg(required = true)]
+        maps: Vec<PathBuf>,
+        #[arg(long, help = "fails if any need migrating, rather than rewriting them")]
+        check: bool,
+    },
+    #[command(about = "prints a map as text, a grid of tile indices per layer")]
+    Print { map: PathBuf },
+}
+
+fn load(path: &Path) -> Result<MapScreen> {
+    Ok(MapScreen::load_from_file(&path.to_string_lossy(), false)?)
+}
+
+// tileset paths in a map are relative to the nearest assets folder above it
+fn assets_for(map: &Path, assets: Option<&Path>) -> Result<PathBuf> {
+    if let Some(assets) = assets {
+        return Ok(assets.to_path_buf());
+    }
+    let full = fs::canonicalize(map).map_err(|e| anyhow!("{}, {:?}", map.to_string_lossy(), e))?;
+    full.ancestors()
+        .skip(1)
+        .find(|dir| dir.file_name().is_some_and(|name| name == "assets"))
+        .map(Path::to_path_buf)
+        .ok_or_else(|| {
+            anyhow!(
+                "{} isn't in an assets folder, so --assets is needed",
+                map.to_string_lossy()
+            )
+        })
+}
+
+fn main() -> Result<()> {
+    match Cli::parse().command {
+        Command::Render {
+            map,
+            output,
+            overlay,
+            assets,
+            tile_size,
+        } => {
+            let assets = assets_for(&map, assets.as_deref())?;
+            let ms = load(&map)?;
+            let metas = ms.load_tileset_metas(&assets)?;
+            let image = render::render(&ms, &metas, &assets, (tile_size, tile_size), overlay)?;
+            let output = output.unwrap_or_else(|| map.with_extension("png"));
+            image
+                .save(&output)
+                .map_err(|e| anyhow!("{}, {:?}", output.to_string_lossy(), e))?;
+            println!("wrote {}", output.to_string_lossy());
+        }
+        Command::Validate {
+            maps,
+            assets,
+            settings,
+        } => {
+            let sf = SettingsFile::load(&settings.to_string_lossy())?;
+            let settings = GameSettings::new_from_sf(&sf, false);
+            let mut failed = 0;
+            for map in &maps {
+                let assets = assets_for(map, assets.as_deref())?;
+                let found = validate_file(&map.to_string_lossy(), &settings, &assets);
+                for diagnostic in &found {
+                    println!("{}: {}", map.to_string_lossy(), diagnostic);
+                }
+                if found.iter().any(|d| d.is_error()) {
+                    failed += 1;
+                }
+            }
+            if failed > 0 {
+                return Err(anyhow!("{} of {} maps have errors", failed, maps.len()));
+            }
+            println!("{} maps ok", maps.len());
+        }
+        Command::Migrate { maps, check } => {
+            let mut outdated = 0;
+            for map in &maps {
+                let filename = map.to_string_lossy();
+                let contents =
+                    fs::read_to_string(map).map_err(|e| anyhow!("{}, {:?}", filename, e))?;
+                let version =
+                    detect_version(&contents).map_err(|e| anyhow!("{}, {:?}", filename, e))?;
+                if version == CURRENT_FORMAT_VERSION {
+                    continue;
+                }
+                outdated += 1;
+                if check {
+                    println!("{} is version {}", filename, version);
+                } else {
+                    let ms = MapScreen::load_from_file(&filename, true)?;
+                    for warning in &ms.upgrade_warnings {
+                        println!("{}: {}", filename, warning);
+                    }
+                    println!(
+                        "{} migrated from version {} to {}",
+                        filename, version, CURRENT_FORMAT_VERSION
+                    );
+                }
+            }
+            if check && outdated > 0 {
+                return Err(anyhow!(
+                    "{} maps aren't version {}",
+                    outdated,
+                    CURRENT_FORMAT_VERSION
+                ));
+            }
+        }
+        Command::Print { map } => print!("{}", describe(&load(&map)?)),
+    }
+    Ok(())
+}
+
+// a map laid out as text, so a change to it reads sensibly in a diff.
+// rows run top to bottom like on screen. tiles are shown by index, along
+// with their tileset when the map has more than one.
+fn describe(ms: &MapScreen) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{} ({})", ms.map_name, ms.map_id);
+    let _ = writeln!(
+        out,
+        "{}x{} tiles, format version {}",
+        ms.tile_cols, ms.tile_rows, ms.format_version
+    );
+    for ts in &ms.tilesets {
+        let _ = writeln!(
+            out,
+            "tileset {}: {} ({}x{})",
+            ts.id,
+            ts.path.to_string_lossy(),
+            ts.columns,
+            ts.rows
+        );
+    }
+    let n = &ms.neighbors;
+    for (edge, id) in [
+        ("north", n.north),
+        ("south", n.south),
+        ("east", n.east),
+        ("west", n.west),
+    ] {
+        if let Some(id) = id {
+            let _ = writeln!(out, "{} of here is {}", edge, id);
+        }
+    }
+    let _ = writeln!(
+        out,
+        "fog of war: {}, ambient light: {}",
+        if ms.fog_of_war { "on" } else { "off" },
+        ms.ambient_light
+    );
+    for light in &ms.lights {
+        let _ = writeln!(
+            out,
+            "light at {}: radius {}, colour {:?}, {} falloff",
+            light.coords,
+            light.radius,
+            light.color,
+            light.falloff.name()
+        );
+    }
+
+    let many_tilesets = ms.tilesets.len() > 1;
+    for (i, layer) in ms.layers.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "\nlayer {} {}{}{}",
+            i,
+            layer.name,
+            if layer.overhead { ", overhead" } else { "" },
+            if layer.visible { "" } else { ", hidden" }
+        );
+        let cells: Vec<Vec<String>> = (0..ms.tile_rows as i32)
+            .rev()
+            .map(|y| {
+                (0..ms.tile_cols as i32)
+                    .map(|x| match layer.tiles.get(TileCoords(x, y)) {
+                        Some(t) if many_tilesets => format!("{}:{}", t.tileset_id, t.tile_index),
+                        Some(t) => t.tile_index.to_string(),
+                        None => ".".to_owned(),
+                    })
+                    .collect()
+            })
+            .collect();
+        let width = cells.iter().flatten().map(String::len).max().unwrap_or(1);
+        for row in cells {
+            let row: Vec<String> = row.iter().map(|c| format!("{:>width$}", c)).collect();
+            let _ = writeln!(out, "{}", row.join(" ").trim_end());
+        }
+    }
+
+    let mut metadata = vec![];
+    for y in (0..ms.tile_rows as i32).rev() {
+        for x in 0..ms.tile_cols as i32 {
+            if let Some(tile_type) = ms.metadata_at(TileCoords(x, y)) {
+                metadata.push(format!("{}: {:?}", TileCoords(x, y), tile_type));
+            }
+        }
+    }
+    if !metadata.is_empty() {
+        let _ = writeln!(out, "\nmetadata\n{}", metadata.join("\n"));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assets_for_test() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("mapctl-assets-{}", std::process::id()));
+        fs::create_dir_all(dir.join("game/assets/data"))?;
+        let map = dir.join("game/assets/data/town.map.ron");
+        fs::write(&map, "")?;
+        let stray = dir.join("town.map.ron");
+        fs::write(&stray, "")?;
+
+        assert_eq!(
+            assets_for(&map, None)?,
+            fs::canonicalize(dir.join("game/assets"))?
+        );
+        assert_eq!(
+            assets_for(&stray, Some(Path::new("elsewhere")))?,
+            PathBuf::from("elsewhere")
+        );
+        assert!(assets_for(&stray, None).is_err());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
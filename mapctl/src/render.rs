@@ -0,0 +1,179 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{anyhow, Result};
+use image::{imageops, Rgba, RgbaImage};
+use shared::tilemap::{MapScreen, TileCoords, TileType};
+use shared::tileset::TilesetMeta;
+
+// how each kind of metadata is marked when it's overlaid
+fn overlay_color(tile_type: &TileType) -> Rgba<u8> {
+    match tile_type {
+        TileType::Wall => Rgba([220, 40, 40, 110]),
+        TileType::Door { .. } => Rgba([40, 90, 230, 130]),
+        TileType::Item { .. } => Rgba([240, 210, 30, 130]),
+        TileType::Enemy { .. } => Rgba([200, 40, 200, 130]),
+        TileType::NPC { .. } => Rgba([40, 200, 80, 130]),
+    }
+}
+
+// draws `src` over `dst`, scaling its alpha by `opacity`
+fn blend(dst: &mut Rgba<u8>, src: Rgba<u8>, opacity: f32) {
+    let a = src[3] as f32 / 255.0 * opacity;
+    if a <= 0.0 {
+        return;
+    }
+    let da = dst[3] as f32 / 255.0;
+    let out_a = a + da * (1.0 - a);
+    for c in 0..3 {
+        let s = src[c] as f32 / 255.0;
+        let d = dst[c] as f32 / 255.0;
+        let v = (s * a + d * da * (1.0 - a)) / out_a;
+        dst[c] = (v * 255.0).round() as u8;
+    }
+    dst[3] = (out_a * 255.0).round() as u8;
+}
+
+// lays the map's visible layers out on their tilesets, bottom layer first,
+// the way the game draws them. each cell is `tile_size` pixels unless a
+// tileset's sidecar says otherwise. with `overlay`, cells with metadata are
+// shaded by what kind it is.
+pub fn render(
+    ms: &MapScreen,
+    metas: &HashMap<u32, TilesetMeta>,
+    assets_root: &Path,
+    tile_size: (u32, u32),
+    overlay: bool,
+) -> Result<RgbaImage> {
+    let (tw, th) = tile_size;
+    let mut canvas = RgbaImage::new(ms.tile_cols * tw, ms.tile_rows * th);
+    let default_meta = TilesetMeta::default();
+
+    let mut sheets = HashMap::new();
+    for ts in &ms.tilesets {
+        let path = assets_root.join(&ts.path);
+        let sheet = image::open(&path)
+            .map_err(|e| anyhow!("{}, {:?}", path.to_string_lossy(), e))?
+            .to_rgba8();
+        sheets.insert(ts.id, (ts, sheet));
+    }
+
+    for layer in ms.layers.iter().filter(|l| l.visible) {
+        for (coords, tile) in layer.tiles.iter() {
+            let (ts, sheet) = sheets.get(&tile.tileset_id).ok_or_else(|| {
+                anyhow!(
+                    "the tile at {} uses tileset {}, which the map doesn't have",
+                    coords,
+                    tile.tileset_id
+                )
+            })?;
+            let meta = metas.get(&ts.id).unwrap_or(&default_meta);
+            let (w, h) = meta.tile_size_or(tile_size);
+            let (col, row) = (tile.tile_index % ts.columns, tile.tile_index / ts.columns);
+            let (sx, sy) = (
+                meta.margin + col * (w + meta.spacing),
+                meta.margin + row * (h + meta.spacing),
+            );
+            if tile.tile_index >= ts.columns * ts.rows
+                || sx + w > sheet.width()
+                || sy + h > sheet.height()
+            {
+                return Err(anyhow!(
+                    "the tile at {} is {}, which is outside {}",
+                    coords,
+                    tile.tile_index,
+                    ts.path.to_string_lossy()
+                ));
+            }
+            let src = imageops::crop_imm(sheet, sx, sy, w, h).to_image();
+
+            // rows count up from the bottom of the map, and down the image.
+            // tiles taller than a cell stick up out of it.
+            let x = coords.0 as i64 * tw as i64;
+            let y = (ms.tile_rows - coords.1 as u32) as i64 * th as i64 - h as i64;
+            for (px, py, pixel) in src.enumerate_pixels() {
+                let (cx, cy) = (x + px as i64, y + py as i64);
+                if cx < 0 || cy < 0 || cx >= canvas.width() as i64 || cy >= canvas.height() as i64 {
+                    continue;
+                }
+                blend(
+                    canvas.get_pixel_mut(cx as u32, cy as u32),
+                    *pixel,
+                    layer.opacity,
+                );
+            }
+        }
+    }
+
+    if overlay {
+        for y in 0..ms.tile_rows as i32 {
+            for x in 0..ms.tile_cols as i32 {
+                let Some(tile_type) = ms.metadata_at(TileCoords(x, y)) else {
+                    continue;
+                };
+                let color = overlay_color(tile_type);
+                let top = (ms.tile_rows - 1 - y as u32) * th;
+                for py in top..top + th {
+                    for px in x as u32 * tw..(x as u32 + 1) * tw {
+                        blend(canvas.get_pixel_mut(px, py), color, 1.0);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(canvas)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use anyhow::Result;
+    use shared::tilemap::MapTile;
+
+    use super::*;
+
+    #[test]
+    fn render_test() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("mapctl-render-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        // a 2x1 sheet of 2px tiles, red then half see-through blue
+        let mut sheet = RgbaImage::new(4, 2);
+        for (x, _, p) in sheet.enumerate_pixels_mut() {
+            *p = if x < 2 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 128])
+            };
+        }
+        sheet.save(dir.join("sheet.png"))?;
+
+        let mut ms = MapScreen::new(2, 3, None);
+        let id = ms.add_tileset(PathBuf::from("sheet.png"), 2, 1);
+        let top = ms.add_layer("top", false);
+        let tile = |tile_index, metadata| MapTile {
+            tileset_id: id,
+            tile_index,
+            metadata,
+        };
+        ms.set(0, TileCoords(0, 0), tile(0, None))?;
+        ms.set(0, TileCoords(2, 1), tile(0, Some(TileType::Wall)))?;
+        ms.set(top, TileCoords(0, 0), tile(1, None))?;
+
+        let image = render(&ms, &HashMap::new(), &dir, (2, 2), false)?;
+        assert_eq!(image.dimensions(), (6, 4));
+        // bottom left, blue over red
+        assert_eq!(image.get_pixel(0, 3), &Rgba([127, 0, 128, 255]));
+        assert_eq!(image.get_pixel(5, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(3, 0), &Rgba([0, 0, 0, 0]));
+
+        let marked = render(&ms, &HashMap::new(), &dir, (2, 2), true)?;
+        assert_ne!(marked.get_pixel(5, 0), image.get_pixel(5, 0));
+        assert_eq!(marked.get_pixel(0, 3), image.get_pixel(0, 3));
+
+        ms.set(0, TileCoords(1, 1), tile(2, None))?;
+        assert!(render(&ms, &HashMap::new(), &dir, (2, 2), false).is_err());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,22 @@
+use bevy::prelude::*;
+use bevy_simple_tilemap::prelude::*;
+
+use crate::tilemap::LayerTiles;
+use crate::tileset::frame_at;
+
+// every animated tile runs off the same clock, so a pond's worth of water
+// stays in step
+pub fn animate_tiles(time: Res<Time>, mut tilemaps: Query<(&mut TileMap, &mut LayerTiles)>) {
+    let elapsed = time.elapsed().as_millis() as u64;
+    for (mut tilemap, mut placed) in tilemaps.iter_mut() {
+        for placed_tile in placed.tiles.iter_mut() {
+            let Some(index) = frame_at(&placed_tile.frames, elapsed) else {
+                continue;
+            };
+            if index != placed_tile.tile.sprite_index {
+                placed_tile.tile.sprite_index = index;
+                tilemap.set_tile(placed_tile.pos, Some(placed_tile.tile));
+            }
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use crate::error::MapError;
+use crate::tilemap::{MapScreen, MapTile, TileCoords};
+use crate::tileset::{
+    Terrain, TilesetMeta, EAST, NORTH, NORTH_EAST, NORTH_WEST, SOUTH, SOUTH_EAST, SOUTH_WEST, WEST,
+};
+
+const AROUND: [(i32, i32, u8); 8] = [
+    (0, 1, NORTH),
+    (1, 1, NORTH_EAST),
+    (1, 0, EAST),
+    (1, -1, SOUTH_EAST),
+    (0, -1, SOUTH),
+    (-1, -1, SOUTH_WEST),
+    (-1, 0, WEST),
+    (-1, 1, NORTH_WEST),
+];
+
+// which of the eight cells around `coords` on `layer` hold the same
+// terrain. cells off the map count, so terrain runs off the edge rather
+// than stopping at it.
+pub fn neighbour_mask(
+    ms: &MapScreen,
+    layer: usize,
+    coords: TileCoords,
+    tileset_id: u32,
+    terrain: &Terrain,
+) -> u8 {
+    AROUND
+        .iter()
+        .filter(|(dx, dy, _)| {
+            let c = TileCoords(coords.0 + dx, coords.1 + dy);
+            !ms.contains(c)
+                || ms
+                    .get(layer, c)
+                    .is_some_and(|t| t.tileset_id == tileset_id && terrain.contains(t.tile_index))
+        })
+        .map(|(_, _, bit)| bit)
+        .sum()
+}
+
+// re-picks the tiles at `coords` and around it on `layer`, after it's been
+// painted or cleared. tiles that aren't part of a terrain are left alone.
+// returns the cells that changed, so a spawned tilemap can be patched
+// rather than redrawn.
+pub fn update(
+    ms: &mut MapScreen,
+    layer: usize,
+    coords: TileCoords,
+    metas: &HashMap<u32, TilesetMeta>,
+) -> Vec<TileCoords> {
+    let mut changed = vec![];
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let c = TileCoords(coords.0 + dx, coords.1 + dy);
+            let Some(tile) = ms.get(layer, c) else {
+                continue;
+            };
+            let Some(meta) = metas.get(&tile.tileset_id) else {
+                continue;
+            };
+            let Some((_, terrain)) = meta.terrain_of(tile.tile_index) else {
+                continue;
+            };
+            let mask = terrain
+                .kind
+                .reduce(neighbour_mask(ms, layer, c, tile.tileset_id, terrain));
+            let Some(tile_index) = terrain.tile_for(mask) else {
+                continue;
+            };
+            if tile_index == tile.tile_index {
+                continue;
+            }
+
+            let old_index = tile.tile_index;
+            let Some(tile) = ms.layers[layer].tiles.get_mut(c) else {
+                continue;
+            };
+            // metadata that came from the sidecar follows the tile, anything
+            // set by hand stays
+            if tile.metadata == meta.tile_type(old_index) {
+                tile.metadata = meta.tile_type(tile_index);
+            }
+            tile.tile_index = tile_index;
+            changed.push(c);
+        }
+    }
+    changed
+}
+
+// puts a terrain tile at `coords` and fixes up the tiles around it
+pub fn paint(
+    ms: &mut MapScreen,
+    layer: usize,
+    coords: TileCoords,
+    tileset_id: u32,
+    terrain: &str,
+    metas: &HashMap<u32, TilesetMeta>,
+) -> Result<Vec<TileCoords>, MapError> {
+    let no_terrain = || MapError::NoTerrain {
+        tileset_id,
+        terrain: terrain.to_owned(),
+    };
+    let meta = metas.get(&tileset_id).ok_or_else(no_terrain)?;
+    let tile_index = meta
+        .terrains
+        .get(terrain)
+        .and_then(|t| t.tile_for(t.kind.full()))
+        .ok_or_else(no_terrain)?;
+    ms.set(
+        layer,
+        coords,
+        MapTile {
+            tileset_id,
+            tile_index,
+            metadata: meta.tile_type(tile_index),
+        },
+    )?;
+
+    let mut changed = update(ms, layer, coords, metas);
+    if !changed.contains(&coords) {
+        changed.push(coords);
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Result};
+
+    use super::*;
+    use crate::tilemap::TileType;
+    use crate::tileset::{AutotileKind, TileMeta};
+
+    fn metas(kind: AutotileKind) -> HashMap<u32, TilesetMeta> {
+        let mut meta = TilesetMeta::default();
+        meta.terrains.insert(
+            "forest".to_owned(),
+            Terrain {
+                kind,
+                first_tile: Some(100),
+                tiles: HashMap::new(),
+            },
+        );
+        HashMap::from([(0, meta)])
+    }
+
+    fn index_at(ms: &MapScreen, coords: TileCoords) -> Option<u32> {
+        ms.get(0, coords).map(|t| t.tile_index)
+    }
+
+    #[test]
+    fn four_bit_test() -> Result<()> {
+        let metas = metas(AutotileKind::FourBit);
+        let mut ms = MapScreen::new(5, 5, None);
+        paint(&mut ms, 0, TileCoords(2, 2), 0, "forest", &metas)?;
+        // on its own, in the middle of the map
+        assert_eq!(index_at(&ms, TileCoords(2, 2)), Some(100));
+
+        let changed = paint(&mut ms, 0, TileCoords(3, 2), 0, "forest", &metas)?;
+        assert_eq!(changed.len(), 2);
+        // east for the first, west for the second
+        assert_eq!(index_at(&ms, TileCoords(2, 2)), Some(102));
+        assert_eq!(index_at(&ms, TileCoords(3, 2)), Some(108));
+
+        // against the edge of the map, which counts as more forest
+        paint(&mut ms, 0, TileCoords(0, 0), 0, "forest", &metas)?;
+        assert_eq!(index_at(&ms, TileCoords(0, 0)), Some(100 + 4 + 8));
+
+        // clearing a tile fixes up the ones next to it
+        ms.clear(0, TileCoords(3, 2));
+        assert_eq!(
+            update(&mut ms, 0, TileCoords(3, 2), &metas),
+            vec![TileCoords(2, 2)]
+        );
+        assert_eq!(index_at(&ms, TileCoords(2, 2)), Some(100));
+
+        assert!(matches!(
+            paint(&mut ms, 0, TileCoords(1, 1), 0, "swamp", &metas),
+            Err(MapError::NoTerrain { tileset_id: 0, .. })
+        ));
+        assert!(matches!(
+            paint(&mut ms, 0, TileCoords(9, 9), 0, "forest", &metas),
+            Err(MapError::OutOfBounds { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn blob_test() -> Result<()> {
+        let metas = metas(AutotileKind::Blob);
+        let masks = AutotileKind::Blob.masks();
+        let tile_for = |mask: u8| -> Result<u32> {
+            let offset = masks
+                .iter()
+                .position(|m| *m == mask)
+                .ok_or_else(|| anyhow!("{} isn't a blob mask", mask))?;
+            Ok(100 + offset as u32)
+        };
+        let mut ms = MapScreen::new(6, 6, None);
+        for y in 1..4 {
+            for x in 1..4 {
+                paint(&mut ms, 0, TileCoords(x, y), 0, "forest", &metas)?;
+            }
+        }
+        assert_eq!(index_at(&ms, TileCoords(2, 2)), Some(tile_for(255)?));
+        assert_eq!(
+            index_at(&ms, TileCoords(1, 1)),
+            Some(tile_for(NORTH | NORTH_EAST | EAST)?)
+        );
+        assert_eq!(
+            index_at(&ms, TileCoords(2, 3)),
+            Some(tile_for(EAST | SOUTH_EAST | SOUTH | SOUTH_WEST | WEST)?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn keeps_hand_set_metadata_test() -> Result<()> {
+        let mut metas = metas(AutotileKind::FourBit);
+        let meta = metas.get_mut(&0).ok_or_else(|| anyhow!("no tileset 0"))?;
+        meta.tiles.insert(
+            100,
+            TileMeta {
+                tile_type: Some(TileType::Wall),
+                ..TileMeta::default()
+            },
+        );
+        let mut ms = MapScreen::new(3, 3, None);
+        // the full tile, 115, isn't a wall
+        paint(&mut ms, 0, TileCoords(1, 1), 0, "forest", &metas)?;
+        assert_eq!(ms.metadata_at(TileCoords(1, 1)), Some(&TileType::Wall));
+
+        // a tile that's been given something else keeps it when it changes
+        let item = TileType::Item {
+            item_id: "acorn".to_owned(),
+        };
+        ms.layers[0]
+            .tiles
+            .get_mut(TileCoords(1, 1))
+            .ok_or_else(|| anyhow!("no tile at 1,1"))?
+            .metadata = Some(item.clone());
+        paint(&mut ms, 0, TileCoords(1, 2), 0, "forest", &metas)?;
+        assert_eq!(index_at(&ms, TileCoords(1, 1)), Some(101));
+        assert_eq!(ms.metadata_at(TileCoords(1, 1)), Some(&item));
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+
+use bevy::{ecs::system::Resource, math::Vec2};
+
+use crate::tilemap::{MapScreen, TileCoords, TileType};
+use crate::tileset::{CollisionShape, TileRect, TilesetMeta};
+
+// the solid parts of the current map, looked up by tile rather than by
+// comparing transforms. cells outside the map aren't blocked here; whether
+// the hero can leave is up to the world.
+#[derive(Debug, Clone, PartialEq, Default, Resource)]
+pub struct CollisionGrid {
+    cols: u32,
+    rows: u32,
+    cells: Vec<Option<CollisionShape>>,
+}
+
+impl CollisionGrid {
+    pub fn new(cols: u32, rows: u32) -> Self {
+        CollisionGrid {
+            cols,
+            rows,
+            cells: vec![None; (cols * rows) as usize],
+        }
+    }
+
+    // a tile with a shape in its tileset's sidecar uses that shape. any
+    // other tile marked as a wall fills its whole cell. the topmost layer
+    // with something to say wins.
+    pub fn from_map(ms: &MapScreen, metas: &HashMap<u32, TilesetMeta>) -> Self {
+        let mut grid = CollisionGrid::new(ms.tile_cols, ms.tile_rows);
+        for (coords, tile) in ms.tiles() {
+            let shape = metas
+                .get(&tile.tileset_id)
+                .and_then(|m| m.collision(tile.tile_index))
+                .cloned();
+            match (shape, &tile.metadata) {
+                (Some(shape), _) => grid.set_shape(coords, Some(shape)),
+                (None, Some(TileType::Wall)) => grid.set_shape(coords, Some(CollisionShape::Full)),
+                _ => false,
+            };
+        }
+        grid
+    }
+
+    // works one cell out again after the map has changed under it, the
+    // same way `from_map` does
+    pub fn refresh(
+        &mut self,
+        ms: &MapScreen,
+        metas: &HashMap<u32, TilesetMeta>,
+        coords: TileCoords,
+    ) -> bool {
+        let shape = ms.layers.iter().rev().find_map(|l| {
+            let tile = l.tiles.get(coords)?;
+            let shape = metas
+                .get(&tile.tileset_id)
+                .and_then(|m| m.collision(tile.tile_index))
+                .cloned();
+            match (shape, &tile.metadata) {
+                (Some(shape), _) => Some(shape),
+                (None, Some(TileType::Wall)) => Some(CollisionShape::Full),
+                _ => None,
+            }
+        });
+        self.set_shape(coords, shape)
+    }
+
+    pub fn cols(&self) -> u32 {
+        self.cols
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+
+    pub fn contains(&self, coords: TileCoords) -> bool {
+        let TileCoords(x, y) = coords;
+        x >= 0 && y >= 0 && (x as u32) < self.cols && (y as u32) < self.rows
+    }
+
+    fn index(&self, coords: TileCoords) -> Option<usize> {
+        if self.contains(coords) {
+            Some((coords.1 as u32 * self.cols + coords.0 as u32) as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn shape_at(&self, coords: TileCoords) -> Option<&CollisionShape> {
+        self.index(coords).and_then(|i| self.cells[i].as_ref())
+    }
+
+    // whether anything in the cell is solid
+    pub fn is_blocked(&self, coords: TileCoords) -> bool {
+        self.shape_at(coords).is_some()
+    }
+
+    // returns false if `coords` is off the map and nothing changed
+    pub fn set_shape(&mut self, coords: TileCoords, shape: Option<CollisionShape>) -> bool {
+        match self.index(coords) {
+            Some(i) => {
+                self.cells[i] = shape;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_solid(&mut self, coords: TileCoords, solid: bool) -> bool {
+        self.set_shape(coords, solid.then_some(CollisionShape::Full))
+    }
+
+    // whether something standing on `coords` with `hitbox` (in tiles,
+    // relative to the bottom left of that tile) runs into anything. only
+    // the cells the box covers are checked.
+    pub fn hits(&self, coords: TileCoords, hitbox: &TileRect) -> bool {
+        let min = Vec2::new(coords.0 as f32 + hitbox.x, coords.1 as f32 + hitbox.y);
+        let max = min + Vec2::new(hitbox.w, hitbox.h);
+        let (x0, y0) = (min.x.floor() as i32, min.y.floor() as i32);
+        let (x1, y1) = (max.x.ceil() as i32, max.y.ceil() as i32);
+
+        (y0..y1).any(|y| {
+            (x0..x1).any(|x| {
+                let cell = Vec2::new(x as f32, y as f32);
+                self.shape_at(TileCoords(x, y))
+                    .map(|s| s.overlaps(min - cell, max - cell))
+                    .unwrap_or(false)
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::tilemap::MapTile;
+    use crate::tileset::{Side, TileMeta};
+
+    #[test]
+    fn from_map_test() -> Result<()> {
+        let mut ms = MapScreen::new(4, 4, None);
+        let ground = 0;
+        let upper = ms.add_layer("upper", false);
+        let wall = MapTile {
+            tileset_id: 0,
+            tile_index: 1,
+            metadata: Some(TileType::Wall),
+        };
+        ms.set(ground, TileCoords(1, 1), wall.clone())?;
+        ms.set(upper, TileCoords(2, 3), wall)?;
+        ms.set(
+            ground,
+            TileCoords(0, 0),
+            MapTile {
+                tileset_id: 0,
+                tile_index: 2,
+                metadata: None,
+            },
+        )?;
+        ms.set(
+            ground,
+            TileCoords(3, 0),
+            MapTile {
+                tileset_id: 0,
+                tile_index: 5,
+                metadata: None,
+            },
+        )?;
+
+        let mut meta = TilesetMeta::default();
+        meta.tiles.insert(
+            5,
+            TileMeta {
+                collision: Some(CollisionShape::Half(Side::Left)),
+                ..TileMeta::default()
+            },
+        );
+        let metas = HashMap::from([(0, meta)]);
+        let mut grid = CollisionGrid::from_map(&ms, &metas);
+        assert_eq!(grid.shape_at(TileCoords(1, 1)), Some(&CollisionShape::Full));
+        assert!(grid.is_blocked(TileCoords(2, 3)));
+        assert!(!grid.is_blocked(TileCoords(0, 0)));
+        assert!(!grid.is_blocked(TileCoords(-1, 0)));
+        assert_eq!(
+            grid.shape_at(TileCoords(3, 0)),
+            Some(&CollisionShape::Half(Side::Left))
+        );
+
+        // a wall knocked down and a fence put up
+        ms.replace_metadata(TileCoords(1, 1), None)?;
+        ms.replace_tile(
+            upper,
+            TileCoords(0, 0),
+            Some(MapTile {
+                tileset_id: 0,
+                tile_index: 5,
+                metadata: None,
+            }),
+        )?;
+        for change in ms.take_changes() {
+            assert!(grid.refresh(&ms, &metas, change.coords));
+        }
+        assert!(!grid.is_blocked(TileCoords(1, 1)));
+        assert!(grid.is_blocked(TileCoords(0, 0)));
+        assert_eq!(grid, CollisionGrid::from_map(&ms, &metas));
+        Ok(())
+    }
+
+    #[test]
+    fn set_solid_test() {
+        let mut grid = CollisionGrid::new(3, 3);
+        assert!(grid.set_solid(TileCoords(2, 2), true));
+        assert!(grid.is_blocked(TileCoords(2, 2)));
+        assert!(grid.set_solid(TileCoords(2, 2), false));
+        assert!(!grid.is_blocked(TileCoords(2, 2)));
+        assert!(!grid.set_solid(TileCoords(3, 0), true));
+    }
+
+    #[test]
+    fn hitbox_test() {
+        let mut grid = CollisionGrid::new(4, 4);
+        grid.set_shape(TileCoords(1, 1), Some(CollisionShape::Half(Side::Top)));
+        grid.set_shape(TileCoords(2, 1), Some(CollisionShape::Half(Side::Bottom)));
+        let feet = TileRect {
+            x: 0.25,
+            y: 0.,
+            w: 0.5,
+            h: 0.5,
+        };
+        assert!(!grid.hits(TileCoords(1, 1), &feet));
+        assert!(grid.hits(TileCoords(2, 1), &feet));
+        assert!(!grid.hits(TileCoords(0, 0), &feet));
+
+        // a box hanging over into the next cell
+        let wide = TileRect {
+            x: 0.5,
+            y: 0.,
+            w: 1.,
+            h: 0.5,
+        };
+        assert!(grid.hits(TileCoords(1, 1), &wide));
+        assert!(!grid.hits(TileCoords(2, 2), &TileRect::FULL));
+    }
+}
@@ -1,7 +1,33 @@
 use bevy::ecs::component::Component;
 
+use crate::tilemap::TileCoords;
+use crate::tileset::TileRect;
+
 #[derive(Debug, Component)]
 pub struct Hero;
 
+// the part of its tile an entity collides with
+#[derive(Debug, Component)]
+pub struct Hitbox(pub TileRect);
+
+#[derive(Debug, Component)]
+pub struct Door {
+    pub target_map: uuid::Uuid,
+    pub target: TileCoords,
+}
+
+#[derive(Debug, Component)]
+pub struct Item {
+    pub item_id: String,
+}
+
+#[derive(Debug, Component)]
+pub struct Enemy {
+    pub kind: String,
+    pub level: u32,
+}
+
 #[derive(Debug, Component)]
-pub struct Wall;
+pub struct Npc {
+    pub npc_id: String,
+}
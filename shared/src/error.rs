@@ -0,0 +1,168 @@
+use std::{io, path::PathBuf};
+
+use thiserror::Error;
+
+use crate::tilemap::TileCoords;
+
+// where ron gave up reading a file, with lines and columns counted from 1
+fn parse_position(e: &ron::error::SpannedError) -> (usize, usize, String) {
+    (e.position.line, e.position.col, e.code.to_string())
+}
+
+#[derive(Debug, Error)]
+pub enum MapError {
+    #[error("{}: {source}", path.to_string_lossy())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("{}:{line}:{column}: {message}", path.to_string_lossy())]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    // it reads, but can't be made into a map, like two tiles in one cell
+    #[error("{}: {message}", path.to_string_lossy())]
+    Invalid { path: PathBuf, message: String },
+    #[error("tileset {} is missing", path.to_string_lossy())]
+    MissingTileset { path: PathBuf },
+    #[error(
+        "tile {tile_index} on layer {layer} at {coords} isn't in {}, which has {tile_count}",
+        path.to_string_lossy()
+    )]
+    TileOutOfRange {
+        layer: usize,
+        coords: TileCoords,
+        path: PathBuf,
+        tile_index: u32,
+        tile_count: u32,
+    },
+    #[error("{coords} is outside the {cols}x{rows} map")]
+    OutOfBounds {
+        coords: TileCoords,
+        cols: u32,
+        rows: u32,
+    },
+    #[error("there is no layer {layer}")]
+    NoLayer { layer: usize },
+    #[error("there's no tile at {coords}")]
+    NoTile { coords: TileCoords },
+    #[error("tileset {tileset_id} has no {terrain} terrain with a full tile")]
+    NoTerrain { tileset_id: u32, terrain: String },
+    #[error("the tileset has no tiles tagged {tag}")]
+    NoTilesTagged { tag: String },
+    #[error("can't generate a {cols}x{rows} map")]
+    EmptyMap { cols: u32, rows: u32 },
+}
+
+impl MapError {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        MapError::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    // anything that went wrong reading or writing a map, or a file that
+    // goes with one. ron, json and xml errors keep where they happened.
+    pub fn from_load(path: impl Into<PathBuf>, e: anyhow::Error) -> Self {
+        let path = path.into();
+        // already says which file, like a tileset the map pointed to
+        let e = match e.downcast::<MapError>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+        let e = match e.downcast::<io::Error>() {
+            Ok(source) => return MapError::Io { path, source },
+            Err(e) => e,
+        };
+        let position = if let Some(spanned) = e.downcast_ref::<ron::error::SpannedError>() {
+            Some(parse_position(spanned))
+        } else if let Some(json) = e.downcast_ref::<serde_json::Error>() {
+            // json's own message already says where
+            (json.line() > 0).then(|| (json.line(), json.column(), json.to_string()))
+        } else {
+            e.downcast_ref::<roxmltree::Error>().map(|xml| {
+                let pos = xml.pos();
+                (pos.row as usize, pos.col as usize, xml.to_string())
+            })
+        };
+        match position {
+            Some((line, column, message)) => MapError::Parse {
+                path,
+                line,
+                column,
+                message,
+            },
+            None => MapError::Invalid {
+                path,
+                message: e.to_string(),
+            },
+        }
+    }
+
+    pub fn parse(path: impl Into<PathBuf>, e: ron::error::SpannedError) -> Self {
+        Self::from_load(path, e.into())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SettingsError {
+    #[error("{}: {source}", path.to_string_lossy())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("{}:{line}:{column}: {message}", path.to_string_lossy())]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("{field} {message}")]
+    Invalid {
+        field: &'static str,
+        message: String,
+    },
+    #[error("{name} is {value:?}, which isn't a number")]
+    Env { name: String, value: String },
+}
+
+impl SettingsError {
+    pub fn parse(path: impl Into<PathBuf>, e: &ron::error::SpannedError) -> Self {
+        let (line, column, message) = parse_position(e);
+        SettingsError::Parse {
+            path: path.into(),
+            line,
+            column,
+            message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn from_load_test() -> Result<()> {
+        let spanned = ron::from_str::<u32>("\n  nope").unwrap_err();
+        let e = MapError::from_load("map.ron", spanned.into());
+        assert!(matches!(e, MapError::Parse { line: 2, .. }));
+        assert!(e.to_string().starts_with("map.ron:2:"));
+
+        let e = MapError::from_load("map.ron", anyhow::anyhow!("two tiles at 1,1"));
+        assert_eq!(e.to_string(), "map.ron: two tiles at 1,1");
+
+        let json = serde_json::from_str::<u32>("\n\n  [").unwrap_err();
+        let e = MapError::from_load("map.tmj", json.into());
+        assert!(matches!(e, MapError::Parse { line: 3, .. }));
+        let xml = roxmltree::Document::parse("<map>\n<layer =>").unwrap_err();
+        let e = MapError::from_load("map.tmx", xml.into());
+        assert!(matches!(e, MapError::Parse { line: 2, .. }));
+
+        let missing = std::fs::read_to_string("nowhere.ron").unwrap_err();
+        let e = MapError::from_load("nowhere.ron", missing.into());
+        assert!(matches!(e, MapError::Io { .. }));
+        Ok(())
+    }
+}
@@ -0,0 +1,366 @@
+use bevy::ecs::system::Resource;
+use serde::{Deserialize, Serialize};
+
+use crate::tilemap::{MapScreen, TileCoords, TileType};
+
+// one bit per cell of a map
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "SavedBitmap")]
+pub struct Bitmap {
+    cols: u32,
+    rows: u32,
+    bits: Vec<u64>,
+}
+
+// a bitmap as it's read, before the bits are checked against its size
+#[derive(Deserialize)]
+#[serde(rename = "Bitmap")]
+struct SavedBitmap {
+    cols: u32,
+    rows: u32,
+    bits: Vec<u64>,
+}
+
+impl TryFrom<SavedBitmap> for Bitmap {
+    type Error = String;
+
+    fn try_from(saved: SavedBitmap) -> Result<Self, String> {
+        let needed = (saved.cols as usize * saved.rows as usize).div_ceil(64);
+        if saved.bits.len() != needed {
+            return Err(format!(
+                "a {}x{} bitmap needs {} words of bits, not {}",
+                saved.cols,
+                saved.rows,
+                needed,
+                saved.bits.len()
+            ));
+        }
+        Ok(Bitmap {
+            cols: saved.cols,
+            rows: saved.rows,
+            bits: saved.bits,
+        })
+    }
+}
+
+impl Bitmap {
+    pub fn new(cols: u32, rows: u32) -> Self {
+        Bitmap {
+            cols,
+            rows,
+            bits: vec![0; ((cols * rows) as usize).div_ceil(64)],
+        }
+    }
+
+    pub fn cols(&self) -> u32 {
+        self.cols
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+
+    pub fn contains(&self, coords: TileCoords) -> bool {
+        let TileCoords(x, y) = coords;
+        x >= 0 && y >= 0 && (x as u32) < self.cols && (y as u32) < self.rows
+    }
+
+    fn index(&self, coords: TileCoords) -> Option<usize> {
+        self.contains(coords)
+            .then(|| (coords.1 as u32 * self.cols + coords.0 as u32) as usize)
+    }
+
+    // false off the map
+    pub fn get(&self, coords: TileCoords) -> bool {
+        self.index(coords)
+            .map(|i| self.bits[i / 64] & (1 << (i % 64)) != 0)
+            .unwrap_or(false)
+    }
+
+    pub fn set(&mut self, coords: TileCoords, on: bool) {
+        if let Some(i) = self.index(coords) {
+            if on {
+                self.bits[i / 64] |= 1 << (i % 64);
+            } else {
+                self.bits[i / 64] &= !(1 << (i % 64));
+            }
+        }
+    }
+
+    // turns on everything that's on in `other`, which should be the same size
+    pub fn union(&mut self, other: &Bitmap) {
+        for (a, b) in self.bits.iter_mut().zip(&other.bits) {
+            *a |= b;
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.bits.iter().map(|b| b.count_ones()).sum()
+    }
+}
+
+// cells that block sight. anything marked as a wall does.
+pub fn opaque_cells(ms: &MapScreen) -> Bitmap {
+    let mut opaque = Bitmap::new(ms.tile_cols, ms.tile_rows);
+    for y in 0..ms.tile_rows as i32 {
+        for x in 0..ms.tile_cols as i32 {
+            let coords = TileCoords(x, y);
+            if ms.metadata_at(coords) == Some(&TileType::Wall) {
+                opaque.set(coords, true);
+            }
+        }
+    }
+    opaque
+}
+
+// how each octant's rows and columns map onto the grid
+const OCTANTS: [(i32, i32, i32, i32); 8] = [
+    (1, 0, 0, 1),
+    (0, 1, 1, 0),
+    (0, -1, 1, 0),
+    (-1, 0, 0, 1),
+    (-1, 0, 0, -1),
+    (0, -1, -1, 0),
+    (0, 1, -1, 0),
+    (1, 0, 0, -1),
+];
+
+// what can be seen from `origin` within `radius` tiles, by recursive
+// shadowcasting. walls themselves are seen, just not past.
+pub fn field_of_view(opaque: &Bitmap, origin: TileCoords, radius: u32) -> Bitmap {
+    let mut visible = Bitmap::new(opaque.cols(), opaque.rows());
+    visible.set(origin, true);
+    for octant in OCTANTS {
+        cast_light(
+            opaque,
+            &mut visible,
+            origin,
+            radius as i32,
+            1,
+            (1.0, 0.0),
+            octant,
+        );
+    }
+    visible
+}
+
+// lights one octant, from `row` outwards, between two slopes
+fn cast_light(
+    opaque: &Bitmap,
+    visible: &mut Bitmap,
+    origin: TileCoords,
+    radius: i32,
+    row: i32,
+    slopes: (f32, f32),
+    octant: (i32, i32, i32, i32),
+) {
+    let (mut start, end) = slopes;
+    if start < end {
+        return;
+    }
+    let (xx, xy, yx, yy) = octant;
+    let mut next_start = start;
+    for distance in row..=radius {
+        let dy = -distance;
+        let mut blocked = false;
+        for dx in -distance..=0 {
+            let left = (dx as f32 - 0.5) / (dy as f32 + 0.5);
+            let right = (dx as f32 + 0.5) / (dy as f32 - 0.5);
+            if start < right {
+                continue;
+            } else if end > left {
+                break;
+            }
+
+            let coords = TileCoords(origin.0 + dx * xx + dy * xy, origin.1 + dx * yx + dy * yy);
+            if dx * dx + dy * dy <= radius * radius {
+                visible.set(coords, true);
+            }
+            let is_opaque = !opaque.contains(coords) || opaque.get(coords);
+            if blocked {
+                if is_opaque {
+                    next_start = right;
+                } else {
+                    blocked = false;
+                    start = next_start;
+                }
+            } else if is_opaque && distance < radius {
+                blocked = true;
+                cast_light(
+                    opaque,
+                    visible,
+                    origin,
+                    radius,
+                    distance + 1,
+                    (start, left),
+                    octant,
+                );
+                next_start = right;
+            }
+        }
+        if blocked {
+            break;
+        }
+    }
+}
+
+// what the hero can see on the current map, and what they've seen before
+#[derive(Debug, Clone, Resource)]
+pub struct FogOfWar {
+    pub map_id: uuid::Uuid,
+    opaque: Bitmap,
+    visible: Bitmap,
+    explored: Bitmap,
+    // where it was last worked out from
+    origin: Option<TileCoords>,
+}
+
+impl FogOfWar {
+    // `explored` is what was seen on an earlier visit. it's dropped if the
+    // map has changed size since.
+    pub fn new(ms: &MapScreen, explored: Option<Bitmap>) -> Self {
+        let opaque = opaque_cells(ms);
+        let blank = Bitmap::new(ms.tile_cols, ms.tile_rows);
+        let explored = explored
+            .filter(|e| (e.cols(), e.rows()) == (ms.tile_cols, ms.tile_rows))
+            .unwrap_or_else(|| blank.clone());
+        FogOfWar {
+            map_id: ms.map_id,
+            opaque,
+            visible: blank,
+            explored,
+            origin: None,
+        }
+    }
+
+    // returns false if the hero hasn't moved and nothing changed
+    pub fn update(&mut self, origin: TileCoords, radius: u32) -> bool {
+        if self.origin == Some(origin) {
+            return false;
+        }
+        self.origin = Some(origin);
+        self.visible = field_of_view(&self.opaque, origin, radius);
+        self.explored.union(&self.visible);
+        true
+    }
+
+    // after walls have come or gone. what's visible is worked out again on
+    // the next update.
+    pub fn refresh(&mut self, ms: &MapScreen) {
+        self.opaque = opaque_cells(ms);
+        self.origin = None;
+    }
+
+    pub fn is_visible(&self, coords: TileCoords) -> bool {
+        self.visible.get(coords)
+    }
+
+    pub fn is_explored(&self, coords: TileCoords) -> bool {
+        self.explored.get(coords)
+    }
+
+    pub fn explored(&self) -> &Bitmap {
+        &self.explored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::tilemap::MapTile;
+
+    fn wall() -> MapTile {
+        MapTile {
+            tileset_id: 0,
+            tile_index: 0,
+            metadata: Some(TileType::Wall),
+        }
+    }
+
+    #[test]
+    fn bitmap_test() -> Result<()> {
+        let mut bits = Bitmap::new(10, 10);
+        bits.set(TileCoords(9, 9), true);
+        bits.set(TileCoords(3, 2), true);
+        bits.set(TileCoords(10, 0), true);
+        assert!(bits.get(TileCoords(9, 9)));
+        assert!(!bits.get(TileCoords(10, 0)));
+        assert_eq!(bits.count(), 2);
+        bits.set(TileCoords(3, 2), false);
+        assert_eq!(bits.count(), 1);
+
+        let saved = ron::to_string(&bits)?;
+        assert_eq!(ron::from_str::<Bitmap>(&saved)?, bits);
+        // cut short, it would index past the end
+        assert!(ron::from_str::<Bitmap>("(cols: 10, rows: 10, bits: [0])").is_err());
+        assert!(ron::from_str::<Bitmap>("(cols: 0, rows: 0, bits: [])").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn open_room_test() {
+        let opaque = Bitmap::new(11, 11);
+        let visible = field_of_view(&opaque, TileCoords(5, 5), 3);
+        assert!(visible.get(TileCoords(5, 5)));
+        assert!(visible.get(TileCoords(8, 5)));
+        assert!(visible.get(TileCoords(7, 7)));
+        // outside the radius
+        assert!(!visible.get(TileCoords(9, 5)));
+        assert!(!visible.get(TileCoords(8, 8)));
+    }
+
+    #[test]
+    fn walls_block_sight_test() -> Result<()> {
+        let mut ms = MapScreen::new(9, 9, None);
+        // a wall across the map, apart from a gap at the end
+        for x in 0..6 {
+            ms.set(0, TileCoords(x, 5), wall())?;
+        }
+        let visible = field_of_view(&opaque_cells(&ms), TileCoords(2, 2), 10);
+        // the wall can be seen, but not what's behind it
+        assert!(visible.get(TileCoords(2, 5)));
+        assert!(!visible.get(TileCoords(2, 6)));
+        assert!(!visible.get(TileCoords(0, 8)));
+        // through the gap
+        assert!(visible.get(TileCoords(7, 6)));
+        Ok(())
+    }
+
+    #[test]
+    fn fog_of_war_test() -> Result<()> {
+        let mut ms = MapScreen::new(5, 12, None);
+        for y in 0..5 {
+            ms.set(0, TileCoords(6, y), wall())?;
+        }
+        let mut fog = FogOfWar::new(&ms, None);
+        assert!(fog.update(TileCoords(1, 2), 4));
+        assert!(!fog.update(TileCoords(1, 2), 4));
+        assert!(fog.is_visible(TileCoords(3, 2)));
+        assert!(!fog.is_explored(TileCoords(8, 2)));
+
+        fog.update(TileCoords(1, 0), 1);
+        // seen before, but not now
+        assert!(fog.is_explored(TileCoords(3, 2)));
+        assert!(!fog.is_visible(TileCoords(3, 2)));
+
+        // a later visit remembers it
+        let again = FogOfWar::new(&ms, Some(fog.explored().clone()));
+        assert!(again.is_explored(TileCoords(3, 2)));
+        assert!(!again.is_visible(TileCoords(3, 2)));
+
+        // knocking the wall down lets the hero see through
+        for y in 0..5 {
+            ms.replace_metadata(TileCoords(6, y), None)?;
+        }
+        fog.refresh(&ms);
+        assert!(fog.update(TileCoords(1, 0), 1));
+        assert!(fog.update(TileCoords(4, 2), 4));
+        assert!(fog.is_visible(TileCoords(8, 2)));
+
+        let smaller = Bitmap::new(2, 2);
+        assert_eq!(FogOfWar::new(&ms, Some(smaller)).explored().count(), 0);
+        Ok(())
+    }
+}
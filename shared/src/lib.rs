@@ -1,3 +1,14 @@
+pub mod animation;
+pub mod autotile;
+pub mod collision;
 pub mod components;
+pub mod error;
+pub mod fov;
+pub mod lighting;
+pub mod pathfind;
+pub mod procgen;
+pub mod save;
 pub mod settings;
 pub mod tilemap;
+pub mod tileset;
+pub mod world;
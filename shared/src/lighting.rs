@@ -0,0 +1,288 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error, Result};
+use bevy::ecs::system::Resource;
+use bevy::render::color::Color;
+use serde::{Deserialize, Serialize};
+
+use crate::fov::{field_of_view, opaque_cells, Bitmap};
+use crate::tilemap::{MapScreen, TileCoords};
+
+// ambient light for maps that don't say, so they're drawn as they are
+pub const FULL_LIGHT: f32 = 1.0;
+
+// how a light dims towards the edge of its radius
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Falloff {
+    // just as bright all the way out
+    None,
+    #[default]
+    Linear,
+    Quadratic,
+}
+
+impl FromStr for Falloff {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Falloff::None),
+            "linear" => Ok(Falloff::Linear),
+            "quadratic" => Ok(Falloff::Quadratic),
+            other => Err(anyhow!("unknown falloff {}", other)),
+        }
+    }
+}
+
+impl Falloff {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Falloff::None => "none",
+            Falloff::Linear => "linear",
+            Falloff::Quadratic => "quadratic",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LightSource {
+    pub coords: TileCoords,
+    // in tiles
+    pub radius: u32,
+    // red, green and blue, from 0 to 1
+    pub color: (f32, f32, f32),
+    #[serde(default)]
+    pub falloff: Falloff,
+}
+
+impl LightSource {
+    // how much of the light reaches `distance` tiles away. the last tile
+    // inside the radius still gets a little.
+    pub fn intensity(&self, distance: f32) -> f32 {
+        if distance > self.radius as f32 {
+            return 0.0;
+        }
+        let left = 1.0 - distance / (self.radius + 1) as f32;
+        match self.falloff {
+            Falloff::None => 1.0,
+            Falloff::Linear => left,
+            Falloff::Quadratic => left * left,
+        }
+    }
+}
+
+// how much light falls on each cell of a map
+#[derive(Debug, Clone, PartialEq)]
+pub struct LightMap {
+    cols: u32,
+    rows: u32,
+    light: Vec<(f32, f32, f32)>,
+}
+
+impl LightMap {
+    pub fn new(cols: u32, rows: u32, ambient: f32) -> Self {
+        LightMap {
+            cols,
+            rows,
+            light: vec![(ambient, ambient, ambient); (cols * rows) as usize],
+        }
+    }
+
+    fn index(&self, coords: TileCoords) -> Option<usize> {
+        let TileCoords(x, y) = coords;
+        if x >= 0 && y >= 0 && (x as u32) < self.cols && (y as u32) < self.rows {
+            Some((y as u32 * self.cols + x as u32) as usize)
+        } else {
+            None
+        }
+    }
+
+    // adds a light to what's already there. it only reaches cells it can
+    // see, so walls cast shadows.
+    pub fn add(&mut self, light: &LightSource, opaque: &Bitmap) {
+        let reached = field_of_view(opaque, light.coords, light.radius);
+        let r = light.radius as i32;
+        let TileCoords(lx, ly) = light.coords;
+        for y in ly - r..=ly + r {
+            for x in lx - r..=lx + r {
+                let coords = TileCoords(x, y);
+                let Some(i) = self.index(coords) else {
+                    continue;
+                };
+                if !reached.get(coords) {
+                    continue;
+                }
+                let (dx, dy) = ((x - lx) as f32, (y - ly) as f32);
+                let strength = light.intensity((dx * dx + dy * dy).sqrt());
+                let (r, g, b) = light.color;
+                let cell = &mut self.light[i];
+                cell.0 += r * strength;
+                cell.1 += g * strength;
+                cell.2 += b * strength;
+            }
+        }
+    }
+
+    // lights add up, but never past full brightness. black off the map.
+    pub fn get(&self, coords: TileCoords) -> Color {
+        match self.index(coords) {
+            Some(i) => {
+                let (r, g, b) = self.light[i];
+                Color::rgb(r.min(1.0), g.min(1.0), b.min(1.0))
+            }
+            None => Color::BLACK,
+        }
+    }
+}
+
+// the light on the current map
+#[derive(Debug, Clone, Resource)]
+pub struct Lighting {
+    opaque: Bitmap,
+    // the map's own lights, which never move
+    fixed: LightMap,
+    lit: LightMap,
+    carried: Option<LightSource>,
+}
+
+impl Lighting {
+    pub fn new(ms: &MapScreen) -> Self {
+        let opaque = opaque_cells(ms);
+        let mut fixed = LightMap::new(ms.tile_cols, ms.tile_rows, ms.ambient_light);
+        for light in &ms.lights {
+            fixed.add(light, &opaque);
+        }
+        Lighting {
+            opaque,
+            lit: fixed.clone(),
+            fixed,
+            carried: None,
+        }
+    }
+
+    // a light that goes wherever something does, like the hero's lantern.
+    // returns false if it hasn't moved and nothing changed.
+    pub fn carry(&mut self, light: Option<LightSource>) -> bool {
+        if self.carried == light {
+            return false;
+        }
+        self.lit = self.fixed.clone();
+        if let Some(light) = &light {
+            self.lit.add(light, &self.opaque);
+        }
+        self.carried = light;
+        true
+    }
+
+    // after walls have come or gone, or the map's lights have changed
+    pub fn refresh(&mut self, ms: &MapScreen) {
+        let carried = self.carried.take();
+        *self = Lighting::new(ms);
+        self.carry(carried);
+    }
+
+    pub fn light_at(&self, coords: TileCoords) -> Color {
+        self.lit.get(coords)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tilemap::{MapTile, TileType};
+
+    fn torch(coords: TileCoords, falloff: Falloff) -> LightSource {
+        LightSource {
+            coords,
+            radius: 3,
+            color: (1.0, 0.5, 0.0),
+            falloff,
+        }
+    }
+
+    #[test]
+    fn falloff_test() -> Result<()> {
+        let flat = torch(TileCoords(0, 0), Falloff::None);
+        let linear = torch(TileCoords(0, 0), Falloff::Linear);
+        let quadratic = torch(TileCoords(0, 0), Falloff::Quadratic);
+        assert_eq!(flat.intensity(3.0), 1.0);
+        assert_eq!(linear.intensity(0.0), 1.0);
+        assert_eq!(linear.intensity(2.0), 0.5);
+        assert_eq!(quadratic.intensity(2.0), 0.25);
+        assert_eq!(linear.intensity(3.5), 0.0);
+        assert!(linear.intensity(3.0) > 0.0);
+        assert_eq!("Quadratic".parse::<Falloff>()?, Falloff::Quadratic);
+        assert!("bright".parse::<Falloff>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn light_map_test() -> Result<()> {
+        let mut ms = MapScreen::new(9, 9, None);
+        ms.ambient_light = 0.2;
+        // a wall between the torch and the left of the map
+        for y in 0..9 {
+            ms.set(
+                0,
+                TileCoords(3, y),
+                MapTile {
+                    tileset_id: 0,
+                    tile_index: 0,
+                    metadata: Some(TileType::Wall),
+                },
+            )?;
+        }
+        ms.lights.push(torch(TileCoords(5, 4), Falloff::Linear));
+
+        let mut lighting = Lighting::new(&ms);
+        assert_eq!(
+            lighting.light_at(TileCoords(5, 4)),
+            Color::rgb(1.0, 0.7, 0.2)
+        );
+        assert_eq!(
+            lighting.light_at(TileCoords(7, 4)),
+            Color::rgb(0.7, 0.45, 0.2)
+        );
+        // the wall is lit, but not what's behind it, or out of reach
+        assert_eq!(
+            lighting.light_at(TileCoords(3, 4)),
+            Color::rgb(0.7, 0.45, 0.2)
+        );
+        assert_eq!(
+            lighting.light_at(TileCoords(2, 4)),
+            Color::rgb(0.2, 0.2, 0.2)
+        );
+        assert_eq!(
+            lighting.light_at(TileCoords(5, 8)),
+            Color::rgb(0.2, 0.2, 0.2)
+        );
+        assert_eq!(lighting.light_at(TileCoords(9, 4)), Color::BLACK);
+
+        // a lantern carried to the other side
+        let lantern = LightSource {
+            coords: TileCoords(1, 4),
+            radius: 1,
+            color: (1.0, 1.0, 1.0),
+            falloff: Falloff::None,
+        };
+        assert!(lighting.carry(Some(lantern.clone())));
+        assert!(!lighting.carry(Some(lantern)));
+        assert_eq!(lighting.light_at(TileCoords(2, 4)), Color::WHITE);
+        assert!(lighting.carry(None));
+        assert_eq!(
+            lighting.light_at(TileCoords(2, 4)),
+            Color::rgb(0.2, 0.2, 0.2)
+        );
+
+        // the wall comes down
+        for y in 0..9 {
+            ms.replace_metadata(TileCoords(3, y), None)?;
+        }
+        lighting.refresh(&ms);
+        assert_eq!(
+            lighting.light_at(TileCoords(2, 4)),
+            Color::rgb(0.45, 0.325, 0.2)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use crate::collision::CollisionGrid;
+use crate::tilemap::{MapScreen, TileCoords, TileType};
+
+// a step straight across a tile costs its cost times this, and a step
+// across a corner about 1.4 times as much
+const STRAIGHT: u32 = 10;
+const DIAGONAL: u32 = 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Moves {
+    Four,
+    // diagonal steps can't cut past a corner that's blocked
+    Eight,
+}
+
+// what it costs to walk onto each cell of a map. None is somewhere that
+// can't be walked onto at all.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathGrid {
+    cols: u32,
+    rows: u32,
+    costs: Vec<Option<u32>>,
+}
+
+impl PathGrid {
+    pub fn new(cols: u32, rows: u32) -> Self {
+        PathGrid {
+            cols,
+            rows,
+            costs: vec![Some(1); (cols * rows) as usize],
+        }
+    }
+
+    // walls can't be walked through, everything else costs the same
+    pub fn from_map(ms: &MapScreen) -> Self {
+        let mut grid = PathGrid::new(ms.tile_cols, ms.tile_rows);
+        for y in 0..ms.tile_rows as i32 {
+            for x in 0..ms.tile_cols as i32 {
+                let coords = TileCoords(x, y);
+                if ms.metadata_at(coords) == Some(&TileType::Wall) {
+                    grid.set_cost(coords, None);
+                }
+            }
+        }
+        grid
+    }
+
+    // anything with a collision shape is out of bounds, even if it only
+    // fills part of the cell
+    pub fn from_collision(collision: &CollisionGrid) -> Self {
+        let mut grid = PathGrid::new(collision.cols(), collision.rows());
+        for y in 0..collision.rows() as i32 {
+            for x in 0..collision.cols() as i32 {
+                if collision.is_blocked(TileCoords(x, y)) {
+                    grid.set_cost(TileCoords(x, y), None);
+                }
+            }
+        }
+        grid
+    }
+
+    fn index(&self, coords: TileCoords) -> Option<usize> {
+        let TileCoords(x, y) = coords;
+        if x >= 0 && y >= 0 && (x as u32) < self.cols && (y as u32) < self.rows {
+            Some((y as u32 * self.cols + x as u32) as usize)
+        } else {
+            None
+        }
+    }
+
+    // None off the map
+    pub fn cost(&self, coords: TileCoords) -> Option<u32> {
+        self.index(coords).and_then(|i| self.costs[i])
+    }
+
+    // returns false if `coords` is off the map and nothing changed. costs
+    // below 1 are treated as 1, so paths still prefer fewer steps.
+    pub fn set_cost(&mut self, coords: TileCoords, cost: Option<u32>) -> bool {
+        match self.index(coords) {
+            Some(i) => {
+                self.costs[i] = cost.map(|c| c.max(1));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // the cheapest way from `from` to `to`, not counting `from` itself.
+    // cells in `occupied` are treated as blocked, apart from `to`, so
+    // something can path to whatever it's chasing. None if there's no way
+    // there.
+    pub fn find_path(
+        &self,
+        from: TileCoords,
+        to: TileCoords,
+        moves: Moves,
+        occupied: &HashSet<TileCoords>,
+    ) -> Option<Vec<TileCoords>> {
+        let passable = |c: TileCoords| {
+            let cost = self.cost(c)?;
+            (c == to || !occupied.contains(&c)).then_some(cost)
+        };
+        passable(to)?;
+        if from == to {
+            return Some(vec![]);
+        }
+
+        let heuristic = |c: TileCoords| {
+            let dx = (c.0 - to.0).unsigned_abs();
+            let dy = (c.1 - to.1).unsigned_abs();
+            match moves {
+                Moves::Four => STRAIGHT * (dx + dy),
+                Moves::Eight => STRAIGHT * dx.max(dy) + (DIAGONAL - STRAIGHT) * dx.min(dy),
+            }
+        };
+
+        // (estimate, order found) so ties come out the same way every time
+        let mut open = BinaryHeap::new();
+        let mut came_from: HashMap<TileCoords, TileCoords> = HashMap::new();
+        let mut best: HashMap<TileCoords, u32> = HashMap::from([(from, 0)]);
+        let mut found = 0u32;
+        open.push(Reverse((heuristic(from), found, from.0, from.1)));
+
+        while let Some(Reverse((_, _, x, y))) = open.pop() {
+            let current = TileCoords(x, y);
+            if current == to {
+                let mut path = vec![current];
+                let mut at = current;
+                while let Some(prev) = came_from.get(&at) {
+                    if *prev == from {
+                        break;
+                    }
+                    path.push(*prev);
+                    at = *prev;
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            let so_far = best[&current];
+            for &(dx, dy) in neighbours(moves) {
+                let next = TileCoords(current.0 + dx, current.1 + dy);
+                let Some(cost) = passable(next) else {
+                    continue;
+                };
+                let diagonal = dx != 0 && dy != 0;
+                if diagonal
+                    && (self.cost(TileCoords(current.0 + dx, current.1)).is_none()
+                        || self.cost(TileCoords(current.0, current.1 + dy)).is_none())
+                {
+                    continue;
+                }
+                let step = if diagonal { DIAGONAL } else { STRAIGHT };
+                let total = so_far + cost * step;
+                if best.get(&next).is_some_and(|b| *b <= total) {
+                    continue;
+                }
+                best.insert(next, total);
+                came_from.insert(next, current);
+                found += 1;
+                open.push(Reverse((total + heuristic(next), found, next.0, next.1)));
+            }
+        }
+        None
+    }
+}
+
+fn neighbours(moves: Moves) -> &'static [(i32, i32)] {
+    match moves {
+        Moves::Four => &[(0, 1), (1, 0), (0, -1), (-1, 0)],
+        Moves::Eight => &[
+            (0, 1),
+            (1, 0),
+            (0, -1),
+            (-1, 0),
+            (1, 1),
+            (1, -1),
+            (-1, -1),
+            (-1, 1),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Result};
+
+    use super::*;
+    use crate::tilemap::MapTile;
+
+    // a 5x5 map with a wall down the middle, open at the top
+    fn walled_map() -> Result<MapScreen> {
+        let mut ms = MapScreen::new(5, 5, None);
+        for y in 0..4 {
+            ms.set(
+                0,
+                TileCoords(2, y),
+                MapTile {
+                    tileset_id: 0,
+                    tile_index: 0,
+                    metadata: Some(TileType::Wall),
+                },
+            )?;
+        }
+        Ok(ms)
+    }
+
+    #[test]
+    fn around_a_wall_test() -> Result<()> {
+        let grid = PathGrid::from_map(&walled_map()?);
+        let none = HashSet::new();
+        let path = grid
+            .find_path(TileCoords(0, 0), TileCoords(4, 0), Moves::Four, &none)
+            .ok_or_else(|| anyhow!("no path"))?;
+        // up the left, over the top, down the right
+        assert_eq!(path.len(), 12);
+        assert_eq!(path.last(), Some(&TileCoords(4, 0)));
+        assert!(path.contains(&TileCoords(2, 4)));
+        for pair in path.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!((a.0 - b.0).abs() + (a.1 - b.1).abs(), 1);
+        }
+
+        let path = grid
+            .find_path(TileCoords(0, 0), TileCoords(4, 0), Moves::Eight, &none)
+            .ok_or_else(|| anyhow!("no path"))?;
+        assert_eq!(path.len(), 10);
+        assert_eq!(
+            grid.find_path(TileCoords(1, 1), TileCoords(1, 1), Moves::Four, &none),
+            Some(vec![])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn no_path_test() -> Result<()> {
+        let mut grid = PathGrid::from_map(&walled_map()?);
+        grid.set_cost(TileCoords(2, 4), None);
+        assert_eq!(
+            PathGrid::from_collision(&CollisionGrid::from_map(&walled_map()?, &HashMap::new()))
+                .cost(TileCoords(2, 0)),
+            None
+        );
+        let none = HashSet::new();
+        assert_eq!(
+            grid.find_path(TileCoords(0, 0), TileCoords(4, 0), Moves::Eight, &none),
+            None
+        );
+        // into a wall, or off the map
+        assert_eq!(
+            grid.find_path(TileCoords(0, 0), TileCoords(2, 0), Moves::Four, &none),
+            None
+        );
+        assert_eq!(
+            grid.find_path(TileCoords(0, 0), TileCoords(9, 0), Moves::Four, &none),
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn corners_test() -> Result<()> {
+        let mut grid = PathGrid::new(2, 2);
+        grid.set_cost(TileCoords(1, 0), None);
+        let path = grid
+            .find_path(
+                TileCoords(0, 0),
+                TileCoords(1, 1),
+                Moves::Eight,
+                &HashSet::new(),
+            )
+            .ok_or_else(|| anyhow!("no path"))?;
+        // round the corner rather than across it
+        assert_eq!(path, vec![TileCoords(0, 1), TileCoords(1, 1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn costs_and_occupied_test() -> Result<()> {
+        let mut grid = PathGrid::new(3, 3);
+        let straight = grid
+            .find_path(
+                TileCoords(0, 1),
+                TileCoords(2, 1),
+                Moves::Four,
+                &HashSet::new(),
+            )
+            .ok_or_else(|| anyhow!("no path"))?;
+        assert_eq!(straight, vec![TileCoords(1, 1), TileCoords(2, 1)]);
+
+        // mud in the middle is worth walking round
+        grid.set_cost(TileCoords(1, 1), Some(5));
+        let round = grid
+            .find_path(
+                TileCoords(0, 1),
+                TileCoords(2, 1),
+                Moves::Four,
+                &HashSet::new(),
+            )
+            .ok_or_else(|| anyhow!("no path"))?;
+        assert_eq!(round.len(), 4);
+        assert!(!round.contains(&TileCoords(1, 1)));
+
+        // something standing in the way is too, but the target isn't
+        grid.set_cost(TileCoords(1, 1), Some(1));
+        let occupied = HashSet::from([TileCoords(1, 1), TileCoords(2, 1)]);
+        let path = grid
+            .find_path(TileCoords(0, 1), TileCoords(2, 1), Moves::Four, &occupied)
+            .ok_or_else(|| anyhow!("no path"))?;
+        assert_eq!(path.len(), 4);
+        assert_eq!(path.last(), Some(&TileCoords(2, 1)));
+        Ok(())
+    }
+}
@@ -0,0 +1,487 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::MapError;
+use crate::tilemap::{MapScreen, MapTile, TileCoords, TileType, Tileset};
+use crate::tileset::TilesetMeta;
+
+// splitmix64. it's here rather than pulled in so a seed gives the same map
+// no matter what happens to a crate's version
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // somewhere in 0..n, or 0 if n is
+    fn below(&mut self, n: u32) -> u32 {
+        if n == 0 {
+            return 0;
+        }
+        (self.next_u64() % n as u64) as u32
+    }
+
+    // somewhere in lo..=hi
+    fn between(&mut self, lo: u32, hi: u32) -> u32 {
+        lo + self.below(hi.saturating_sub(lo) + 1)
+    }
+
+    fn chance(&mut self, p: f32) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p as f64
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum Generator {
+    // cellular automata. `fill` is how much starts solid, and each step
+    // smooths it towards caves.
+    Caves { fill: f32, steps: u32 },
+    // binary space partitioning: the map is split in two until the pieces
+    // are too small, then each gets a room and siblings are joined by
+    // corridors
+    Rooms { min_room: u32, max_depth: u32 },
+    // open ground with `density` of it covered
+    Forest { density: f32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Params {
+    pub cols: u32,
+    pub rows: u32,
+    pub generator: Generator,
+    // tiles are picked from the tileset's sidecar by these tags
+    pub floor_tag: String,
+    pub wall_tag: String,
+}
+
+impl Params {
+    pub fn new(cols: u32, rows: u32, generator: Generator) -> Self {
+        Params {
+            cols,
+            rows,
+            generator,
+            floor_tag: "floor".to_owned(),
+            wall_tag: "wall".to_owned(),
+        }
+    }
+}
+
+// which cells are solid, row by row from the bottom
+#[derive(Debug, Clone, PartialEq)]
+struct Solid {
+    cols: u32,
+    rows: u32,
+    cells: Vec<bool>,
+}
+
+impl Solid {
+    fn new(cols: u32, rows: u32, solid: bool) -> Self {
+        Solid {
+            cols,
+            rows,
+            cells: vec![solid; (cols * rows) as usize],
+        }
+    }
+
+    // off the edge counts as solid
+    fn get(&self, x: i32, y: i32) -> bool {
+        if x < 0 || y < 0 || x as u32 >= self.cols || y as u32 >= self.rows {
+            return true;
+        }
+        self.cells[(y as u32 * self.cols + x as u32) as usize]
+    }
+
+    fn set(&mut self, x: u32, y: u32, solid: bool) {
+        if x < self.cols && y < self.rows {
+            self.cells[(y * self.cols + x) as usize] = solid;
+        }
+    }
+
+    fn solid_around(&self, x: i32, y: i32) -> u32 {
+        let mut count = 0;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if (dx, dy) != (0, 0) && self.get(x + dx, y + dy) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+}
+
+fn caves(rng: &mut Rng, cols: u32, rows: u32, fill: f32, steps: u32) -> Solid {
+    let mut solid = Solid::new(cols, rows, false);
+    for y in 0..rows {
+        for x in 0..cols {
+            let edge = x == 0 || y == 0 || x == cols - 1 || y == rows - 1;
+            solid.set(x, y, edge || rng.chance(fill));
+        }
+    }
+    for _ in 0..steps {
+        let mut next = solid.clone();
+        for y in 0..rows {
+            for x in 0..cols {
+                let around = solid.solid_around(x as i32, y as i32);
+                if around > 4 {
+                    next.set(x, y, true);
+                } else if around < 4 {
+                    next.set(x, y, false);
+                }
+            }
+        }
+        solid = next;
+    }
+    solid
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+impl Rect {
+    fn center(&self) -> (u32, u32) {
+        (self.x + self.w / 2, self.y + self.h / 2)
+    }
+}
+
+// carves a room somewhere in `area` and returns a point in it, for the
+// corridor from its sibling to aim at
+fn split(rng: &mut Rng, solid: &mut Solid, area: Rect, min_room: u32, depth: u32) -> (u32, u32) {
+    // a room needs a wall on each side of it
+    let min_area = min_room + 2;
+    let can_split_x = area.w >= min_area * 2;
+    let can_split_y = area.h >= min_area * 2;
+    if depth == 0 || !(can_split_x || can_split_y) {
+        // on a map too small for `min_room` the room is as big as fits
+        let (max_w, max_h) = (area.w.saturating_sub(2), area.h.saturating_sub(2));
+        let w = rng.between(min_room.min(max_w), max_w);
+        let h = rng.between(min_room.min(max_h), max_h);
+        let x = area.x + 1 + rng.below(area.w.saturating_sub(w + 1));
+        let y = area.y + 1 + rng.below(area.h.saturating_sub(h + 1));
+        let room = Rect { x, y, w, h };
+        for ry in room.y..room.y + room.h {
+            for rx in room.x..room.x + room.w {
+                solid.set(rx, ry, false);
+            }
+        }
+        return room.center();
+    }
+
+    let across = match (can_split_x, can_split_y) {
+        (true, true) => area.w >= area.h,
+        (x, _) => x,
+    };
+    let (a, b) = if across {
+        let at = rng.between(min_area, area.w - min_area);
+        (
+            Rect { w: at, ..area },
+            Rect {
+                x: area.x + at,
+                w: area.w - at,
+                ..area
+            },
+        )
+    } else {
+        let at = rng.between(min_area, area.h - min_area);
+        (
+            Rect { h: at, ..area },
+            Rect {
+                y: area.y + at,
+                h: area.h - at,
+                ..area
+            },
+        )
+    };
+    let from = split(rng, solid, a, min_room, depth - 1);
+    let to = split(rng, solid, b, min_room, depth - 1);
+
+    // an l shaped corridor, going along then up or up then along
+    let corner = if rng.chance(0.5) {
+        (to.0, from.1)
+    } else {
+        (from.0, to.1)
+    };
+    for (start, end) in [(from, corner), (corner, to)] {
+        for x in start.0.min(end.0)..=start.0.max(end.0) {
+            for y in start.1.min(end.1)..=start.1.max(end.1) {
+                solid.set(x, y, false);
+            }
+        }
+    }
+    from
+}
+
+fn rooms(rng: &mut Rng, cols: u32, rows: u32, min_room: u32, max_depth: u32) -> Solid {
+    let mut solid = Solid::new(cols, rows, true);
+    let area = Rect {
+        x: 0,
+        y: 0,
+        w: cols,
+        h: rows,
+    };
+    split(rng, &mut solid, area, min_room.max(1), max_depth);
+    solid
+}
+
+fn forest(rng: &mut Rng, cols: u32, rows: u32, density: f32) -> Solid {
+    let mut solid = Solid::new(cols, rows, false);
+    for y in 0..rows {
+        for x in 0..cols {
+            solid.set(x, y, rng.chance(density));
+        }
+    }
+    solid
+}
+
+// tiles with a tag, in a fixed order so the same seed picks the same ones
+fn tagged(meta: &TilesetMeta, tag: &str) -> Result<Vec<u32>, MapError> {
+    let mut tiles: Vec<u32> = meta.tagged(tag).collect();
+    if tiles.is_empty() {
+        return Err(MapError::NoTilesTagged {
+            tag: tag.to_owned(),
+        });
+    }
+    tiles.sort();
+    Ok(tiles)
+}
+
+// builds a map from `seed`, painting the ground layer with tiles from
+// `tileset` picked by the tags in `params`. solid tiles are walls unless
+// their sidecar gives them some other type. the map id comes from the seed
+// too, so a generated area can be found again.
+pub fn generate(
+    seed: u64,
+    params: &Params,
+    tileset: &Tileset,
+    meta: &TilesetMeta,
+) -> Result<MapScreen, MapError> {
+    let (cols, rows) = (params.cols, params.rows);
+    if cols == 0 || rows == 0 {
+        return Err(MapError::EmptyMap { cols, rows });
+    }
+    let floors = tagged(meta, &params.floor_tag)?;
+    let walls = tagged(meta, &params.wall_tag)?;
+
+    let mut rng = Rng::new(seed);
+    let map_id = uuid::Uuid::from_u64_pair(rng.next_u64(), rng.next_u64());
+    let solid = match params.generator {
+        Generator::Caves { fill, steps } => caves(&mut rng, cols, rows, fill, steps),
+        Generator::Rooms {
+            min_room,
+            max_depth,
+        } => rooms(&mut rng, cols, rows, min_room, max_depth),
+        Generator::Forest { density } => forest(&mut rng, cols, rows, density),
+    };
+
+    let mut ms = MapScreen::new(rows, cols, Some(&format!("generated {}", seed)));
+    ms.map_id = map_id;
+    let tileset_id = ms.add_tileset(tileset.path.clone(), tileset.columns, tileset.rows);
+    for y in 0..rows as i32 {
+        for x in 0..cols as i32 {
+            let is_solid = solid.get(x, y);
+            let choices = if is_solid { &walls } else { &floors };
+            let tile_index = choices[rng.below(choices.len() as u32) as usize];
+            let metadata = match meta.tile_type(tile_index) {
+                Some(tile_type) => Some(tile_type),
+                None if is_solid => Some(TileType::Wall),
+                None => None,
+            };
+            ms.set(
+                0,
+                TileCoords(x, y),
+                MapTile {
+                    tileset_id,
+                    tile_index,
+                    metadata,
+                },
+            )?;
+        }
+    }
+    Ok(ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use anyhow::Result;
+
+    use super::*;
+    use crate::tileset::TileMeta;
+
+    fn tileset() -> (Tileset, TilesetMeta) {
+        let mut meta = TilesetMeta::default();
+        for (i, tag) in [(0, "floor"), (1, "floor"), (2, "wall"), (3, "tree")] {
+            meta.tiles.insert(
+                i,
+                TileMeta {
+                    tags: vec![tag.to_owned()],
+                    ..TileMeta::default()
+                },
+            );
+        }
+        let tileset = Tileset {
+            id: 0,
+            path: PathBuf::from("tiles/forest1.png"),
+            columns: 4,
+            rows: 1,
+        };
+        (tileset, meta)
+    }
+
+    fn is_wall(ms: &MapScreen, x: i32, y: i32) -> bool {
+        ms.metadata_at(TileCoords(x, y)) == Some(&TileType::Wall)
+    }
+
+    #[test]
+    fn same_seed_same_map_test() -> Result<()> {
+        let (tileset, meta) = tileset();
+        for generator in [
+            Generator::Caves {
+                fill: 0.45,
+                steps: 4,
+            },
+            Generator::Rooms {
+                min_room: 3,
+                max_depth: 3,
+            },
+            Generator::Forest { density: 0.2 },
+        ] {
+            let params = Params::new(20, 15, generator);
+            let a = generate(7, &params, &tileset, &meta)?;
+            let b = generate(7, &params, &tileset, &meta)?;
+            let c = generate(8, &params, &tileset, &meta)?;
+            assert_eq!(ron::to_string(&a)?, ron::to_string(&b)?);
+            assert_ne!(ron::to_string(&a)?, ron::to_string(&c)?);
+            assert_eq!(a.map_id, b.map_id);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn caves_test() -> Result<()> {
+        let (tileset, meta) = tileset();
+        let params = Params::new(
+            30,
+            20,
+            Generator::Caves {
+                fill: 0.45,
+                steps: 4,
+            },
+        );
+        let ms = generate(1, &params, &tileset, &meta)?;
+        // closed in all the way round
+        for x in 0..30 {
+            assert!(is_wall(&ms, x, 0) && is_wall(&ms, x, 19));
+        }
+        for y in 0..20 {
+            assert!(is_wall(&ms, 0, y) && is_wall(&ms, 29, y));
+        }
+        let open = ms.tiles().filter(|(_, t)| t.metadata.is_none()).count();
+        assert!(open > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn small_rooms_test() -> Result<()> {
+        let (tileset, meta) = tileset();
+        for (cols, rows) in [(4, 4), (2, 5), (6, 3)] {
+            let params = Params::new(
+                cols,
+                rows,
+                Generator::Rooms {
+                    min_room: 3,
+                    max_depth: 3,
+                },
+            );
+            for seed in 0..10 {
+                let ms = generate(seed, &params, &tileset, &meta)?;
+                let (cols, rows) = (cols as i32, rows as i32);
+                for x in 0..cols {
+                    assert!(is_wall(&ms, x, 0) && is_wall(&ms, x, rows - 1));
+                }
+                for y in 0..rows {
+                    assert!(is_wall(&ms, 0, y) && is_wall(&ms, cols - 1, y));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rooms_are_connected_test() -> Result<()> {
+        let (tileset, meta) = tileset();
+        let params = Params::new(
+            40,
+            30,
+            Generator::Rooms {
+                min_room: 3,
+                max_depth: 4,
+            },
+        );
+        for seed in 0..10 {
+            let ms = generate(seed, &params, &tileset, &meta)?;
+            let open: Vec<TileCoords> = ms
+                .tiles()
+                .filter(|(_, t)| t.metadata.is_none())
+                .map(|(c, _)| c)
+                .collect();
+            assert!(!open.is_empty());
+
+            // every open cell can be walked to from the first
+            let mut seen = vec![open[0]];
+            let mut todo = vec![open[0]];
+            while let Some(TileCoords(x, y)) = todo.pop() {
+                for next in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+                    let next = TileCoords(next.0, next.1);
+                    if open.contains(&next) && !seen.contains(&next) {
+                        seen.push(next);
+                        todo.push(next);
+                    }
+                }
+            }
+            assert_eq!(seen.len(), open.len(), "seed {}", seed);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn forest_test() -> Result<()> {
+        let (tileset, meta) = tileset();
+        let mut params = Params::new(20, 20, Generator::Forest { density: 0.25 });
+        params.wall_tag = "tree".to_owned();
+        let ms = generate(3, &params, &tileset, &meta)?;
+        let trees: Vec<_> = ms.tiles().filter(|(_, t)| t.tile_index == 3).collect();
+        assert!(trees.len() > 50 && trees.len() < 150);
+        assert!(trees
+            .iter()
+            .all(|(_, t)| t.metadata == Some(TileType::Wall)));
+        assert!(ms.tiles().all(|(_, t)| t.tile_index != 2));
+
+        params.floor_tag = "grass".to_owned();
+        assert!(matches!(
+            generate(3, &params, &tileset, &meta),
+            Err(MapError::NoTilesTagged { .. })
+        ));
+        params.cols = 0;
+        assert!(matches!(
+            generate(3, &params, &tileset, &meta),
+            Err(MapError::EmptyMap { .. })
+        ));
+        Ok(())
+    }
+}
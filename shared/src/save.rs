@@ -0,0 +1,64 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use bevy::ecs::system::Resource;
+use serde::{Deserialize, Serialize};
+
+use crate::error::MapError;
+use crate::fov::Bitmap;
+
+// what's kept between sessions
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Resource)]
+pub struct SaveData {
+    // the cells the hero has seen on each map, keyed by map id
+    #[serde(default)]
+    pub explored: HashMap<uuid::Uuid, Bitmap>,
+}
+
+impl SaveData {
+    pub fn new_from_file(filename: &str) -> Result<Self, MapError> {
+        let file_data = fs::read_to_string(filename).map_err(|e| MapError::io(filename, e))?;
+        ron::from_str(&file_data).map_err(|e| MapError::parse(filename, e))
+    }
+
+    // a new game if there's no save yet
+    pub fn load_or_default(filename: &str) -> Result<Self, MapError> {
+        if !Path::new(filename).exists() {
+            return Ok(SaveData::default());
+        }
+        Self::new_from_file(filename)
+    }
+
+    pub fn save_to_file(&self, filename: &str) -> Result<(), MapError> {
+        let pretty = ron::ser::PrettyConfig::new()
+            .struct_names(true)
+            .indentor("  ".to_owned());
+        let data = ron::ser::to_string_pretty(self, pretty)
+            .map_err(|e| MapError::from_load(filename, e.into()))?;
+        fs::write(filename, data).map_err(|e| MapError::io(filename, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::tilemap::TileCoords;
+
+    #[test]
+    fn save_round_trip_test() -> Result<()> {
+        let mut explored = Bitmap::new(4, 3);
+        explored.set(TileCoords(1, 2), true);
+        let save = SaveData {
+            explored: HashMap::from([(uuid::Uuid::new_v4(), explored)]),
+        };
+        let pretty = ron::ser::PrettyConfig::new().struct_names(true);
+        let data = ron::ser::to_string_pretty(&save, pretty)?;
+        assert_eq!(ron::from_str::<SaveData>(&data)?, save);
+        assert_eq!(
+            ron::from_str::<SaveData>("SaveData()")?,
+            SaveData::default()
+        );
+        Ok(())
+    }
+}
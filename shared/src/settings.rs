@@ -1,10 +1,21 @@
-use std::fs;
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Result;
 use bevy::ecs::system::Resource;
+use ron::extensions::Extensions;
 use serde::{Deserialize, Serialize};
 
+use crate::error::SettingsError;
+
+// environment variables like ADVENTURES_SCALE override the settings files
+pub const ENV_PREFIX: &str = "ADVENTURES_";
+
+// anything a settings file leaves out is filled in from `Default`
 #[derive(Debug, Serialize, Deserialize, Resource)]
+#[serde(default, deny_unknown_fields)]
 pub struct SettingsFile {
     pub scale: f32,
     pub x_max: f32,
@@ -16,11 +27,186 @@ pub struct SettingsFile {
     pub game_z: f32,
 }
 
+impl Default for SettingsFile {
+    fn default() -> Self {
+        SettingsFile {
+            scale: 2.5,
+            x_max: 24.,
+            y_max: 18.,
+            input_debounce: 0.04,
+            tile_width: 16.,
+            tile_height: 16.,
+            tile_z: 0.,
+            game_z: 1.,
+        }
+    }
+}
+
+// the settings one file or the environment sets, to go over the others
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename = "SettingsFile", deny_unknown_fields)]
+struct SettingsLayer {
+    scale: Option<f32>,
+    x_max: Option<f32>,
+    y_max: Option<f32>,
+    input_debounce: Option<f32>,
+    tile_width: Option<f32>,
+    tile_height: Option<f32>,
+    tile_z: Option<f32>,
+    game_z: Option<f32>,
+}
+
+impl SettingsLayer {
+    // with `optional`, a file that isn't there sets nothing
+    fn from_file(path: &Path, optional: bool) -> Result<Self, SettingsError> {
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if optional && e.kind() == io::ErrorKind::NotFound => {
+                return Ok(SettingsLayer::default())
+            }
+            Err(e) => {
+                return Err(SettingsError::Io {
+                    path: path.into(),
+                    source: e,
+                })
+            }
+        };
+        // written just like a whole settings file, without Some(..)
+        ron::Options::default()
+            .with_default_extension(Extensions::IMPLICIT_SOME)
+            .from_str(&data)
+            .map_err(|e| SettingsError::parse(path, &e))
+    }
+
+    fn from_env(var: &impl Fn(&str) -> Option<String>) -> Result<Self, SettingsError> {
+        let get = |field: &str| {
+            let name = format!("{}{}", ENV_PREFIX, field.to_uppercase());
+            match var(&name) {
+                Some(value) => match value.trim().parse() {
+                    Ok(v) => Ok(Some(v)),
+                    Err(_) => Err(SettingsError::Env { name, value }),
+                },
+                None => Ok(None),
+            }
+        };
+        Ok(SettingsLayer {
+            scale: get("scale")?,
+            x_max: get("x_max")?,
+            y_max: get("y_max")?,
+            input_debounce: get("input_debounce")?,
+            tile_width: get("tile_width")?,
+            tile_height: get("tile_height")?,
+            tile_z: get("tile_z")?,
+            game_z: get("game_z")?,
+        })
+    }
+}
+
+// where a player's own settings go, under the xdg config dir
+fn user_file(var: &impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    let config_dir = var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| var("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_dir.join("adventures").join("settings.ron"))
+}
+
 impl SettingsFile {
-    pub fn new_from_file(filename: &str) -> Result<Self> {
-        let data = fs::read_to_string(filename)?;
-        let s = ron::from_str(&data)?;
-        Ok(s)
+    // just the one file, over the defaults
+    pub fn new_from_file(filename: &str) -> Result<Self, SettingsError> {
+        let data = fs::read_to_string(filename).map_err(|e| SettingsError::Io {
+            path: filename.into(),
+            source: e,
+        })?;
+        let sf: SettingsFile =
+            ron::from_str(&data).map_err(|e| SettingsError::parse(filename, &e))?;
+        sf.validate()?;
+        Ok(sf)
+    }
+
+    // the defaults, then the project's settings file, then the player's
+    // own, then environment variables. only the player's file can be
+    // missing.
+    pub fn load(project_file: &str) -> Result<Self, SettingsError> {
+        Self::load_with(Path::new(project_file), &|name| env::var(name).ok())
+    }
+
+    fn load_with(
+        project_file: &Path,
+        var: &impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, SettingsError> {
+        let mut sf = SettingsFile::default();
+        sf.apply(SettingsLayer::from_file(project_file, false)?);
+        if let Some(path) = user_file(var) {
+            sf.apply(SettingsLayer::from_file(&path, true)?);
+        }
+        sf.apply(SettingsLayer::from_env(var)?);
+        sf.validate()?;
+        Ok(sf)
+    }
+
+    fn apply(&mut self, layer: SettingsLayer) {
+        let SettingsLayer {
+            scale,
+            x_max,
+            y_max,
+            input_debounce,
+            tile_width,
+            tile_height,
+            tile_z,
+            game_z,
+        } = layer;
+        self.scale = scale.unwrap_or(self.scale);
+        self.x_max = x_max.unwrap_or(self.x_max);
+        self.y_max = y_max.unwrap_or(self.y_max);
+        self.input_debounce = input_debounce.unwrap_or(self.input_debounce);
+        self.tile_width = tile_width.unwrap_or(self.tile_width);
+        self.tile_height = tile_height.unwrap_or(self.tile_height);
+        self.tile_z = tile_z.unwrap_or(self.tile_z);
+        self.game_z = game_z.unwrap_or(self.game_z);
+    }
+
+    // the first setting that can't work
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let invalid = |field, message: String| Err(SettingsError::Invalid { field, message });
+        for (field, value) in [
+            ("scale", self.scale),
+            ("tile_width", self.tile_width),
+            ("tile_height", self.tile_height),
+        ] {
+            if value <= 0. || !value.is_finite() {
+                return invalid(field, format!("is {}, but has to be more than 0", value));
+            }
+        }
+        for (field, value) in [("x_max", self.x_max), ("y_max", self.y_max)] {
+            if value < 1. || !value.is_finite() || value.fract() != 0. {
+                return invalid(
+                    field,
+                    format!("is {}, but has to be a whole number of tiles", value),
+                );
+            }
+        }
+        if self.input_debounce < 0. || !self.input_debounce.is_finite() {
+            return invalid(
+                "input_debounce",
+                format!("is {}, but can't be less than 0", self.input_debounce),
+            );
+        }
+        for (field, value) in [("tile_z", self.tile_z), ("game_z", self.game_z)] {
+            if !value.is_finite() {
+                return invalid(field, format!("is {}, but has to be a number", value));
+            }
+        }
+        if self.game_z <= self.tile_z {
+            return invalid(
+                "game_z",
+                format!(
+                    "is {}, so it would be drawn under the tiles at {}",
+                    self.game_z, self.tile_z
+                ),
+            );
+        }
+        Ok(())
     }
 }
 
@@ -139,6 +325,8 @@ impl GameSettings {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use super::*;
 
     #[test]
@@ -196,4 +384,141 @@ mod tests {
         assert_eq!(gs.game_area_y_min, -165., "game_area_y_min");
         Ok(())
     }
+
+    #[test]
+    fn new_from_file_test() -> Result<()> {
+        let missing = SettingsFile::new_from_file("nowhere.ron");
+        assert!(matches!(missing, Err(SettingsError::Io { .. })));
+
+        let path = std::env::temp_dir().join(format!("settings-{}.ron", std::process::id()));
+        std::fs::write(&path, "SettingsFile(\n  scale: 1.0,\n  x_max: oops,\n)")?;
+        let broken = SettingsFile::new_from_file(&path.to_string_lossy());
+        std::fs::remove_file(&path)?;
+        assert!(matches!(broken, Err(SettingsError::Parse { line: 3, .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn defaults_test() -> Result<()> {
+        // the game's settings leave out the z values
+        let sf: SettingsFile = ron::from_str(include_str!("../../game/settings.ron"))?;
+        assert_eq!((sf.tile_z, sf.game_z), (0., 1.));
+        assert_eq!(sf.scale, 2.5);
+        sf.validate()?;
+        Ok(())
+    }
+
+    #[test]
+    fn validate_test() -> Result<()> {
+        let check = |sf: SettingsFile| sf.validate().map_err(|e| e.to_string());
+        assert_eq!(check(SettingsFile::default()), Ok(()));
+        assert_eq!(
+            check(SettingsFile {
+                scale: -1.,
+                ..Default::default()
+            }),
+            Err("scale is -1, but has to be more than 0".to_owned())
+        );
+        assert!(check(SettingsFile {
+            tile_width: 0.,
+            ..Default::default()
+        })
+        .is_err());
+        assert!(check(SettingsFile {
+            x_max: 2.5,
+            ..Default::default()
+        })
+        .is_err());
+        assert!(check(SettingsFile {
+            input_debounce: f32::NAN,
+            ..Default::default()
+        })
+        .is_err());
+        assert!(check(SettingsFile {
+            game_z: 0.,
+            ..Default::default()
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn layers_test() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("settings-layers-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("config/adventures"))?;
+        let project = dir.join("settings.ron");
+        std::fs::write(
+            &project,
+            "SettingsFile(scale: 3.0, x_max: 20.0, y_max: 10.0)",
+        )?;
+        std::fs::write(
+            dir.join("config/adventures/settings.ron"),
+            "SettingsFile(x_max: 30.0)",
+        )?;
+
+        let config = dir.join("config").to_string_lossy().into_owned();
+        let mut vars = HashMap::from([
+            ("XDG_CONFIG_HOME".to_owned(), config),
+            ("ADVENTURES_Y_MAX".to_owned(), " 12 ".to_owned()),
+        ]);
+        let sf = SettingsFile::load_with(&project, &|name| vars.get(name).cloned())?;
+        assert_eq!(sf.scale, 3.);
+        assert_eq!(sf.x_max, 30.);
+        assert_eq!(sf.y_max, 12.);
+        assert_eq!(sf.tile_width, 16.);
+
+        // no player file, so just the project's and the environment
+        let sf = SettingsFile::load_with(&project, &|name| {
+            (name == "ADVENTURES_SCALE").then(|| "1.5".to_owned())
+        })?;
+        assert_eq!((sf.scale, sf.x_max, sf.y_max), (1.5, 20., 10.));
+
+        // the project's file has to be there, and spelled right
+        let missing = SettingsFile::load_with(&dir.join("nowhere.ron"), &|_| None);
+        assert!(matches!(missing, Err(SettingsError::Io { .. })));
+        std::fs::write(&project, "SettingsFile(scael: 2.0)")?;
+        let misspelled = SettingsFile::load_with(&project, &|_| None);
+        assert!(matches!(misspelled, Err(SettingsError::Parse { .. })));
+        std::fs::write(
+            &project,
+            "SettingsFile(scale: 3.0, x_max: 20.0, y_max: 10.0)",
+        )?;
+
+        vars.insert("ADVENTURES_SCALE".to_owned(), "big".to_owned());
+        let bad = SettingsFile::load_with(&project, &|name| vars.get(name).cloned());
+        assert!(matches!(bad, Err(SettingsError::Env { .. })));
+        vars.insert("ADVENTURES_SCALE".to_owned(), "0".to_owned());
+        let bad = SettingsFile::load_with(&project, &|name| vars.get(name).cloned());
+        assert!(matches!(
+            bad,
+            Err(SettingsError::Invalid { field: "scale", .. })
+        ));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn user_file_test() {
+        let home = |name: &str| (name == "HOME").then(|| "/home/harry".to_owned());
+        let xdg = |dir: &'static str| {
+            move |name: &str| match name {
+                "XDG_CONFIG_HOME" => Some(dir.to_owned()),
+                other => home(other),
+            }
+        };
+        assert_eq!(
+            user_file(&xdg("/etc/xdg")),
+            Some(PathBuf::from("/etc/xdg/adventures/settings.ron"))
+        );
+        // relative xdg dirs are ignored, as the spec says
+        assert_eq!(
+            user_file(&xdg("config")),
+            Some(PathBuf::from("/home/harry/.config/adventures/settings.ron"))
+        );
+        assert_eq!(
+            user_file(&home),
+            Some(PathBuf::from("/home/harry/.config/adventures/settings.ron"))
+        );
+        assert_eq!(user_file(&|_: &str| None), None);
+    }
 }
@@ -1,6 +1,10 @@
-use std::{fmt::Display, fs, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
 
-use anyhow::{anyhow, Result};
 use bevy::{
     math::{ivec3, vec2},
     prelude::*,
@@ -8,11 +12,28 @@ use bevy::{
 use bevy_simple_tilemap::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::components::Wall;
+use crate::components::{Door, Enemy, Item, Npc};
+use crate::error::MapError;
+use crate::lighting::{LightSource, FULL_LIGHT};
 use crate::settings::GameSettings;
+use crate::tileset::{AnimationFrame, TilesetMeta};
+use crate::world::Edge;
+
+mod asset;
+mod grid;
+pub mod ldtk;
+mod migrate;
+mod paths;
+pub mod tiled;
+mod validate;
 
-#[derive(Debug, PartialEq)]
-pub struct TileCoords(i32, i32);
+pub use asset::{MapAssetPlugin, MapLoader};
+pub use grid::{MapTile, TileGrid};
+pub use migrate::{detect_version, CURRENT_FORMAT_VERSION};
+pub use validate::{validate_file, Diagnostic, Severity};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileCoords(pub i32, pub i32);
 
 impl Display for TileCoords {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -37,6 +58,19 @@ pub fn screen_pos_to_coord(coords: Vec3, settings: &GameSettings) -> TileCoords
     TileCoords(new_x.floor() as i32, new_y.floor() as i32)
 }
 
+// the tile whose centre is closest to `coords`. sprites sit on the centre
+// of their tile, so this doesn't flip to the next tile over when float
+// error lands a position just short of it.
+pub fn nearest_coord(coords: Vec3, settings: &GameSettings) -> TileCoords {
+    let tile_x = settings.tile_width * settings.scale;
+    let tile_y = settings.tile_height * settings.scale;
+
+    let new_x = (coords.x - settings.game_area_x_transform) / tile_x;
+    let new_y = (coords.y - settings.game_area_y_transform) / tile_y;
+
+    TileCoords(new_x.round() as i32, new_y.round() as i32)
+}
+
 pub fn top_left_to_coord(coords: Vec3, settings: &GameSettings) -> TileCoords {
     let y = (coords.y - settings.viewport_height).abs();
     let x = coords.x - settings.left_margin;
@@ -50,140 +84,726 @@ pub fn top_left_to_coord(coords: Vec3, settings: &GameSettings) -> TileCoords {
     TileCoords(new_x as i32, new_y as i32)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Asset, TypePath)]
 pub struct MapScreen {
+    pub format_version: u32,
     pub map_name: String,
     pub map_id: uuid::Uuid,
-    pub tile_map: Option<PathBuf>,
+    pub tilesets: Vec<Tileset>,
+    // size of the map in tiles
     pub tile_rows: u32,
     pub tile_cols: u32,
-    pub tile_data: Vec<TileDesc>,
+    #[serde(default)]
+    pub neighbors: Neighbors,
+    // only what the hero can see, or has seen, is drawn
+    #[serde(default)]
+    pub fog_of_war: bool,
+    // how bright the map is away from any light, from 0 for pitch dark
+    #[serde(default = "full_light")]
+    pub ambient_light: f32,
+    #[serde(default)]
+    pub lights: Vec<LightSource>,
+    // drawn in order, so the first layer is the ground
+    pub layers: Vec<TileLayer>,
+    // made by `replace_tile` and `replace_metadata`, waiting to be sent on
+    #[serde(skip)]
+    pub(crate) changes: Vec<TileChanged>,
+    // held while the map is loaded as an asset, so they load along with it
+    #[serde(skip)]
+    #[dependency]
+    pub tileset_images: Vec<Handle<Image>>,
+    // the sidecars for each tileset, keyed by tileset id, when the map was
+    // loaded as an asset. a changed sidecar reloads the map.
+    #[serde(skip)]
+    pub tileset_metas: HashMap<u32, TilesetMeta>,
+    // whatever had to give when the map was upgraded from an older layout,
+    // reported by `validate`
+    #[serde(skip)]
+    pub upgrade_warnings: Vec<Diagnostic>,
+}
+
+// a cell of a map that was changed while it was being played, so whatever
+// was spawned from it can catch up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Event)]
+pub struct TileChanged {
+    pub layer: usize,
+    pub coords: TileCoords,
+}
+
+// the maps the hero scrolls to when walking off each edge of the screen
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
+pub struct Neighbors {
+    pub north: Option<uuid::Uuid>,
+    pub south: Option<uuid::Uuid>,
+    pub east: Option<uuid::Uuid>,
+    pub west: Option<uuid::Uuid>,
+}
+
+impl Neighbors {
+    pub fn get(&self, edge: Edge) -> Option<uuid::Uuid> {
+        match edge {
+            Edge::North => self.north,
+            Edge::South => self.south,
+            Edge::East => self.east,
+            Edge::West => self.west,
+        }
+    }
+}
+
+fn full_light() -> f32 {
+    FULL_LIGHT
+}
+
+pub const DEFAULT_MAP_ROWS: u32 = 18;
+pub const DEFAULT_MAP_COLS: u32 = 24;
+// drawn in place of tiles from a tileset whose image is missing
+pub const PLACEHOLDER_TILE: &str = "tiles/no-tile.png";
+
+// a sprite sheet the map draws from. tiles refer to it by `id` rather than
+// by position, so removing a tileset doesn't renumber the others.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Tileset {
+    pub id: u32,
+    pub path: PathBuf,
+    // size of the sheet in tiles
+    pub columns: u32,
+    pub rows: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TileLayer {
+    pub name: String,
+    // overhead layers are drawn above the hero, everything else below
+    pub overhead: bool,
+    pub visible: bool,
+    pub opacity: f32,
+    #[serde(rename = "tile_data")]
+    pub tiles: TileGrid,
+}
+
+impl TileLayer {
+    pub fn new(name: &str, overhead: bool, cols: u32, rows: u32) -> Self {
+        TileLayer {
+            name: name.to_owned(),
+            overhead,
+            visible: true,
+            opacity: 1.0,
+            tiles: TileGrid::new(cols, rows),
+        }
+    }
+
+    pub fn tilemapdata_from_struct(&self, tileset_id: u32) -> Vec<(IVec3, Option<Tile>)> {
+        self.tiles
+            .iter()
+            .filter(|(_, t)| t.tileset_id == tileset_id)
+            .map(|(coords, t)| (ivec3(coords.0, coords.1, 0), Some(self.tile_for(t))))
+            .collect()
+    }
+
+    // how `tile` is drawn on this layer
+    pub fn tile_for(&self, tile: &MapTile) -> Tile {
+        Tile {
+            sprite_index: tile.tile_index,
+            color: Color::rgba(1.0, 1.0, 1.0, self.opacity),
+            ..default()
+        }
+    }
+}
+
+// a tile as it was spawned into a tilemap, so it can be changed later
+// without going back to the map
+#[derive(Debug, Clone)]
+pub struct PlacedTile {
+    pub pos: IVec3,
+    // what's showing now
+    pub tile: Tile,
+    // the colour it was placed with, before any shading
+    pub color: Color,
+    // empty for tiles that don't animate
+    pub frames: Vec<AnimationFrame>,
+}
+
+// every tile in a spawned tilemap, which holds one tileset's tiles from one
+// layer of the map. sits on the same entity as the tilemap.
+#[derive(Debug, Clone, Default, Component)]
+pub struct LayerTiles {
+    pub layer: usize,
+    pub tileset_id: u32,
+    pub tiles: Vec<PlacedTile>,
+    // the tileset's image is missing, so everything is the placeholder tile
+    pub placeholder: bool,
+}
+
+impl LayerTiles {
+    pub fn from_tiles(
+        layer: usize,
+        tileset_id: u32,
+        tiles: &[(IVec3, Option<Tile>)],
+        meta: &TilesetMeta,
+    ) -> Self {
+        LayerTiles {
+            layer,
+            tileset_id,
+            placeholder: false,
+            tiles: tiles
+                .iter()
+                .filter_map(|(pos, tile)| {
+                    let tile = (*tile)?;
+                    Some(PlacedTile {
+                        pos: *pos,
+                        tile,
+                        color: tile.color,
+                        frames: meta.animation(tile.sprite_index).to_vec(),
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    // draws every tile as the only one the placeholder has
+    pub fn use_placeholder(&mut self) {
+        self.placeholder = true;
+        for placed in self.tiles.iter_mut() {
+            placed.tile.sprite_index = 0;
+            placed.frames.clear();
+        }
+    }
+
+    // puts `tile` in the cell, or takes away whatever was there. it goes
+    // in unshaded, so anything tinting the layer needs to run again.
+    pub fn replace(
+        &mut self,
+        tilemap: &mut TileMap,
+        coords: TileCoords,
+        tile: Option<Tile>,
+        meta: &TilesetMeta,
+    ) {
+        let pos = ivec3(coords.0, coords.1, 0);
+        self.tiles.retain(|p| p.pos != pos);
+        let tile = tile.map(|tile| Tile {
+            sprite_index: if self.placeholder {
+                0
+            } else {
+                tile.sprite_index
+            },
+            ..tile
+        });
+        if let Some(tile) = tile {
+            self.tiles.push(PlacedTile {
+                pos,
+                tile,
+                color: tile.color,
+                frames: if self.placeholder {
+                    vec![]
+                } else {
+                    meta.animation(tile.sprite_index).to_vec()
+                },
+            });
+        }
+        tilemap.set_tile(pos, tile);
+    }
+
+    // shades each tile by multiplying the colour it was placed with by
+    // `tint`. only tiles whose colour changes are touched.
+    pub fn tint(&mut self, tilemap: &mut TileMap, tint: impl Fn(TileCoords) -> Color) {
+        for placed in self.tiles.iter_mut() {
+            let t = tint(TileCoords(placed.pos.x, placed.pos.y));
+            let c = placed.color;
+            let color = Color::rgba(c.r() * t.r(), c.g() * t.g(), c.b() * t.b(), c.a() * t.a());
+            if color != placed.tile.color {
+                placed.tile.color = color;
+                tilemap.set_tile(placed.pos, Some(placed.tile));
+            }
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub enum TileType {
     Wall,
-    Door,
-    Item,
-    Enemy,
-    NPC,
+    // where the hero ends up after walking through
+    Door {
+        target_map: uuid::Uuid,
+        target: TileCoords,
+    },
+    Item {
+        item_id: String,
+    },
+    Enemy {
+        kind: String,
+        level: u32,
+    },
+    NPC {
+        npc_id: String,
+    },
 }
 
 impl Default for MapScreen {
     fn default() -> Self {
         MapScreen {
-            tile_rows: 24,
-            tile_cols: 18,
+            format_version: CURRENT_FORMAT_VERSION,
+            tile_rows: DEFAULT_MAP_ROWS,
+            tile_cols: DEFAULT_MAP_COLS,
             map_name: String::default(),
             map_id: uuid::Uuid::default(),
-            tile_map: None,
-            tile_data: vec![],
+            tilesets: vec![],
+            neighbors: Neighbors::default(),
+            fog_of_war: false,
+            ambient_light: FULL_LIGHT,
+            lights: vec![],
+            changes: vec![],
+            tileset_images: vec![],
+            tileset_metas: HashMap::new(),
+            upgrade_warnings: vec![],
+            layers: vec![TileLayer::new(
+                "ground",
+                false,
+                DEFAULT_MAP_COLS,
+                DEFAULT_MAP_ROWS,
+            )],
         }
     }
 }
 
 impl MapScreen {
-    pub fn new(rows: u32, cols: u32, map_name: Option<&str>, filename: Option<&str>) -> Self {
+    pub fn new(rows: u32, cols: u32, map_name: Option<&str>) -> Self {
         MapScreen {
+            format_version: CURRENT_FORMAT_VERSION,
             map_name: map_name.unwrap_or_default().to_owned(),
             map_id: uuid::Uuid::new_v4(),
-            tile_map: Some(filename.unwrap_or_default().to_owned().into()),
+            tilesets: vec![],
             tile_rows: rows,
             tile_cols: cols,
-            tile_data: vec![],
+            neighbors: Neighbors::default(),
+            fog_of_war: false,
+            ambient_light: FULL_LIGHT,
+            lights: vec![],
+            changes: vec![],
+            tileset_images: vec![],
+            tileset_metas: HashMap::new(),
+            upgrade_warnings: vec![],
+            layers: vec![TileLayer::new("ground", false, cols, rows)],
         }
     }
 
-    pub fn new_from_file(filename: &str) -> Result<Self> {
-        let file_data = fs::read_to_string(filename)?;
-        match ron::from_str(&file_data) {
-            Ok(ms) => Ok(ms),
-            Err(e) => Err(anyhow!("{}, {:?}", filename, e)),
+    // new layers go on top
+    pub fn add_layer(&mut self, name: &str, overhead: bool) -> usize {
+        self.layers.push(TileLayer::new(
+            name,
+            overhead,
+            self.tile_cols,
+            self.tile_rows,
+        ));
+        self.layers.len() - 1
+    }
+
+    // grows or shrinks every layer. fails, leaving the map untouched, if
+    // any tiles would end up outside it.
+    pub fn resize(&mut self, cols: u32, rows: u32) -> Result<(), MapError> {
+        let mut layers = Vec::with_capacity(self.layers.len());
+        for layer in &self.layers {
+            let mut tiles = layer.tiles.clone();
+            tiles.resize(cols, rows)?;
+            layers.push(tiles);
+        }
+        for (layer, tiles) in self.layers.iter_mut().zip(layers) {
+            layer.tiles = tiles;
         }
+        self.tile_cols = cols;
+        self.tile_rows = rows;
+        Ok(())
     }
 
-    pub fn tilemapdata_from_struct(&self, tile_z: f32) -> Vec<(IVec3, Option<Tile>)> {
-        self.tile_data
+    // returns the id new tiles should use to refer to the sheet
+    pub fn add_tileset(&mut self, path: PathBuf, columns: u32, rows: u32) -> u32 {
+        let id = self.tilesets.iter().map(|ts| ts.id + 1).max().unwrap_or(0);
+        self.tilesets.push(Tileset {
+            id,
+            path,
+            columns,
+            rows,
+        });
+        id
+    }
+
+    // the sidecar for every tileset the map uses, keyed by tileset id.
+    // tileset paths are relative to `assets_root`.
+    pub fn load_tileset_metas(
+        &self,
+        assets_root: &Path,
+    ) -> Result<HashMap<u32, TilesetMeta>, MapError> {
+        self.tilesets
             .iter()
-            .map(|t| {
-                // let sprite_index = t.tile_source.1 + (t.tile_source.0 * self.tile_rows);
-                let tile = Tile {
-                    sprite_index: t.tile_index,
-                    ..default()
-                };
-                let v3 = ivec3(t.x, t.y, tile_z.floor() as i32);
-                (v3, Some(tile))
-            })
+            .map(|ts| Ok((ts.id, TilesetMeta::load_for(&ts.path, assets_root)?)))
             .collect()
     }
 
-    pub fn get_wallmap(&self, settings: &GameSettings) -> Vec<(SpatialBundle, Wall)> {
-        self.tile_data
+    pub fn get_tileset(&self, id: u32) -> Option<&Tileset> {
+        self.tilesets.iter().find(|ts| ts.id == id)
+    }
+
+    pub fn new_from_file(filename: &str) -> Result<Self, MapError> {
+        Self::load_from_file(filename, false)
+    }
+
+    // older map layouts are upgraded on load. with `rewrite` set, a map that
+    // needed upgrading is saved back over the original file.
+    pub fn load_from_file(filename: &str, rewrite: bool) -> Result<Self, MapError> {
+        let file_data = fs::read_to_string(filename).map_err(|e| MapError::io(filename, e))?;
+        let (ms, from_version) =
+            migrate::upgrade(&file_data).map_err(|e| MapError::from_load(filename, e))?;
+        if rewrite && from_version != CURRENT_FORMAT_VERSION {
+            ms.save_to_file(filename)?;
+        }
+        Ok(ms)
+    }
+
+    pub fn save_to_file(&self, filename: &str) -> Result<(), MapError> {
+        let pretty = ron::ser::PrettyConfig::new()
+            .struct_names(true)
+            .indentor("  ".to_owned());
+        let data = ron::ser::to_string_pretty(self, pretty)
+            .map_err(|e| MapError::from_load(filename, e.into()))?;
+        fs::write(filename, data).map_err(|e| MapError::io(filename, e))
+    }
+
+    // every placed tile on every layer, bottom layer first
+    pub fn tiles(&self) -> impl Iterator<Item = (TileCoords, &MapTile)> {
+        self.layers.iter().flat_map(|l| l.tiles.iter())
+    }
+
+    pub fn contains(&self, coords: TileCoords) -> bool {
+        let TileCoords(x, y) = coords;
+        x >= 0 && y >= 0 && (x as u32) < self.tile_cols && (y as u32) < self.tile_rows
+    }
+
+    pub fn get(&self, layer: usize, coords: TileCoords) -> Option<&MapTile> {
+        self.layers.get(layer).and_then(|l| l.tiles.get(coords))
+    }
+
+    // returns whatever was in the cell before
+    pub fn set(
+        &mut self,
+        layer: usize,
+        coords: TileCoords,
+        tile: MapTile,
+    ) -> Result<Option<MapTile>, MapError> {
+        let l = self
+            .layers
+            .get_mut(layer)
+            .ok_or(MapError::NoLayer { layer })?;
+        l.tiles.set(coords, tile)
+    }
+
+    pub fn clear(&mut self, layer: usize, coords: TileCoords) -> Option<MapTile> {
+        self.layers
+            .get_mut(layer)
+            .and_then(|l| l.tiles.clear(coords))
+    }
+
+    pub fn iter_region(
+        &self,
+        layer: usize,
+        from: TileCoords,
+        to: TileCoords,
+    ) -> impl Iterator<Item = (TileCoords, &MapTile)> {
+        self.layers
+            .get(layer)
+            .into_iter()
+            .flat_map(move |l| l.tiles.iter_region(from, to))
+    }
+
+    // named so it doesn't read like the `neighbors` field, which is about
+    // other maps
+    pub fn neighbors_of(
+        &self,
+        coords: TileCoords,
+        diagonal: bool,
+    ) -> impl Iterator<Item = TileCoords> {
+        grid::neighbors_within(self.tile_cols, self.tile_rows, coords, diagonal)
+    }
+
+    // for changing the map while it's being played. unlike `set` and
+    // `clear`, each change is kept until `take_changes`, so the tilemap and
+    // collision can be brought up to date. returns whatever was in the
+    // cell before.
+    pub fn replace_tile(
+        &mut self,
+        layer: usize,
+        coords: TileCoords,
+        tile: Option<MapTile>,
+    ) -> Result<Option<MapTile>, MapError> {
+        if layer >= self.layers.len() {
+            return Err(MapError::NoLayer { layer });
+        }
+        if !self.contains(coords) {
+            return Err(MapError::OutOfBounds {
+                coords,
+                cols: self.tile_cols,
+                rows: self.tile_rows,
+            });
+        }
+        let before = match &tile {
+            Some(tile) => self.set(layer, coords, tile.clone())?,
+            None => self.clear(layer, coords),
+        };
+        if before != tile {
+            self.changes.push(TileChanged { layer, coords });
+        }
+        Ok(before)
+    }
+
+    // replaces the metadata that counts at `coords`, which is on the topmost
+    // tile that has any, or the topmost tile if none do. this is how doors
+    // open and bushes get cut down.
+    pub fn replace_metadata(
+        &mut self,
+        coords: TileCoords,
+        metadata: Option<TileType>,
+    ) -> Result<Option<TileType>, MapError> {
+        let topmost = |with_metadata: bool| {
+            (0..self.layers.len()).rev().find_map(|l| {
+                let tile = self.get(l, coords)?;
+                (!with_metadata || tile.metadata.is_some()).then(|| (l, tile.clone()))
+            })
+        };
+        let (layer, mut tile) = topmost(true)
+            .or_else(|| topmost(false))
+            .ok_or(MapError::NoTile { coords })?;
+        let before = std::mem::replace(&mut tile.metadata, metadata);
+        self.replace_tile(layer, coords, Some(tile))?;
+        Ok(before)
+    }
+
+    // every change made by `replace_tile` and `replace_metadata` since the
+    // last call, oldest first
+    pub fn take_changes(&mut self) -> Vec<TileChanged> {
+        std::mem::take(&mut self.changes)
+    }
+
+    // metadata on the topmost layer that has any at `coords`
+    pub fn metadata_at(&self, coords: TileCoords) -> Option<&TileType> {
+        self.layers
             .iter()
-            .filter(|t| t.metadata == Some(TileType::Wall))
-            .map(|t| {
-                let pos = coord_to_screen_pos(t.x, t.y, settings.game_z, settings);
-                (
-                    SpatialBundle {
-                        transform: Transform {
-                            translation: pos,
-                            scale: Vec3::splat(settings.scale),
-                            ..default()
-                        },
-                        ..default()
+            .rev()
+            .find_map(|l| l.tiles.get(coords).and_then(|t| t.metadata.as_ref()))
+    }
+
+    // layers under the hero are spread between `tile_z` and `game_z`, with
+    // the ground at `tile_z`. overhead layers stack above `game_z` using
+    // the same spacing.
+    pub fn layer_z(&self, index: usize, settings: &GameSettings) -> f32 {
+        let below = self.layers.iter().filter(|l| !l.overhead).count();
+        let step = (settings.game_z - settings.tile_z) / (below + 1) as f32;
+        let overhead = self.layers[index].overhead;
+        let position = self.layers[..index]
+            .iter()
+            .filter(|l| l.overhead == overhead)
+            .count();
+        if overhead {
+            settings.game_z + step * (position + 1) as f32
+        } else {
+            settings.tile_z + step * position as f32
+        }
+    }
+
+    pub fn door_at(&self, coords: TileCoords) -> Option<(uuid::Uuid, TileCoords)> {
+        self.layers
+            .iter()
+            .filter_map(|l| l.tiles.get(coords))
+            .find_map(|t| match &t.metadata {
+                Some(TileType::Door { target_map, target }) => Some((*target_map, *target)),
+                _ => None,
+            })
+    }
+
+    fn spatial_bundle_at(coords: TileCoords, settings: &GameSettings) -> SpatialBundle {
+        let pos = coord_to_screen_pos(coords.0, coords.1, settings.game_z, settings);
+        SpatialBundle {
+            transform: Transform {
+                translation: pos,
+                scale: Vec3::splat(settings.scale),
+                ..default()
+            },
+            ..default()
+        }
+    }
+
+    pub fn get_doormap(&self, settings: &GameSettings) -> Vec<(SpatialBundle, Door)> {
+        self.tiles()
+            .filter_map(|(coords, t)| match &t.metadata {
+                Some(TileType::Door { target_map, target }) => Some((
+                    Self::spatial_bundle_at(coords, settings),
+                    Door {
+                        target_map: *target_map,
+                        target: *target,
+                    },
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn get_itemmap(&self, settings: &GameSettings) -> Vec<(SpatialBundle, Item)> {
+        self.tiles()
+            .filter_map(|(coords, t)| match &t.metadata {
+                Some(TileType::Item { item_id }) => Some((
+                    Self::spatial_bundle_at(coords, settings),
+                    Item {
+                        item_id: item_id.clone(),
+                    },
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn get_enemymap(&self, settings: &GameSettings) -> Vec<(SpatialBundle, Enemy)> {
+        self.tiles()
+            .filter_map(|(coords, t)| match &t.metadata {
+                Some(TileType::Enemy { kind, level }) => Some((
+                    Self::spatial_bundle_at(coords, settings),
+                    Enemy {
+                        kind: kind.clone(),
+                        level: *level,
+                    },
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn get_npcmap(&self, settings: &GameSettings) -> Vec<(SpatialBundle, Npc)> {
+        self.tiles()
+            .filter_map(|(coords, t)| match &t.metadata {
+                Some(TileType::NPC { npc_id }) => Some((
+                    Self::spatial_bundle_at(coords, settings),
+                    Npc {
+                        npc_id: npc_id.clone(),
                     },
-                    Wall,
-                )
+                )),
+                _ => None,
             })
             .collect()
     }
 
+    // one tilemap per tileset used in each layer, bottom to top, with the
+    // tiles that went into it. hidden layers are still spawned so they can
+    // be toggled on later. a tileset whose image is missing is drawn with
+    // the placeholder tile instead, and tiles that aren't in their tileset
+    // are left out. both are handed back so they can be shown.
     pub fn get_tilemap(
         &self,
         settings: &GameSettings,
+        metas: &HashMap<u32, TilesetMeta>,
+        assets_root: &Path,
         asset_server: &Res<AssetServer>,
-        mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-    ) -> TileMapBundle {
-        let tm = match &self.tile_map {
-            Some(tm) => tm.clone(),
-            None => {
-                println!("Tile map did not exist!");
-                std::process::exit(1)
-            }
-        };
-        let texture_handle = asset_server.load(tm);
-        let texture_atlas = TextureAtlas::from_grid(
-            texture_handle,
-            vec2(settings.tile_width, settings.tile_height),
-            self.tile_cols as usize,
-            self.tile_rows as usize,
-            None,
-            None,
-        );
-        let texture_atlas_handle = texture_atlases.add(texture_atlas);
+        texture_atlases: &mut Assets<TextureAtlas>,
+    ) -> (Vec<(TileMapBundle, LayerTiles)>, Vec<MapError>) {
+        let mut problems = vec![];
+        let tile_size = (settings.tile_width as u32, settings.tile_height as u32);
 
-        let mut tilemap = TileMap::default();
-        tilemap.set_tiles(self.tilemapdata_from_struct(settings.tile_z));
+        let mut missing = HashSet::new();
+        let atlases: Vec<(u32, Handle<TextureAtlas>)> = self
+            .tilesets
+            .iter()
+            .map(|ts| {
+                let texture_atlas = if assets_root.join(&ts.path).exists() {
+                    let meta = metas.get(&ts.id).cloned().unwrap_or_default();
+                    let (tile_width, tile_height) = meta.tile_size_or(tile_size);
+                    TextureAtlas::from_grid(
+                        asset_server.load(ts.path.clone()),
+                        vec2(tile_width as f32, tile_height as f32),
+                        ts.columns as usize,
+                        ts.rows as usize,
+                        Some(Vec2::splat(meta.spacing as f32)),
+                        Some(Vec2::splat(meta.margin as f32)),
+                    )
+                } else {
+                    problems.push(MapError::MissingTileset {
+                        path: ts.path.clone(),
+                    });
+                    missing.insert(ts.id);
+                    TextureAtlas::from_grid(
+                        asset_server.load(PLACEHOLDER_TILE),
+                        vec2(tile_size.0 as f32, tile_size.1 as f32),
+                        1,
+                        1,
+                        None,
+                        None,
+                    )
+                };
+                (ts.id, texture_atlases.add(texture_atlas))
+            })
+            .collect();
 
-        TileMapBundle {
-            tilemap,
-            texture_atlas: texture_atlas_handle.clone(),
-            transform: Transform {
-                translation: Vec3::new(
-                    settings.game_area_x_transform,
-                    settings.game_area_y_transform,
-                    0.0,
-                ),
-                scale: Vec3::splat(settings.scale),
-                ..default()
-            },
-            ..default()
+        // every layer gets a tilemap for every tileset, even if it starts
+        // out empty, so any tile can be put anywhere later
+        let default_meta = TilesetMeta::default();
+        let mut bundles = vec![];
+        for (i, layer) in self.layers.iter().enumerate() {
+            for ts in &self.tilesets {
+                let Some((_, atlas)) = atlases.iter().find(|(id, _)| *id == ts.id) else {
+                    continue;
+                };
+                let placeholder = missing.contains(&ts.id);
+                let tile_count = ts.columns * ts.rows;
+                let mut tiles = layer.tilemapdata_from_struct(ts.id);
+                tiles.retain(|(pos, tile)| match tile {
+                    Some(tile) if tile.sprite_index >= tile_count => {
+                        problems.push(MapError::TileOutOfRange {
+                            layer: i,
+                            coords: TileCoords(pos.x, pos.y),
+                            path: ts.path.clone(),
+                            tile_index: tile.sprite_index,
+                            tile_count,
+                        });
+                        false
+                    }
+                    _ => true,
+                });
+                let meta = if placeholder {
+                    &default_meta
+                } else {
+                    metas.get(&ts.id).unwrap_or(&default_meta)
+                };
+                let mut placed = LayerTiles::from_tiles(i, ts.id, &tiles, meta);
+                if placeholder {
+                    placed.use_placeholder();
+                }
+                let mut tilemap = TileMap::default();
+                tilemap.set_tiles(placed.tiles.iter().map(|p| (p.pos, Some(p.tile))));
+
+                let bundle = TileMapBundle {
+                    tilemap,
+                    texture_atlas: atlas.clone(),
+                    transform: Transform {
+                        translation: Vec3::new(
+                            settings.game_area_x_transform,
+                            settings.game_area_y_transform,
+                            self.layer_z(i, settings),
+                        ),
+                        scale: Vec3::splat(settings.scale),
+                        ..default()
+                    },
+                    visibility: if layer.visible {
+                        Visibility::Inherited
+                    } else {
+                        Visibility::Hidden
+                    },
+                    ..default()
+                };
+                bundles.push((bundle, placed));
+            }
         }
+        (bundles, problems)
     }
 }
 
+// how a placed tile is written to the map file
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TileDesc {
+    tileset_id: u32,
     tile_index: u32,
     x: i32,
     y: i32,
@@ -192,6 +812,8 @@ pub struct TileDesc {
 
 #[cfg(test)]
 mod tests {
+    use anyhow::Result;
+
     use crate::settings::SettingsFile;
 
     use super::*;
@@ -265,6 +887,27 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn nearest_coord_test() -> Result<()> {
+        let sf = SettingsFile {
+            scale: 3.,
+            x_max: 24.,
+            y_max: 18.,
+            input_debounce: 0.04,
+            tile_height: 16.,
+            tile_width: 16.,
+            tile_z: 0.0,
+            game_z: 1.0,
+        };
+        let gs = GameSettings::new_from_sf(&sf, false);
+        let pos = coord_to_screen_pos(7, 5, 0.0, &gs);
+        assert_eq!(nearest_coord(pos, &gs), TileCoords(7, 5));
+        // just short of the tile still lands on it
+        let pos = pos - Vec3::new(0.01, 0.01, 0.0);
+        assert_eq!(nearest_coord(pos, &gs), TileCoords(7, 5));
+        Ok(())
+    }
+
     #[test]
     fn screen_pos_to_coord_editor_test() -> Result<()> {
         let sf = SettingsFile {
@@ -326,4 +969,231 @@ mod tests {
         assert_eq!(pos0, TileCoords(6, 6));
         Ok(())
     }
+
+    #[test]
+    fn layer_z_test() -> Result<()> {
+        let sf = SettingsFile {
+            scale: 1.,
+            x_max: 24.,
+            y_max: 18.,
+            input_debounce: 0.04,
+            tile_height: 16.,
+            tile_width: 16.,
+            tile_z: 0.0,
+            game_z: 1.0,
+        };
+        let gs = GameSettings::new_from_sf(&sf, false);
+        let mut ms = MapScreen::default();
+        ms.add_layer("overhead", true);
+        ms.add_layer("decoration", false);
+        assert_eq!(ms.layer_z(0, &gs), gs.tile_z);
+        assert!(ms.layer_z(2, &gs) > ms.layer_z(0, &gs));
+        assert!(ms.layer_z(2, &gs) < gs.game_z);
+        assert!(ms.layer_z(1, &gs) > gs.game_z);
+        Ok(())
+    }
+
+    #[test]
+    fn add_tileset_test() -> Result<()> {
+        let mut ms = MapScreen::default();
+        assert_eq!(ms.add_tileset("tiles/forest1.png".into(), 16, 16), 0);
+        assert_eq!(ms.add_tileset("tiles/ashlands.png".into(), 8, 8), 1);
+        ms.tilesets.remove(0);
+        assert_eq!(ms.add_tileset("tiles/forest1.png".into(), 16, 16), 2);
+        assert_eq!(ms.get_tileset(1).map(|ts| ts.columns), Some(8));
+        assert_eq!(ms.get_tileset(0), None);
+        Ok(())
+    }
+
+    #[test]
+    fn new_map_test() -> Result<()> {
+        let ms = MapScreen::new(18, 24, Some("cave"));
+        assert_eq!(ms.map_name, "cave");
+        assert_ne!(ms.map_id, MapScreen::new(18, 24, None).map_id);
+        assert_eq!((ms.tile_cols, ms.tile_rows), (24, 18));
+        assert_eq!(ms.tiles().count(), 0);
+        assert_eq!(ms.layers.len(), 1);
+        assert!(ms.tilesets.is_empty());
+        assert!(ms.lights.is_empty());
+        assert_eq!(ms.neighbors, Neighbors::default());
+        Ok(())
+    }
+
+    #[test]
+    fn grid_api_test() -> Result<()> {
+        let mut ms = MapScreen::new(4, 5, Some("grid"));
+        let top = ms.add_layer("decoration", false);
+        let wall = MapTile {
+            tileset_id: 0,
+            tile_index: 3,
+            metadata: Some(TileType::Wall),
+        };
+        ms.set(0, TileCoords(4, 3), wall.clone())?;
+        assert!(matches!(
+            ms.set(0, TileCoords(5, 3), wall.clone()),
+            Err(MapError::OutOfBounds {
+                coords: TileCoords(5, 3),
+                ..
+            })
+        ));
+        assert!(matches!(
+            ms.set(7, TileCoords(0, 0), wall.clone()),
+            Err(MapError::NoLayer { layer: 7 })
+        ));
+        assert_eq!(ms.get(0, TileCoords(4, 3)), Some(&wall));
+        assert_eq!(ms.get(top, TileCoords(4, 3)), None);
+        assert_eq!(ms.metadata_at(TileCoords(4, 3)), Some(&TileType::Wall));
+        assert_eq!(
+            ms.iter_region(0, TileCoords(0, 0), TileCoords(4, 3))
+                .count(),
+            1
+        );
+        assert_eq!(ms.neighbors_of(TileCoords(4, 3), false).count(), 2);
+
+        assert!(matches!(
+            ms.resize(3, 3),
+            Err(MapError::OutOfBounds { cols: 3, .. })
+        ));
+        assert_eq!(ms.tile_cols, 5);
+        ms.resize(8, 8)?;
+        assert_eq!(ms.layers[top].tiles.cols(), 8);
+        assert_eq!(ms.clear(0, TileCoords(4, 3)), Some(wall));
+        assert_eq!(ms.tiles().count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn layer_tiles_test() {
+        let mut meta = TilesetMeta::default();
+        meta.tiles.insert(
+            4,
+            crate::tileset::TileMeta {
+                animation: vec![
+                    AnimationFrame {
+                        tile_index: 4,
+                        duration_ms: 100,
+                    },
+                    AnimationFrame {
+                        tile_index: 5,
+                        duration_ms: 100,
+                    },
+                ],
+                ..default()
+            },
+        );
+        let tile = |sprite_index| {
+            Some(Tile {
+                sprite_index,
+                color: Color::rgba(1., 1., 1., 0.5),
+                ..default()
+            })
+        };
+        let tiles = vec![
+            (ivec3(0, 0, 0), tile(4)),
+            (ivec3(1, 0, 0), tile(3)),
+            (ivec3(2, 0, 0), None),
+        ];
+        let mut placed = LayerTiles::from_tiles(0, 0, &tiles, &meta);
+        assert_eq!(placed.tiles.len(), 2);
+        assert_eq!(placed.tiles[0].frames.len(), 2);
+        assert!(placed.tiles[1].frames.is_empty());
+
+        // shading keeps the layer's opacity
+        let mut tilemap = TileMap::default();
+        placed.tint(&mut tilemap, |c| {
+            if c == TileCoords(0, 0) {
+                Color::rgb(0.5, 0.5, 0.5)
+            } else {
+                Color::WHITE
+            }
+        });
+        assert_eq!(placed.tiles[0].tile.color, Color::rgba(0.5, 0.5, 0.5, 0.5));
+        assert_eq!(placed.tiles[1].tile.color, Color::rgba(1., 1., 1., 0.5));
+        assert_eq!(placed.tiles[0].color, Color::rgba(1., 1., 1., 0.5));
+
+        // swapped for an animated tile, then taken away
+        placed.replace(&mut tilemap, TileCoords(1, 0), tile(4), &meta);
+        assert_eq!(placed.tiles.len(), 2);
+        assert_eq!(placed.tiles[1].frames.len(), 2);
+        assert_eq!(placed.tiles[1].tile.color, Color::rgba(1., 1., 1., 0.5));
+        placed.replace(&mut tilemap, TileCoords(0, 0), None, &meta);
+        assert_eq!(placed.tiles.len(), 1);
+        assert_eq!(placed.tiles[0].pos, ivec3(1, 0, 0));
+
+        // the tileset's image is gone, so everything is the placeholder
+        placed.use_placeholder();
+        assert_eq!(placed.tiles[0].tile.sprite_index, 0);
+        assert!(placed.tiles[0].frames.is_empty());
+        placed.replace(&mut tilemap, TileCoords(2, 0), tile(4), &meta);
+        assert_eq!(placed.tiles[1].tile.sprite_index, 0);
+        assert!(placed.tiles[1].frames.is_empty());
+    }
+
+    #[test]
+    fn replace_test() -> Result<()> {
+        let mut ms = MapScreen::new(4, 4, None);
+        let top = ms.add_layer("decoration", false);
+        let bush = MapTile {
+            tileset_id: 0,
+            tile_index: 7,
+            metadata: Some(TileType::Wall),
+        };
+        // `set` isn't tracked
+        ms.set(top, TileCoords(1, 1), bush.clone())?;
+        ms.set(
+            0,
+            TileCoords(1, 1),
+            MapTile {
+                tileset_id: 0,
+                tile_index: 1,
+                metadata: None,
+            },
+        )?;
+        assert!(ms.take_changes().is_empty());
+
+        // the bush is cut down, leaving the grass under it
+        assert_eq!(
+            ms.replace_metadata(TileCoords(1, 1), None)?,
+            Some(TileType::Wall)
+        );
+        assert_eq!(ms.metadata_at(TileCoords(1, 1)), None);
+        assert_eq!(
+            ms.replace_tile(top, TileCoords(1, 1), None)?
+                .map(|t| t.tile_index),
+            Some(7)
+        );
+        // nothing changes the second time
+        assert_eq!(ms.replace_tile(top, TileCoords(1, 1), None)?, None);
+        assert_eq!(
+            ms.take_changes(),
+            vec![
+                TileChanged {
+                    layer: top,
+                    coords: TileCoords(1, 1)
+                };
+                2
+            ]
+        );
+        assert!(ms.take_changes().is_empty());
+
+        // metadata goes on the topmost tile when nothing has any
+        ms.replace_metadata(TileCoords(1, 1), Some(TileType::Wall))?;
+        assert_eq!(
+            ms.get(0, TileCoords(1, 1)).and_then(|t| t.metadata.clone()),
+            Some(TileType::Wall)
+        );
+        assert!(matches!(
+            ms.replace_metadata(TileCoords(2, 2), None),
+            Err(MapError::NoTile { .. })
+        ));
+        assert!(matches!(
+            ms.replace_tile(5, TileCoords(0, 0), None),
+            Err(MapError::NoLayer { .. })
+        ));
+        assert!(matches!(
+            ms.replace_tile(0, TileCoords(4, 0), None),
+            Err(MapError::OutOfBounds { .. })
+        ));
+        Ok(())
+    }
 }
@@ -0,0 +1,212 @@
+use bevy::{
+    asset::{
+        io::{AssetReaderError, Reader},
+        AssetLoader, AsyncReadExt, LoadContext, ReadAssetBytesError,
+    },
+    prelude::*,
+    utils::BoxedFuture,
+};
+
+use super::{migrate, MapScreen};
+use crate::error::MapError;
+use crate::tileset::TilesetMeta;
+use crate::world::{World, WorldLoader};
+
+// reads maps through the asset server, so they load in the background and
+// are reloaded when the file changes. older layouts are upgraded, but never
+// written back.
+#[derive(Default)]
+pub struct MapLoader;
+
+impl AssetLoader for MapLoader {
+    type Asset = MapScreen;
+    type Settings = ();
+    type Error = MapError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<MapScreen, MapError>> {
+        Box::pin(async move {
+            let path = load_context.path().to_path_buf();
+            let mut contents = String::new();
+            reader
+                .read_to_string(&mut contents)
+                .await
+                .map_err(|e| MapError::io(&path, e))?;
+            let (mut ms, _) =
+                migrate::upgrade(&contents).map_err(|e| MapError::from_load(&path, e))?;
+            // tileset paths are relative to the assets folder, same as maps
+            ms.tileset_images = ms
+                .tilesets
+                .iter()
+                .map(|ts| load_context.load(ts.path.clone()))
+                .collect();
+            // sidecars are read as part of the map, so editing one reloads it
+            for ts in &ms.tilesets {
+                let sidecar = TilesetMeta::path_for(&ts.path);
+                let meta = match load_context.read_asset_bytes(sidecar.clone()).await {
+                    Ok(bytes) => ron::de::from_bytes(&bytes)
+                        .map_err(|e| MapError::from_load(&sidecar, e.into()))?,
+                    Err(ReadAssetBytesError::AssetReaderError(AssetReaderError::NotFound(_))) => {
+                        TilesetMeta::default()
+                    }
+                    Err(e) => {
+                        return Err(MapError::Invalid {
+                            path: sidecar,
+                            message: e.to_string(),
+                        })
+                    }
+                };
+                ms.tileset_metas.insert(ts.id, meta);
+            }
+            Ok(ms)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["map.ron"]
+    }
+}
+
+// maps, and the worlds that tie them together
+pub struct MapAssetPlugin;
+
+impl Plugin for MapAssetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<MapScreen>()
+            .init_asset::<World>()
+            .init_asset_loader::<MapLoader>()
+            .init_asset_loader::<WorldLoader>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::{anyhow, Result};
+    use bevy::asset::io::{
+        memory::{Dir, MemoryAssetReader},
+        AssetSource, AssetSourceEvent, AssetSourceId, AssetWatcher,
+    };
+    use bevy::asset::LoadState;
+    use bevy::ecs::event::ManualEventReader;
+
+    use super::*;
+    use crate::tilemap::CURRENT_FORMAT_VERSION;
+
+    // stands in for the file watcher, so the test says when a file changed
+    struct TestWatcher;
+
+    impl AssetWatcher for TestWatcher {}
+
+    // loads happen on other threads, so this gives them plenty of updates
+    // rather than a length of time
+    fn run_until<T>(
+        app: &mut App,
+        mut done: impl FnMut(&mut App) -> Result<Option<T>>,
+    ) -> Result<T> {
+        for _ in 0..10_000 {
+            app.update();
+            if let Some(found) = done(app)? {
+                return Ok(found);
+            }
+        }
+        Err(anyhow!("gave up waiting"))
+    }
+
+    #[test]
+    fn loader_test() -> Result<()> {
+        let dir = Dir::default();
+        let mut ms = MapScreen::new(2, 2, Some("loaded"));
+        ms.add_tileset("tiles/forest1.png".into(), 16, 16);
+        ms.add_tileset("tiles/plain.png".into(), 16, 16);
+        dir.insert_asset_text(Path::new("data/loaded.map.ron"), &ron::to_string(&ms)?);
+        let sidecar = Path::new("tiles/forest1.tileset.ron");
+        dir.insert_asset_text(sidecar, "TilesetMeta(tile_size: Some((8, 8)))");
+        dir.insert_asset_text(
+            Path::new("data/small.world.ron"),
+            &format!(
+                "World(name: \"small\", start_map: \"{}\", start: (0, 0), \
+                 maps: [WorldMap(map_id: \"{}\", path: \"loaded.map.ron\")], connections: [])",
+                ms.map_id, ms.map_id
+            ),
+        );
+
+        let changes = Arc::new(Mutex::new(None));
+        let (root, watch) = (dir.clone(), changes.clone());
+        let mut app = App::new();
+        app.register_asset_source(
+            AssetSourceId::Default,
+            AssetSource::build()
+                .with_reader(move || Box::new(MemoryAssetReader { root: root.clone() }))
+                .with_watcher(move |sender| {
+                    *watch.lock().ok()? = Some(sender);
+                    Some(Box::new(TestWatcher))
+                }),
+        )
+        .add_plugins((
+            MinimalPlugins,
+            AssetPlugin {
+                watch_for_changes_override: Some(true),
+                ..default()
+            },
+            MapAssetPlugin,
+        ))
+        // the tileset images don't have to load, but they need somewhere to go
+        .init_asset::<Image>();
+
+        let world: Handle<World> = app
+            .world
+            .resource::<AssetServer>()
+            .load("data/small.world.ron");
+        let map_path = run_until(&mut app, |app| {
+            let worlds = app.world.resource::<Assets<World>>();
+            Ok(worlds.get(&world).and_then(|w| w.get_map_path(ms.map_id)))
+        })?;
+        // the map's handle has to be held on to, or it's dropped mid-load
+        let map: Handle<MapScreen> = app.world.resource::<AssetServer>().load(map_path);
+        let loaded = run_until(&mut app, |app| {
+            if app.world.resource::<AssetServer>().load_state(&map) == LoadState::Failed {
+                return Err(anyhow!("the map failed to load"));
+            }
+            Ok(app.world.resource::<Assets<MapScreen>>().get(&map).cloned())
+        })?;
+        assert_eq!(loaded.map_name, "loaded");
+        assert_eq!(loaded.format_version, CURRENT_FORMAT_VERSION);
+        assert_eq!(loaded.tileset_images.len(), 2);
+        // a tileset without a sidecar gets an empty one
+        assert_eq!(loaded.tileset_metas[&0].tile_size, Some((8, 8)));
+        assert_eq!(loaded.tileset_metas[&1], TilesetMeta::default());
+
+        // editing the sidecar reloads the map
+        let mut events = ManualEventReader::<AssetEvent<MapScreen>>::default();
+        let _ = events
+            .read(app.world.resource::<Events<AssetEvent<MapScreen>>>())
+            .count();
+        dir.insert_asset_text(sidecar, "TilesetMeta(tile_size: Some((4, 4)))");
+        changes
+            .lock()
+            .map_err(|_| anyhow!("the watcher panicked"))?
+            .as_ref()
+            .ok_or_else(|| anyhow!("nothing is watching for changes"))?
+            .send(AssetSourceEvent::ModifiedAsset(sidecar.to_path_buf()))?;
+        run_until(&mut app, |app| {
+            let modified = events
+                .read(app.world.resource::<Events<AssetEvent<MapScreen>>>())
+                .any(|e| e.is_modified(&map));
+            Ok(modified.then_some(()))
+        })?;
+        let reloaded = app
+            .world
+            .resource::<Assets<MapScreen>>()
+            .get(&map)
+            .ok_or_else(|| anyhow!("the map went away"))?;
+        assert_eq!(reloaded.tileset_metas[&0].tile_size, Some((4, 4)));
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{TileCoords, TileDesc, TileType};
+use crate::error::MapError;
+
+// the cells next to `coords` that are inside a `cols` by `rows` area
+pub(super) fn neighbors_within(
+    cols: u32,
+    rows: u32,
+    coords: TileCoords,
+    diagonal: bool,
+) -> impl Iterator<Item = TileCoords> {
+    const ORTHOGONAL: [(i32, i32); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+    const DIAGONAL: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+    let extra: &[(i32, i32)] = if diagonal { &DIAGONAL } else { &[] };
+    ORTHOGONAL
+        .iter()
+        .chain(extra.iter())
+        .map(move |(dx, dy)| TileCoords(coords.0 + dx, coords.1 + dy))
+        .filter(move |c| c.0 >= 0 && c.1 >= 0 && (c.0 as u32) < cols && (c.1 as u32) < rows)
+}
+
+// what's painted in one cell of a layer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapTile {
+    pub tileset_id: u32,
+    pub tile_index: u32,
+    pub metadata: Option<TileType>,
+}
+
+// a layer's tiles stored row-major, (0, 0) in the bottom left like the
+// screen coordinates. on disk it's still the sparse list of `TileDesc`s,
+// so empty cells cost nothing and older files read the same.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TileGrid {
+    cols: u32,
+    rows: u32,
+    cells: Vec<Option<MapTile>>,
+}
+
+impl TileGrid {
+    pub fn new(cols: u32, rows: u32) -> Self {
+        TileGrid {
+            cols,
+            rows,
+            cells: vec![None; (cols * rows) as usize],
+        }
+    }
+
+    // sized just big enough to hold every tile. where two tiles share a
+    // cell the later one wins, and the cell is handed back so it can be
+    // reported. a tile at a negative coordinate is an error.
+    pub fn from_descs(descs: Vec<TileDesc>) -> Result<(Self, Vec<TileCoords>), MapError> {
+        let cols = descs.iter().map(|t| t.x + 1).max().unwrap_or(0).max(0) as u32;
+        let rows = descs.iter().map(|t| t.y + 1).max().unwrap_or(0).max(0) as u32;
+        let mut grid = TileGrid::new(cols, rows);
+        let mut doubled = vec![];
+        for t in descs {
+            let coords = TileCoords(t.x, t.y);
+            let tile = MapTile {
+                tileset_id: t.tileset_id,
+                tile_index: t.tile_index,
+                metadata: t.metadata,
+            };
+            if grid.set(coords, tile)?.is_some() {
+                doubled.push(coords);
+            }
+        }
+        Ok((grid, doubled))
+    }
+
+    pub fn cols(&self) -> u32 {
+        self.cols
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+
+    pub fn contains(&self, coords: TileCoords) -> bool {
+        let TileCoords(x, y) = coords;
+        x >= 0 && y >= 0 && (x as u32) < self.cols && (y as u32) < self.rows
+    }
+
+    fn index(&self, coords: TileCoords) -> Option<usize> {
+        if self.contains(coords) {
+            Some((coords.1 as u32 * self.cols + coords.0 as u32) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn coords(&self, index: usize) -> TileCoords {
+        let index = index as u32;
+        TileCoords((index % self.cols) as i32, (index / self.cols) as i32)
+    }
+
+    pub fn get(&self, coords: TileCoords) -> Option<&MapTile> {
+        self.index(coords).and_then(|i| self.cells[i].as_ref())
+    }
+
+    pub fn get_mut(&mut self, coords: TileCoords) -> Option<&mut MapTile> {
+        self.index(coords).and_then(|i| self.cells[i].as_mut())
+    }
+
+    // returns whatever was in the cell before
+    pub fn set(&mut self, coords: TileCoords, tile: MapTile) -> Result<Option<MapTile>, MapError> {
+        let i = self.index(coords).ok_or(MapError::OutOfBounds {
+            coords,
+            cols: self.cols,
+            rows: self.rows,
+        })?;
+        Ok(self.cells[i].replace(tile))
+    }
+
+    pub fn clear(&mut self, coords: TileCoords) -> Option<MapTile> {
+        self.index(coords).and_then(|i| self.cells[i].take())
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.iter().filter(|c| c.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.iter().all(|c| c.is_none())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (TileCoords, &MapTile)> {
+        self.cells
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| c.as_ref().map(|t| (self.coords(i), t)))
+    }
+
+    // every filled cell between the two corners, inclusive. the region is
+    // clipped to the grid.
+    pub fn iter_region(
+        &self,
+        from: TileCoords,
+        to: TileCoords,
+    ) -> impl Iterator<Item = (TileCoords, &MapTile)> {
+        let min_x = from.0.min(to.0).max(0);
+        let max_x = from.0.max(to.0).min(self.cols as i32 - 1);
+        let min_y = from.1.min(to.1).max(0);
+        let max_y = from.1.max(to.1).min(self.rows as i32 - 1);
+        (min_y..=max_y)
+            .flat_map(move |y| (min_x..=max_x).map(move |x| TileCoords(x, y)))
+            .filter_map(|c| self.get(c).map(|t| (c, t)))
+    }
+
+    // the in-bounds cells around `coords`, orthogonal ones first
+    pub fn neighbors(
+        &self,
+        coords: TileCoords,
+        diagonal: bool,
+    ) -> impl Iterator<Item = TileCoords> {
+        neighbors_within(self.cols, self.rows, coords, diagonal)
+    }
+
+    // fails rather than dropping tiles that wouldn't fit
+    pub fn resize(&mut self, cols: u32, rows: u32) -> Result<(), MapError> {
+        let mut grid = TileGrid::new(cols, rows);
+        for (coords, tile) in self.iter() {
+            grid.set(coords, tile.clone())?;
+        }
+        *self = grid;
+        Ok(())
+    }
+}
+
+impl Serialize for TileGrid {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter().map(|(coords, t)| TileDesc {
+            tileset_id: t.tileset_id,
+            tile_index: t.tile_index,
+            x: coords.0,
+            y: coords.1,
+            metadata: t.metadata.clone(),
+        }))
+    }
+}
+
+impl<'de> Deserialize<'de> for TileGrid {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let descs = Vec::<TileDesc>::deserialize(deserializer)?;
+        let (grid, doubled) = TileGrid::from_descs(descs).map_err(D::Error::custom)?;
+        // only older layouts get fixed up like that
+        if let Some(coords) = doubled.first() {
+            return Err(D::Error::custom(format!(
+                "more than one tile at {}",
+                coords
+            )));
+        }
+        Ok(grid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    fn tile(tile_index: u32) -> MapTile {
+        MapTile {
+            tileset_id: 0,
+            tile_index,
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn set_get_clear() -> Result<()> {
+        let mut grid = TileGrid::new(4, 3);
+        assert_eq!(grid.set(TileCoords(3, 2), tile(5))?, None);
+        assert_eq!(grid.set(TileCoords(3, 2), tile(6))?, Some(tile(5)));
+        assert_eq!(grid.get(TileCoords(3, 2)), Some(&tile(6)));
+        assert_eq!(grid.get(TileCoords(2, 3)), None);
+        assert!(grid.set(TileCoords(4, 0), tile(1)).is_err());
+        assert!(grid.set(TileCoords(-1, 0), tile(1)).is_err());
+        assert_eq!(grid.len(), 1);
+        assert_eq!(grid.clear(TileCoords(3, 2)), Some(tile(6)));
+        assert!(grid.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn region_and_neighbors() -> Result<()> {
+        let mut grid = TileGrid::new(4, 4);
+        for x in 0..4 {
+            for y in 0..4 {
+                grid.set(TileCoords(x, y), tile((y * 4 + x) as u32))?;
+            }
+        }
+        let region: Vec<u32> = grid
+            .iter_region(TileCoords(2, 2), TileCoords(-3, 1))
+            .map(|(_, t)| t.tile_index)
+            .collect();
+        assert_eq!(region, vec![4, 5, 6, 8, 9, 10]);
+
+        let corner: Vec<TileCoords> = grid.neighbors(TileCoords(0, 0), false).collect();
+        assert_eq!(corner, vec![TileCoords(0, 1), TileCoords(1, 0)]);
+        assert_eq!(grid.neighbors(TileCoords(1, 1), true).count(), 8);
+        Ok(())
+    }
+
+    #[test]
+    fn resize_keeps_tiles() -> Result<()> {
+        let mut grid = TileGrid::new(2, 2);
+        grid.set(TileCoords(1, 1), tile(3))?;
+        grid.resize(5, 5)?;
+        assert_eq!(grid.get(TileCoords(1, 1)), Some(&tile(3)));
+        assert!(grid.resize(1, 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn serializes_sparse() -> Result<()> {
+        let mut grid = TileGrid::new(10, 10);
+        grid.set(TileCoords(7, 2), tile(3))?;
+        let data = ron::to_string(&grid)?;
+        assert_eq!(data.matches("tile_index").count(), 1);
+        let reloaded: TileGrid = ron::from_str(&data)?;
+        assert_eq!(reloaded.get(TileCoords(7, 2)), Some(&tile(3)));
+
+        let dupes = "[(tileset_id: 0, tile_index: 1, x: 0, y: 0, metadata: None), \
+                     (tileset_id: 0, tile_index: 2, x: 0, y: 0, metadata: None)]";
+        assert!(ron::from_str::<TileGrid>(dupes).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn from_descs_keeps_last() -> Result<()> {
+        let desc = |tile_index, x| TileDesc {
+            tileset_id: 0,
+            tile_index,
+            x,
+            y: 1,
+            metadata: None,
+        };
+        let (grid, doubled) = TileGrid::from_descs(vec![desc(1, 2), desc(2, 0), desc(3, 2)])?;
+        assert_eq!((grid.cols(), grid.rows()), (3, 2));
+        assert_eq!(grid.get(TileCoords(2, 1)), Some(&tile(3)));
+        assert_eq!(doubled, vec![TileCoords(2, 1)]);
+        assert!(TileGrid::from_descs(vec![desc(1, -1)]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,1215 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+
+use super::paths::{normalize, relative_to};
+use super::tiled::{covered_cells, set_metadata, tile_type_fields, tile_type_from_properties};
+use super::{
+    MapScreen, MapTile, Neighbors, TileCoords, TileGrid, TileLayer, TileType, Tileset,
+    CURRENT_FORMAT_VERSION,
+};
+use crate::error::MapError;
+use crate::lighting::FULL_LIGHT;
+use crate::tileset::TilesetMeta;
+
+const LDTK_VERSION: &str = "1.5.3";
+
+// one of each, to describe the entities metadata is exported as
+fn tile_type_templates() -> Vec<TileType> {
+    vec![
+        TileType::Wall,
+        TileType::Door {
+            target_map: uuid::Uuid::nil(),
+            target: TileCoords(0, 0),
+        },
+        TileType::Item {
+            item_id: String::new(),
+        },
+        TileType::Enemy {
+            kind: String::new(),
+            level: 0,
+        },
+        TileType::NPC {
+            npc_id: String::new(),
+        },
+    ]
+}
+
+// ldtk identifiers start with a capital letter and only use letters,
+// digits and underscores
+fn identifier(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    match out.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => out[..1].make_ascii_uppercase(),
+        _ => out.insert(0, '_'),
+    }
+    out
+}
+
+fn field_type(value: &Value) -> &'static str {
+    match value {
+        Value::Number(_) => "Int",
+        Value::Bool(_) => "Bool",
+        _ => "String",
+    }
+}
+
+// hands out the uids ldtk uses to tie definitions together
+#[derive(Debug, Default)]
+struct Uids(u32);
+
+impl Uids {
+    fn next(&mut self) -> u32 {
+        self.0 += 1;
+        self.0
+    }
+}
+
+// writes the map as an ldtk project holding a single level. each layer
+// becomes a tile layer per tileset it uses, and metadata becomes entities
+// whose fields hold the same values `tiled::export` writes as properties.
+// the level is named after the map and takes the map's id as its iid.
+pub fn export(
+    ms: &MapScreen,
+    metas: &HashMap<u32, TilesetMeta>,
+    tile_size: (u32, u32),
+    filename: &Path,
+    assets_root: &Path,
+) -> Result<(), MapError> {
+    let dir = filename.parent().unwrap_or(Path::new(""));
+    let ldtk = to_ldtk(ms, metas, tile_size, &relative_to(assets_root, dir))
+        .map_err(|e| MapError::from_load(filename, e))?;
+    let data =
+        serde_json::to_string_pretty(&ldtk).map_err(|e| MapError::from_load(filename, e.into()))?;
+    fs::write(filename, data).map_err(|e| MapError::io(filename, e))
+}
+
+// `image_dir` is where tileset paths are relative to, as seen from the
+// project file
+fn to_ldtk(
+    ms: &MapScreen,
+    metas: &HashMap<u32, TilesetMeta>,
+    tile_size: (u32, u32),
+    image_dir: &Path,
+) -> Result<Value> {
+    if tile_size.0 != tile_size.1 {
+        return Err(anyhow!(
+            "ldtk needs square tiles, but the map's are {}x{}",
+            tile_size.0,
+            tile_size.1
+        ));
+    }
+    let grid = tile_size.0;
+    let (width, height) = (ms.tile_cols, ms.tile_rows);
+    let default_meta = TilesetMeta::default();
+    let mut uids = Uids::default();
+    // iids are derived from the map id, so exporting the same map twice
+    // gives the same file
+    let mut iid_count = 0;
+    let mut iid = || {
+        iid_count += 1;
+        uuid::Uuid::from_u128(ms.map_id.as_u128().wrapping_add(iid_count)).to_string()
+    };
+
+    let mut tileset_defs = vec![];
+    let mut tileset_uids = HashMap::new();
+    for ts in &ms.tilesets {
+        let meta = metas.get(&ts.id).unwrap_or(&default_meta);
+        let (tw, th) = meta.tile_size_or(tile_size);
+        if tw != th {
+            return Err(anyhow!(
+                "ldtk needs square tiles, but {} has {}x{} tiles",
+                ts.path.to_string_lossy(),
+                tw,
+                th
+            ));
+        }
+        let sheet =
+            |count: u32| meta.margin * 2 + count * tw + count.saturating_sub(1) * meta.spacing;
+        let rel_path = if ts.path.is_absolute() {
+            ts.path.clone()
+        } else {
+            normalize(&image_dir.join(&ts.path))
+        };
+        let uid = uids.next();
+        tileset_uids.insert(ts.id, uid);
+        tileset_defs.push(json!({
+            "__cWid": ts.columns,
+            "__cHei": ts.rows,
+            "identifier": identifier(
+                &ts.path.file_stem().map(|n| n.to_string_lossy()).unwrap_or_default()
+            ),
+            "uid": uid,
+            "relPath": rel_path.to_string_lossy().replace('\\', "/"),
+            "embedAtlas": null,
+            "pxWid": sheet(ts.columns),
+            "pxHei": sheet(ts.rows),
+            "tileGridSize": tw,
+            "spacing": meta.spacing,
+            "padding": meta.margin,
+            "tags": [],
+            "tagsSourceEnumUid": null,
+            "enumTags": [],
+            "customData": [],
+            "savedSelections": [],
+            "cachedPixelData": null,
+        }));
+    }
+
+    let field_def = |uid: u32, name: &str, kind: &str| {
+        json!({
+            "identifier": name,
+            "doc": null,
+            "__type": kind,
+            "uid": uid,
+            "type": format!("F_{}", kind),
+            "isArray": false,
+            "canBeNull": false,
+            "arrayMinLength": null,
+            "arrayMaxLength": null,
+            "editorDisplayMode": "NameAndValue",
+            "editorDisplayScale": 1,
+            "editorDisplayPos": "Above",
+            "editorLinkStyle": "StraightArrow",
+            "editorDisplayColor": null,
+            "editorAlwaysShow": false,
+            "editorShowInWorld": true,
+            "editorCutLongValues": true,
+            "editorTextSuffix": null,
+            "editorTextPrefix": null,
+            "useForSmartColor": false,
+            "exportToToc": false,
+            "searchable": false,
+            "min": null,
+            "max": null,
+            "regex": null,
+            "acceptFileTypes": null,
+            "defaultOverride": null,
+            "textLanguageMode": null,
+            "symmetricalRef": false,
+            "autoChainRef": true,
+            "allowOutOfLevelRef": true,
+            "allowedRefs": "OnlySame",
+            "allowedRefsEntityUid": null,
+            "allowedRefTags": [],
+            "tilesetUid": null,
+        })
+    };
+
+    // entity name -> (uid, field name -> uid)
+    let mut entity_uids: HashMap<&str, (u32, HashMap<&str, u32>)> = HashMap::new();
+    let mut entity_defs = vec![];
+    for template in tile_type_templates() {
+        let (kind, fields) = tile_type_fields(&template);
+        let uid = uids.next();
+        let mut field_uids = HashMap::new();
+        let mut field_defs = vec![];
+        for (name, value) in &fields {
+            let field_uid = uids.next();
+            field_uids.insert(*name, field_uid);
+            field_defs.push(field_def(field_uid, name, field_type(value)));
+        }
+        entity_uids.insert(kind, (uid, field_uids));
+        entity_defs.push(json!({
+            "identifier": identifier(kind),
+            "uid": uid,
+            "tags": [],
+            "exportToToc": false,
+            "allowOutOfBounds": false,
+            "doc": null,
+            "width": grid,
+            "height": grid,
+            "resizableX": false,
+            "resizableY": false,
+            "minWidth": null,
+            "maxWidth": null,
+            "minHeight": null,
+            "maxHeight": null,
+            "keepAspectRatio": false,
+            "tileOpacity": 1,
+            "fillOpacity": 0.08,
+            "lineOpacity": 0,
+            "hollow": false,
+            "color": "#BE4A2F",
+            "renderMode": "Rectangle",
+            "showName": true,
+            "tilesetId": null,
+            "tileRenderMode": "FitInside",
+            "tileRect": null,
+            "uiTileRect": null,
+            "nineSliceBorders": [],
+            "maxCount": 0,
+            "limitScope": "PerLevel",
+            "limitBehavior": "MoveLastOne",
+            "pivotX": 0,
+            "pivotY": 0,
+            "fieldDefs": field_defs,
+        }));
+    }
+
+    let level_uid = uids.next();
+    let layer_def = |uid: u32, name: &str, kind: &str, opacity: f32, tileset: Option<u32>| {
+        json!({
+            "__type": kind,
+            "identifier": name,
+            "type": kind,
+            "uid": uid,
+            "doc": null,
+            "uiColor": null,
+            "gridSize": grid,
+            "guideGridWid": 0,
+            "guideGridHei": 0,
+            "displayOpacity": opacity,
+            "inactiveOpacity": 1,
+            "hideInList": false,
+            "hideFieldsWhenInactive": true,
+            "canSelectWhenInactive": true,
+            "renderInWorldView": true,
+            "pxOffsetX": 0,
+            "pxOffsetY": 0,
+            "parallaxFactorX": 0,
+            "parallaxFactorY": 0,
+            "parallaxScaling": true,
+            "requiredTags": [],
+            "excludedTags": [],
+            "autoTilesKilledByOtherLayerUid": null,
+            "uiFilterTags": [],
+            "useAsyncRender": false,
+            "intGridValues": [],
+            "intGridValuesGroups": [],
+            "autoRuleGroups": [],
+            "autoSourceLayerDefUid": null,
+            "tilesetDefUid": tileset,
+            "tilePivotX": 0,
+            "tilePivotY": 0,
+            "biomeFieldUid": null,
+        })
+    };
+    let layer_instance =
+        |iid: String, uid: u32, name: &str, kind: &str, opacity: f32, visible: bool| {
+            json!({
+                "__identifier": name,
+                "__type": kind,
+                "__cWid": width,
+                "__cHei": height,
+                "__gridSize": grid,
+                "__opacity": opacity,
+                "__pxTotalOffsetX": 0,
+                "__pxTotalOffsetY": 0,
+                "__tilesetDefUid": null,
+                "__tilesetRelPath": null,
+                "iid": iid,
+                "levelId": level_uid,
+                "layerDefUid": uid,
+                "pxOffsetX": 0,
+                "pxOffsetY": 0,
+                "visible": visible,
+                "optionalRules": [],
+                "intGridCsv": [],
+                "autoLayerTiles": [],
+                "seed": 0,
+                "overrideTilesetUid": null,
+                "gridTiles": [],
+                "entityInstances": [],
+            })
+        };
+
+    // ldtk lists layers top first, so metadata goes on top and the ground
+    // layer last
+    let mut layer_defs = vec![];
+    let mut layer_instances = vec![];
+
+    let entities_uid = uids.next();
+    layer_defs.push(layer_def(entities_uid, "Metadata", "Entities", 1., None));
+    let mut entities = layer_instance(iid(), entities_uid, "Metadata", "Entities", 1., true);
+    let mut entity_instances = vec![];
+    for y in (0..height as i32).rev() {
+        for x in 0..width as i32 {
+            let Some(tile_type) = ms.metadata_at(TileCoords(x, y)) else {
+                continue;
+            };
+            let (kind, fields) = tile_type_fields(tile_type);
+            let (def_uid, field_uids) = &entity_uids[kind];
+            let (cx, cy) = (x as u32, height - 1 - y as u32);
+            let field_instances: Vec<Value> = fields
+                .into_iter()
+                .map(|(name, value)| {
+                    let kind = field_type(&value);
+                    json!({
+                        "__identifier": name,
+                        "__type": kind,
+                        "__value": value,
+                        "__tile": null,
+                        "defUid": field_uids[name],
+                        "realEditorValues": [{ "id": format!("V_{}", kind), "params": [value] }],
+                    })
+                })
+                .collect();
+            entity_instances.push(json!({
+                "__identifier": identifier(kind),
+                "__grid": [cx, cy],
+                "__pivot": [0, 0],
+                "__tags": [],
+                "__tile": null,
+                "__smartColor": "#BE4A2F",
+                "__worldX": cx * grid,
+                "__worldY": cy * grid,
+                "iid": iid(),
+                "width": grid,
+                "height": grid,
+                "defUid": def_uid,
+                "px": [cx * grid, cy * grid],
+                "fieldInstances": field_instances,
+            }));
+        }
+    }
+    entities["entityInstances"] = json!(entity_instances);
+    layer_instances.push(entities);
+
+    for layer in ms.layers.iter().rev() {
+        let mut used: Vec<u32> = layer.tiles.iter().map(|(_, t)| t.tileset_id).collect();
+        used.sort();
+        used.dedup();
+        // a layer drawing from more than one tileset is split up
+        let split = used.len() > 1;
+        for tileset_id in used {
+            let ts = ms.get_tileset(tileset_id).ok_or_else(|| {
+                anyhow!("layer {} uses missing tileset {}", layer.name, tileset_id)
+            })?;
+            let meta = metas.get(&tileset_id).unwrap_or(&default_meta);
+            let name = if split {
+                identifier(&format!("{}_{}", layer.name, tileset_id))
+            } else {
+                identifier(&layer.name)
+            };
+
+            let uid = uids.next();
+            let tileset_uid = tileset_uids[&tileset_id];
+            layer_defs.push(layer_def(
+                uid,
+                &name,
+                "Tiles",
+                layer.opacity,
+                Some(tileset_uid),
+            ));
+
+            let mut instance =
+                layer_instance(iid(), uid, &name, "Tiles", layer.opacity, layer.visible);
+            let tile_px = meta.tile_size_or(tile_size);
+            let grid_tiles: Vec<Value> = layer
+                .tiles
+                .iter()
+                .filter(|(_, t)| t.tileset_id == tileset_id)
+                .map(|(TileCoords(x, y), t)| {
+                    let (cx, cy) = (x as u32, height - 1 - y as u32);
+                    let src = meta.tile_origin(
+                        t.tile_index % ts.columns.max(1),
+                        t.tile_index / ts.columns.max(1),
+                        tile_px,
+                    );
+                    json!({
+                        "px": [cx * grid, cy * grid],
+                        "src": [src.0, src.1],
+                        "f": 0,
+                        "t": t.tile_index,
+                        "d": [cx + cy * width],
+                        "a": 1,
+                    })
+                })
+                .collect();
+            instance["__tilesetDefUid"] = json!(tileset_uid);
+            instance["__tilesetRelPath"] = tileset_defs
+                .iter()
+                .find(|d| d["uid"] == tileset_uid)
+                .map(|d| d["relPath"].clone())
+                .unwrap_or(Value::Null);
+            instance["gridTiles"] = json!(grid_tiles);
+            layer_instances.push(instance);
+        }
+    }
+
+    let map_name_uid = uids.next();
+    let level = json!({
+        "identifier": identifier(if ms.map_name.is_empty() { "Level_0" } else { &ms.map_name }),
+        "iid": ms.map_id.to_string(),
+        "uid": level_uid,
+        "worldX": 0,
+        "worldY": 0,
+        "worldDepth": 0,
+        "pxWid": width * grid,
+        "pxHei": height * grid,
+        "__bgColor": "#696A79",
+        "bgColor": null,
+        "useAutoIdentifier": false,
+        "bgRelPath": null,
+        "bgPos": null,
+        "bgPivotX": 0.5,
+        "bgPivotY": 0.5,
+        "__smartColor": "#ADADB5",
+        "__bgPos": null,
+        "externalRelPath": null,
+        "fieldInstances": [{
+            "__identifier": "map_name",
+            "__type": "String",
+            "__value": ms.map_name,
+            "__tile": null,
+            "defUid": map_name_uid,
+            "realEditorValues": [{ "id": "V_String", "params": [ms.map_name] }],
+        }],
+        "layerInstances": layer_instances,
+        "__neighbours": [],
+    });
+
+    Ok(json!({
+        "__header__": {
+            "fileType": "LDtk Project JSON",
+            "app": "LDtk",
+            "doc": "https://ldtk.io/json",
+            "schema": "https://ldtk.io/files/JSON_SCHEMA.json",
+            "appAuthor": "Sebastien 'deepnight' Benard",
+            "appVersion": LDTK_VERSION,
+            "url": "https://ldtk.io",
+        },
+        "iid": iid(),
+        "jsonVersion": LDTK_VERSION,
+        "appBuildId": 473703,
+        "nextUid": uids.next(),
+        "identifierStyle": "Capitalize",
+        "toc": [],
+        "worldLayout": "Free",
+        "worldGridWidth": width * grid,
+        "worldGridHeight": height * grid,
+        "defaultLevelWidth": width * grid,
+        "defaultLevelHeight": height * grid,
+        "defaultPivotX": 0,
+        "defaultPivotY": 0,
+        "defaultGridSize": grid,
+        "defaultEntityWidth": grid,
+        "defaultEntityHeight": grid,
+        "bgColor": "#40465B",
+        "defaultLevelBgColor": "#696A79",
+        "minifyJson": false,
+        "externalLevels": false,
+        "exportTiled": false,
+        "simplifiedExport": false,
+        "imageExportMode": "None",
+        "exportLevelBg": true,
+        "pngFilePattern": null,
+        "backupOnSave": false,
+        "backupLimit": 10,
+        "backupRelPath": null,
+        "levelNamePattern": "Level_%idx",
+        "tutorialDesc": null,
+        "customCommands": [],
+        "flags": [],
+        "defs": {
+            "layers": layer_defs,
+            "entities": entity_defs,
+            "tilesets": tileset_defs,
+            "enums": [],
+            "externalEnums": [],
+            "levelFields": [field_def(map_name_uid, "map_name", "String")],
+        },
+        "levels": [level],
+        "worlds": [],
+        "dummyWorldIid": iid(),
+    }))
+}
+
+// the parts of an ldtk project the importer reads
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkProject {
+    defs: LdtkDefs,
+    levels: Vec<LdtkLevel>,
+    #[serde(default)]
+    external_levels: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct LdtkDefs {
+    layers: Vec<LdtkLayerDef>,
+    tilesets: Vec<LdtkTilesetDef>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkLayerDef {
+    uid: u32,
+    #[serde(default)]
+    int_grid_values: Vec<LdtkIntGridValue>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LdtkIntGridValue {
+    value: u32,
+    identifier: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkTilesetDef {
+    uid: u32,
+    // embedded atlases, like ldtk's own icons, don't have one
+    rel_path: Option<String>,
+    #[serde(rename = "__cWid")]
+    c_wid: u32,
+    #[serde(rename = "__cHei")]
+    c_hei: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkLevel {
+    identifier: String,
+    iid: String,
+    px_wid: u32,
+    px_hei: u32,
+    #[serde(default)]
+    field_instances: Vec<LdtkField>,
+    // missing from the project when levels are saved separately
+    layer_instances: Option<Vec<LdtkLayer>>,
+    external_rel_path: Option<String>,
+    #[serde(rename = "__neighbours", default)]
+    neighbours: Vec<LdtkNeighbour>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkNeighbour {
+    dir: String,
+    level_iid: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkLayer {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    #[serde(rename = "__type")]
+    kind: String,
+    #[serde(rename = "__cWid")]
+    c_wid: u32,
+    #[serde(rename = "__cHei")]
+    c_hei: u32,
+    #[serde(rename = "__gridSize")]
+    grid_size: u32,
+    #[serde(rename = "__opacity")]
+    opacity: f32,
+    #[serde(rename = "__tilesetDefUid")]
+    tileset_def_uid: Option<u32>,
+    #[serde(rename = "__pxTotalOffsetX")]
+    px_total_offset_x: i32,
+    #[serde(rename = "__pxTotalOffsetY")]
+    px_total_offset_y: i32,
+    layer_def_uid: u32,
+    visible: bool,
+    override_tileset_uid: Option<u32>,
+    #[serde(default)]
+    int_grid_csv: Vec<u32>,
+    #[serde(default)]
+    auto_layer_tiles: Vec<LdtkTile>,
+    #[serde(default)]
+    grid_tiles: Vec<LdtkTile>,
+    #[serde(default)]
+    entity_instances: Vec<LdtkEntity>,
+}
+
+// positions are in pixels from the top left of the layer
+#[derive(Debug, Deserialize)]
+struct LdtkTile {
+    px: [i32; 2],
+    t: u32,
+    f: u8,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LdtkEntity {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    #[serde(rename = "__pivot")]
+    pivot: [f32; 2],
+    px: [i32; 2],
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    field_instances: Vec<LdtkField>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LdtkField {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    #[serde(rename = "__value")]
+    value: Value,
+}
+
+// fields as the strings `tile_type_from_properties` parses. unset fields
+// are left out.
+fn field_strings(fields: &[LdtkField]) -> HashMap<String, String> {
+    fields
+        .iter()
+        .filter_map(|f| {
+            let value = match &f.value {
+                Value::Null => return None,
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            Some((f.identifier.clone(), value))
+        })
+        .collect()
+}
+
+fn read_json<T: DeserializeOwned>(filename: &Path) -> Result<T> {
+    let contents = fs::read_to_string(filename)
+        .map_err(|e| anyhow!("{}, {:?}", filename.to_string_lossy(), e))?;
+    serde_json::from_str(&contents).map_err(|e| anyhow!("{}, {:?}", filename.to_string_lossy(), e))
+}
+
+// reads an ldtk project, giving a map for each of its levels. tile and
+// auto layers become layers, intgrid cells marked as walls and entities
+// named after a tile type set the metadata of the tile under them. the
+// level's iid becomes the map id, and its neighbours become the map's.
+pub fn import(filename: &Path, assets_root: &Path) -> Result<Vec<MapScreen>, MapError> {
+    let contents = fs::read_to_string(filename).map_err(|e| MapError::io(filename, e))?;
+    let project: LdtkProject =
+        serde_json::from_str(&contents).map_err(|e| MapError::from_load(filename, e.into()))?;
+    let dir = filename.parent().unwrap_or(Path::new(""));
+    from_project(project, dir, assets_root).map_err(|e| MapError::from_load(filename, e))
+}
+
+// `dir` is the directory the project is in
+fn from_project(project: LdtkProject, dir: &Path, assets_root: &Path) -> Result<Vec<MapScreen>> {
+    let root = normalize(assets_root);
+    let mut tilesets = vec![];
+    let mut tileset_ids = HashMap::new();
+    for def in &project.defs.tilesets {
+        let Some(rel_path) = &def.rel_path else {
+            continue;
+        };
+        let image = normalize(&dir.join(rel_path));
+        let id = tilesets.len() as u32;
+        tileset_ids.insert(def.uid, id);
+        tilesets.push(Tileset {
+            id,
+            path: image
+                .strip_prefix(&root)
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|_| image.clone()),
+            columns: def.c_wid,
+            rows: def.c_hei,
+        });
+    }
+    let int_grid_values: HashMap<u32, &[LdtkIntGridValue]> = project
+        .defs
+        .layers
+        .iter()
+        .map(|d| (d.uid, d.int_grid_values.as_slice()))
+        .collect();
+
+    let mut maps = vec![];
+    for level in project.levels {
+        let level = match (&level.layer_instances, &level.external_rel_path) {
+            (None, Some(path)) if project.external_levels => read_json(&dir.join(path))?,
+            _ => level,
+        };
+        let identifier = level.identifier.clone();
+        let ms = level
+            .into_map_screen(&tilesets, &tileset_ids, &int_grid_values)
+            .map_err(|e| anyhow!("level {}: {}", identifier, e))?;
+        maps.push(ms);
+    }
+    Ok(maps)
+}
+
+// intgrid values called wall are walls. so is 1, unless it's been named
+// something else.
+fn is_wall(values: &[LdtkIntGridValue], value: u32) -> bool {
+    match values.iter().find(|v| v.value == value) {
+        Some(LdtkIntGridValue {
+            identifier: Some(name),
+            ..
+        }) => name.eq_ignore_ascii_case("wall"),
+        _ => value == 1,
+    }
+}
+
+impl LdtkLevel {
+    fn into_map_screen(
+        self,
+        tilesets: &[Tileset],
+        tileset_ids: &HashMap<u32, u32>,
+        int_grid_values: &HashMap<u32, &[LdtkIntGridValue]>,
+    ) -> Result<MapScreen> {
+        let layers = self
+            .layer_instances
+            .ok_or_else(|| anyhow!("has no layers"))?;
+        let grid = layers
+            .iter()
+            .find(|l| l.kind != "Entities")
+            .or(layers.first())
+            .map(|l| l.grid_size)
+            .ok_or_else(|| anyhow!("has no layers"))?;
+        // tile layers have to match it and entities are placed by it, so
+        // it's the only size to check
+        if grid == 0 {
+            return Err(anyhow!("has 0px cells"));
+        }
+        let (cols, rows) = (self.px_wid.div_ceil(grid), self.px_hei.div_ceil(grid));
+
+        let fields = field_strings(&self.field_instances);
+        let mut neighbors = Neighbors::default();
+        for n in &self.neighbours {
+            let edge = match n.dir.as_str() {
+                "n" => &mut neighbors.north,
+                "s" => &mut neighbors.south,
+                "e" => &mut neighbors.east,
+                "w" => &mut neighbors.west,
+                // corners and levels above or below
+                _ => continue,
+            };
+            *edge = n.level_iid.parse().ok();
+        }
+        let mut ms = MapScreen {
+            format_version: CURRENT_FORMAT_VERSION,
+            map_name: fields
+                .get("map_name")
+                .filter(|n| !n.is_empty())
+                .cloned()
+                .unwrap_or(self.identifier),
+            map_id: self
+                .iid
+                .parse()
+                .map_err(|e| anyhow!("iid {} isn't a uuid: {}", self.iid, e))?,
+            tilesets: tilesets.to_vec(),
+            tile_rows: rows,
+            tile_cols: cols,
+            neighbors,
+            fog_of_war: false,
+            ambient_light: match fields.get("ambient_light") {
+                Some(ambient) => ambient
+                    .parse()
+                    .map_err(|e| anyhow!("ambient_light {}: {}", ambient, e))?,
+                None => FULL_LIGHT,
+            },
+            lights: vec![],
+            changes: vec![],
+            tileset_images: vec![],
+            tileset_metas: HashMap::new(),
+            upgrade_warnings: vec![],
+            layers: vec![],
+        };
+
+        let kinds: Vec<&str> = tile_type_templates()
+            .iter()
+            .map(|t| tile_type_fields(t).0)
+            .collect();
+        // (what set it, where, what to) for once every tile is in place
+        let mut metadata = vec![];
+        // ldtk lists layers top first
+        for layer in layers.into_iter().rev() {
+            if layer.px_total_offset_x != 0 || layer.px_total_offset_y != 0 {
+                return Err(anyhow!(
+                    "layer {} is offset, which isn't supported",
+                    layer.identifier
+                ));
+            }
+            if layer.kind != "Entities" && layer.grid_size != grid {
+                return Err(anyhow!(
+                    "layer {} uses {}px cells, but the level uses {}px",
+                    layer.identifier,
+                    layer.grid_size,
+                    grid
+                ));
+            }
+            match layer.kind.as_str() {
+                "Entities" => {
+                    for entity in &layer.entity_instances {
+                        // anything else is for the game, not the map
+                        if !kinds.contains(&entity.identifier.to_lowercase().as_str()) {
+                            continue;
+                        }
+                        let label = format!("entity {} in {}", entity.identifier, layer.identifier);
+                        let Some(tile_type) = tile_type_from_properties(
+                            &entity.identifier,
+                            &label,
+                            &field_strings(&entity.field_instances),
+                        )?
+                        else {
+                            continue;
+                        };
+                        let (w, h) = (entity.width as f32, entity.height as f32);
+                        let top_left = (
+                            entity.px[0] as f32 - entity.pivot[0] * w,
+                            entity.px[1] as f32 - entity.pivot[1] * h,
+                        );
+                        for coords in covered_cells(top_left, (w, h), (grid, grid), rows) {
+                            metadata.push((label.clone(), coords, tile_type.clone()));
+                        }
+                    }
+                }
+                "IntGrid" => {
+                    let values = int_grid_values
+                        .get(&layer.layer_def_uid)
+                        .copied()
+                        .unwrap_or_default();
+                    if layer.c_wid == 0
+                        || layer.int_grid_csv.len() != (layer.c_wid * layer.c_hei) as usize
+                    {
+                        return Err(anyhow!(
+                            "layer {} has {} cells, but it's {}x{}",
+                            layer.identifier,
+                            layer.int_grid_csv.len(),
+                            layer.c_wid,
+                            layer.c_hei
+                        ));
+                    }
+                    for (i, value) in layer.int_grid_csv.iter().enumerate() {
+                        if *value == 0 || !is_wall(values, *value) {
+                            continue;
+                        }
+                        let i = i as u32;
+                        let coords = TileCoords(
+                            (i % layer.c_wid) as i32,
+                            rows as i32 - 1 - (i / layer.c_wid) as i32,
+                        );
+                        let label = format!("intgrid {}", layer.identifier);
+                        metadata.push((label, coords, TileType::Wall));
+                    }
+                    ms.layers
+                        .extend(layer.tile_layers(tileset_ids, cols, rows, false)?);
+                }
+                "Tiles" | "AutoLayer" => {
+                    ms.layers
+                        .extend(layer.tile_layers(tileset_ids, cols, rows, true)?);
+                }
+                other => return Err(anyhow!("{} layers aren't supported", other)),
+            }
+        }
+
+        if ms.layers.is_empty() {
+            ms.add_layer("ground", false);
+        }
+        for (label, coords, tile_type) in metadata {
+            set_metadata(&mut ms, coords, &tile_type).map_err(|e| anyhow!("{} {}", label, e))?;
+        }
+        Ok(ms)
+    }
+}
+
+impl LdtkLayer {
+    // ldtk can stack several tiles in one cell of a layer. the ones that
+    // don't fit go on extra layers above it.
+    fn tile_layers(
+        &self,
+        tileset_ids: &HashMap<u32, u32>,
+        cols: u32,
+        rows: u32,
+        keep_empty: bool,
+    ) -> Result<Vec<TileLayer>> {
+        let mut grids = vec![];
+        if keep_empty {
+            grids.push(TileGrid::new(cols, rows));
+        }
+        let tiles: Vec<&LdtkTile> = self
+            .grid_tiles
+            .iter()
+            .chain(&self.auto_layer_tiles)
+            .collect();
+        if !tiles.is_empty() {
+            let uid = self.override_tileset_uid.or(self.tileset_def_uid);
+            let tileset_id = *uid.and_then(|uid| tileset_ids.get(&uid)).ok_or_else(|| {
+                anyhow!(
+                    "layer {} doesn't use a tileset with an image",
+                    self.identifier
+                )
+            })?;
+            for tile in tiles {
+                let grid = self.grid_size as i32;
+                let coords = TileCoords(
+                    tile.px[0].div_euclid(grid),
+                    rows as i32 - 1 - tile.px[1].div_euclid(grid),
+                );
+                if tile.f != 0 {
+                    return Err(anyhow!(
+                        "layer {} flips the tile at {}, which isn't supported",
+                        self.identifier,
+                        coords
+                    ));
+                }
+                let tile = MapTile {
+                    tileset_id,
+                    tile_index: tile.t,
+                    metadata: None,
+                };
+                match grids
+                    .iter_mut()
+                    .find(|g: &&mut TileGrid| g.get(coords).is_none())
+                {
+                    Some(g) => g.set(coords, tile)?,
+                    None => {
+                        let mut g = TileGrid::new(cols, rows);
+                        g.set(coords, tile)?;
+                        grids.push(g);
+                        None
+                    }
+                };
+            }
+        }
+
+        Ok(grids
+            .into_iter()
+            .enumerate()
+            .map(|(i, tiles)| TileLayer {
+                name: match i {
+                    0 => self.identifier.clone(),
+                    i => format!("{}_{}", self.identifier, i + 1),
+                },
+                overhead: false,
+                visible: self.visible,
+                opacity: self.opacity,
+                tiles,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tilemap::MapTile;
+
+    fn test_map() -> Result<MapScreen> {
+        let mut ms = MapScreen::new(2, 3, Some("forest edge"));
+        ms.add_tileset("tiles/forest1.png".into(), 16, 16);
+        ms.add_tileset("tiles/town.png".into(), 8, 8);
+        let tile = |tileset_id, tile_index, metadata| MapTile {
+            tileset_id,
+            tile_index,
+            metadata,
+        };
+        ms.set(0, TileCoords(0, 1), tile(0, 17, Some(TileType::Wall)))?;
+        ms.set(0, TileCoords(2, 0), tile(1, 3, None))?;
+        let top = ms.add_layer("trees", true);
+        ms.set(
+            top,
+            TileCoords(1, 0),
+            tile(
+                0,
+                5,
+                Some(TileType::Enemy {
+                    kind: "slime".to_owned(),
+                    level: 2,
+                }),
+            ),
+        )?;
+        Ok(ms)
+    }
+
+    #[test]
+    fn exports_ldtk_test() -> Result<()> {
+        let ms = test_map()?;
+        let ldtk = to_ldtk(&ms, &HashMap::new(), (16, 16), Path::new(".."))?;
+        let level = &ldtk["levels"][0];
+        assert_eq!(level["identifier"], "Forest_edge");
+        assert_eq!(level["iid"], ms.map_id.to_string());
+        assert_eq!(level["fieldInstances"][0]["__value"], "forest edge");
+        assert_eq!(ldtk["defs"]["tilesets"][1]["relPath"], "../tiles/town.png");
+
+        // metadata, then trees, then the ground split by tileset
+        let layers = level["layerInstances"]
+            .as_array()
+            .ok_or_else(|| anyhow!("the level has no layers"))?;
+        let names: Vec<_> = layers.iter().map(|l| l["__identifier"].clone()).collect();
+        assert_eq!(names, vec!["Metadata", "Trees", "Ground_0", "Ground_1"]);
+
+        let wall = &layers[2]["gridTiles"][0];
+        assert_eq!(wall["px"], json!([0, 0]));
+        assert_eq!(wall["src"], json!([16, 16]));
+        assert_eq!(wall["t"], 17);
+
+        let entities = layers[0]["entityInstances"]
+            .as_array()
+            .ok_or_else(|| anyhow!("the metadata layer has no entities"))?;
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[0]["__identifier"], "Wall");
+        assert_eq!(entities[0]["__grid"], json!([0, 0]));
+        assert_eq!(entities[1]["__identifier"], "Enemy");
+        assert_eq!(entities[1]["__grid"], json!([1, 1]));
+        assert_eq!(entities[1]["fieldInstances"][1]["__value"], 2);
+
+        // the same map exports the same way every time
+        assert_eq!(
+            ldtk,
+            to_ldtk(&ms, &HashMap::new(), (16, 16), Path::new(".."))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ldtk_needs_square_tiles_test() -> Result<()> {
+        let ms = test_map()?;
+        assert!(to_ldtk(&ms, &HashMap::new(), (16, 8), Path::new("")).is_err());
+        Ok(())
+    }
+
+    fn import_value(ldtk: Value) -> Result<Vec<MapScreen>> {
+        from_project(
+            serde_json::from_value(ldtk)?,
+            Path::new("assets/maps"),
+            Path::new("assets"),
+        )
+    }
+
+    // (coords, tileset, index, metadata), in a stable order
+    fn cells(ms: &MapScreen) -> Vec<(i32, i32, u32, u32, Option<TileType>)> {
+        let mut cells: Vec<_> = ms
+            .tiles()
+            .map(|(c, t)| (c.0, c.1, t.tileset_id, t.tile_index, t.metadata.clone()))
+            .collect();
+        cells.sort_by_key(|c| (c.0, c.1, c.2, c.3));
+        cells
+    }
+
+    #[test]
+    fn ldtk_round_trip_test() -> Result<()> {
+        let ms = test_map()?;
+        let maps = import_value(to_ldtk(&ms, &HashMap::new(), (16, 16), Path::new(".."))?)?;
+        assert_eq!(maps.len(), 1);
+        let imported = &maps[0];
+        assert_eq!(imported.map_id, ms.map_id);
+        assert_eq!(imported.map_name, "forest edge");
+        assert_eq!(
+            (imported.tile_cols, imported.tile_rows),
+            (ms.tile_cols, ms.tile_rows)
+        );
+        assert_eq!(imported.tilesets, ms.tilesets);
+        assert_eq!(cells(imported), cells(&ms));
+
+        // the ground comes back in one layer per tileset, below the trees
+        let names: Vec<_> = imported.layers.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["Ground_1", "Ground_0", "Trees"]);
+        Ok(())
+    }
+
+    #[test]
+    fn imports_intgrid_and_entities_test() -> Result<()> {
+        let north = uuid::Uuid::new_v4();
+        let tile = |x: i32, y: i32, t: u32| json!({ "px": [x * 8, y * 8], "t": t, "f": 0 });
+        let layer = |name: &str, kind: &str, def: u32| {
+            json!({
+                "__identifier": name,
+                "__type": kind,
+                "__cWid": 3,
+                "__cHei": 2,
+                "__gridSize": 8,
+                "__opacity": 1,
+                "__tilesetDefUid": 10,
+                "__pxTotalOffsetX": 0,
+                "__pxTotalOffsetY": 0,
+                "layerDefUid": def,
+                "visible": true,
+                "overrideTilesetUid": null,
+            })
+        };
+        let mut entities = layer("Things", "Entities", 3);
+        entities["entityInstances"] = json!([
+            {
+                "__identifier": "Npc",
+                "__pivot": [0.5, 1],
+                "px": [12, 16],
+                "width": 8,
+                "height": 8,
+                "fieldInstances": [{ "__identifier": "npc_id", "__value": "miller" }],
+            },
+            {
+                "__identifier": "PlayerStart",
+                "__pivot": [0, 0],
+                "px": [0, 0],
+                "width": 8,
+                "height": 8,
+            },
+        ]);
+        let mut walls = layer("Walls", "IntGrid", 1);
+        walls["intGridCsv"] = json!([1, 0, 0, 0, 0, 2]);
+        walls["autoLayerTiles"] = json!([tile(0, 0, 4), tile(2, 1, 5), tile(2, 1, 6)]);
+        let mut ground = layer("Ground", "Tiles", 2);
+        ground["gridTiles"] = json!([tile(1, 1, 1)]);
+
+        let ldtk = json!({
+            "externalLevels": false,
+            "defs": {
+                "layers": [
+                    { "uid": 1, "intGridValues": [
+                        { "value": 1, "identifier": null },
+                        { "value": 2, "identifier": "water" },
+                    ] },
+                    { "uid": 2 },
+                    { "uid": 3 },
+                ],
+                "tilesets": [
+                    { "uid": 9, "relPath": null, "__cWid": 4, "__cHei": 4 },
+                    { "uid": 10, "relPath": "../tiles/town.png", "__cWid": 8, "__cHei": 8 },
+                ],
+            },
+            "levels": [{
+                "identifier": "Town",
+                "iid": uuid::Uuid::nil().to_string(),
+                "pxWid": 24,
+                "pxHei": 16,
+                "layerInstances": [entities, walls, ground],
+                "__neighbours": [{ "dir": "n", "levelIid": north.to_string() }],
+            }],
+        });
+
+        let ms = import_value(ldtk.clone())?.remove(0);
+        assert_eq!(ms.map_name, "Town");
+        assert_eq!(ms.neighbors.north, Some(north));
+        assert_eq!(ms.tilesets.len(), 1);
+        assert_eq!(ms.tilesets[0].path, Path::new("tiles/town.png"));
+        assert_eq!((ms.tile_cols, ms.tile_rows), (3, 2));
+
+        // ground, then the intgrid's tiles with the stacked one on top
+        let names: Vec<_> = ms.layers.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["Ground", "Walls", "Walls_2"]);
+        assert_eq!(ms.get(2, TileCoords(2, 0)).map(|t| t.tile_index), Some(6));
+
+        // value 1 is a wall, water isn't
+        assert_eq!(ms.metadata_at(TileCoords(0, 1)), Some(&TileType::Wall));
+        assert_eq!(ms.metadata_at(TileCoords(2, 0)), None);
+        assert_eq!(
+            ms.metadata_at(TileCoords(1, 0)),
+            Some(&TileType::NPC {
+                npc_id: "miller".to_owned()
+            })
+        );
+
+        // an intgrid that doesn't fill its layer is a broken file
+        let mut short = ldtk.clone();
+        short["levels"][0]["layerInstances"][1]["intGridCsv"] = json!([1, 0, 0]);
+        assert!(import_value(short).is_err());
+        let mut no_grid = ldtk.clone();
+        for layer in 0..3 {
+            no_grid["levels"][0]["layerInstances"][layer]["__gridSize"] = json!(0);
+        }
+        assert!(import_value(no_grid).is_err());
+        let mut empty = ldtk;
+        empty["levels"][0]["layerInstances"][1]["__cWid"] = json!(0);
+        empty["levels"][0]["layerInstances"][1]["intGridCsv"] = json!([]);
+        assert!(import_value(empty).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn identifier_test() {
+        assert_eq!(identifier("ground"), "Ground");
+        assert_eq!(identifier("big tree-2"), "Big_tree_2");
+        assert_eq!(identifier("2nd"), "_2nd");
+    }
+}
@@ -0,0 +1,690 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde::{de::IgnoredAny, Deserialize, Deserializer};
+
+use crate::lighting::FULL_LIGHT;
+
+use super::{
+    Diagnostic, MapScreen, Neighbors, TileCoords, TileDesc, TileGrid, TileLayer, TileType, Tileset,
+    DEFAULT_MAP_COLS, DEFAULT_MAP_ROWS,
+};
+
+pub const CURRENT_FORMAT_VERSION: u32 = 5;
+
+// just enough of a map file to figure out which layout it uses. files
+// written before `format_version` existed are told apart by their shape.
+#[derive(Debug, Deserialize)]
+#[serde(rename = "MapScreen")]
+struct VersionProbe {
+    #[serde(default, deserialize_with = "present")]
+    format_version: Option<u32>,
+    #[serde(default, deserialize_with = "present")]
+    data: Option<IgnoredAny>,
+}
+
+// ron wants `Some(..)` around optional values, but these fields are written
+// bare; a field that shows up at all is `Some`
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+// version 0: tiles live in `data`, addressed by sheet (row, col) and a 3d
+// screen position, and the map has no name or id
+#[derive(Debug, Deserialize)]
+#[serde(rename = "MapScreen")]
+struct MapScreenV0 {
+    tile_map: PathBuf,
+    tile_rows: u32,
+    tile_cols: u32,
+    data: Vec<TileDescV0>,
+    // not in the file, see `content_id`
+    #[serde(skip)]
+    map_id: uuid::Uuid,
+}
+
+// an id for a map saved before maps had one. the same file always gets the
+// same id, so a world can list it and its save data is kept, even though
+// loading it as an asset never writes the upgrade back. fnv-1a from two
+// starting points, which unlike std's hasher can't change between builds.
+fn content_id(contents: &str) -> uuid::Uuid {
+    let fnv = |basis: u64| {
+        contents
+            .bytes()
+            .fold(basis, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+    };
+    uuid::Uuid::from_u64_pair(fnv(0xcbf29ce484222325), fnv(0x84222325cbf29ce4))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename = "TileDesc")]
+struct TileDescV0 {
+    tile_source: (u32, u32),
+    screen_pos: (i32, i32, i32),
+    #[serde(default)]
+    metadata: Option<TileTypeV1>,
+}
+
+// version 1: `tile_data` with `tile_index`/`x`/`y`, but no `format_version`
+#[derive(Debug, Deserialize)]
+#[serde(rename = "MapScreen")]
+struct MapScreenV1 {
+    map_name: String,
+    map_id: uuid::Uuid,
+    tile_map: Option<PathBuf>,
+    tile_rows: u32,
+    tile_cols: u32,
+    tile_data: Vec<TileDescV1>,
+}
+
+// version 2: adds `format_version`, tiles still in a single `tile_data`
+#[derive(Debug, Deserialize)]
+#[serde(rename = "MapScreen")]
+struct MapScreenV2 {
+    map_name: String,
+    map_id: uuid::Uuid,
+    tile_map: Option<PathBuf>,
+    tile_rows: u32,
+    tile_cols: u32,
+    tile_data: Vec<TileDescV1>,
+}
+
+// version 3: tiles split into layers, still drawing from a single sheet
+// whose size is given by `tile_rows`/`tile_cols`
+#[derive(Debug, Deserialize)]
+#[serde(rename = "MapScreen")]
+struct MapScreenV3 {
+    map_name: String,
+    map_id: uuid::Uuid,
+    tile_map: Option<PathBuf>,
+    tile_rows: u32,
+    tile_cols: u32,
+    layers: Vec<TileLayerV3>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename = "TileLayer")]
+struct TileLayerV3 {
+    name: String,
+    overhead: bool,
+    visible: bool,
+    opacity: f32,
+    tile_data: Vec<TileDescV1>,
+}
+
+// version 4: multiple tilesets, `tile_rows`/`tile_cols` size the map
+#[derive(Debug, Deserialize)]
+#[serde(rename = "MapScreen")]
+struct MapScreenV4 {
+    map_name: String,
+    map_id: uuid::Uuid,
+    tilesets: Vec<Tileset>,
+    tile_rows: u32,
+    tile_cols: u32,
+    layers: Vec<TileLayerV4>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename = "TileLayer")]
+struct TileLayerV4 {
+    name: String,
+    overhead: bool,
+    visible: bool,
+    opacity: f32,
+    tile_data: Vec<TileDescV4>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename = "TileDesc")]
+struct TileDescV4 {
+    tileset_id: u32,
+    tile_index: u32,
+    x: i32,
+    y: i32,
+    metadata: Option<TileTypeV1>,
+}
+
+// up to version 4 the tile types were bare markers. variant names have to
+// match what's in the files.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename = "TileType")]
+enum TileTypeV1 {
+    Wall,
+    Door,
+    Item,
+    Enemy,
+    NPC,
+}
+
+// shared by versions 1 through 3
+#[derive(Debug, Deserialize)]
+#[serde(rename = "TileDesc")]
+struct TileDescV1 {
+    tile_index: u32,
+    x: i32,
+    y: i32,
+    metadata: Option<TileTypeV1>,
+}
+
+impl From<MapScreenV0> for MapScreenV1 {
+    fn from(ms: MapScreenV0) -> Self {
+        let tile_cols = ms.tile_cols;
+        MapScreenV1 {
+            map_name: String::default(),
+            map_id: ms.map_id,
+            tile_map: Some(ms.tile_map),
+            tile_rows: ms.tile_rows,
+            tile_cols: ms.tile_cols,
+            tile_data: ms
+                .data
+                .into_iter()
+                .map(|t| TileDescV1 {
+                    tile_index: t.tile_source.1 + (t.tile_source.0 * tile_cols),
+                    x: t.screen_pos.0,
+                    y: t.screen_pos.1,
+                    metadata: t.metadata,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<MapScreenV1> for MapScreenV2 {
+    fn from(ms: MapScreenV1) -> Self {
+        MapScreenV2 {
+            map_name: ms.map_name,
+            map_id: ms.map_id,
+            tile_map: ms.tile_map,
+            tile_rows: ms.tile_rows,
+            tile_cols: ms.tile_cols,
+            tile_data: ms.tile_data,
+        }
+    }
+}
+
+// everything moves onto a single ground layer
+impl From<MapScreenV2> for MapScreenV3 {
+    fn from(ms: MapScreenV2) -> Self {
+        MapScreenV3 {
+            map_name: ms.map_name,
+            map_id: ms.map_id,
+            tile_map: ms.tile_map,
+            tile_rows: ms.tile_rows,
+            tile_cols: ms.tile_cols,
+            layers: vec![TileLayerV3 {
+                name: "ground".to_owned(),
+                overhead: false,
+                visible: true,
+                opacity: 1.0,
+                tile_data: ms.tile_data,
+            }],
+        }
+    }
+}
+
+// the single sheet becomes tileset 0, and `tile_rows`/`tile_cols` now size
+// the map itself, grown if needed to fit every placed tile
+impl From<MapScreenV3> for MapScreenV4 {
+    fn from(ms: MapScreenV3) -> Self {
+        let tiles = ms.layers.iter().flat_map(|l| l.tile_data.iter());
+        let max_x = tiles.clone().map(|t| t.x + 1).max().unwrap_or(0);
+        let max_y = tiles.map(|t| t.y + 1).max().unwrap_or(0);
+
+        MapScreenV4 {
+            map_name: ms.map_name,
+            map_id: ms.map_id,
+            tilesets: ms
+                .tile_map
+                .map(|path| Tileset {
+                    id: 0,
+                    path,
+                    columns: ms.tile_cols,
+                    rows: ms.tile_rows,
+                })
+                .into_iter()
+                .collect(),
+            tile_rows: DEFAULT_MAP_ROWS.max(max_y as u32),
+            tile_cols: DEFAULT_MAP_COLS.max(max_x as u32),
+            layers: ms
+                .layers
+                .into_iter()
+                .map(|l| TileLayerV4 {
+                    name: l.name,
+                    overhead: l.overhead,
+                    visible: l.visible,
+                    opacity: l.opacity,
+                    tile_data: l
+                        .tile_data
+                        .into_iter()
+                        .map(|t| TileDescV4 {
+                            tileset_id: 0,
+                            tile_index: t.tile_index,
+                            x: t.x,
+                            y: t.y,
+                            metadata: t.metadata,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+// marker types gain their payloads, filled with placeholders that point
+// nowhere until someone edits them
+impl From<TileTypeV1> for TileType {
+    fn from(tt: TileTypeV1) -> Self {
+        match tt {
+            TileTypeV1::Wall => TileType::Wall,
+            TileTypeV1::Door => TileType::Door {
+                target_map: uuid::Uuid::nil(),
+                target: TileCoords(0, 0),
+            },
+            TileTypeV1::Item => TileType::Item {
+                item_id: String::default(),
+            },
+            TileTypeV1::Enemy => TileType::Enemy {
+                kind: String::default(),
+                level: 0,
+            },
+            TileTypeV1::NPC => TileType::NPC {
+                npc_id: String::default(),
+            },
+        }
+    }
+}
+
+// the grid holds one tile per cell, so where older maps stacked tiles the
+// last one is kept and the cell is reported
+impl TryFrom<MapScreenV4> for MapScreen {
+    type Error = anyhow::Error;
+
+    fn try_from(ms: MapScreenV4) -> Result<Self> {
+        let mut layers = vec![];
+        let mut upgrade_warnings = vec![];
+        for (index, l) in ms.layers.into_iter().enumerate() {
+            let descs = l
+                .tile_data
+                .into_iter()
+                .map(|t| TileDesc {
+                    tileset_id: t.tileset_id,
+                    tile_index: t.tile_index,
+                    x: t.x,
+                    y: t.y,
+                    metadata: t.metadata.map(TileType::from),
+                })
+                .collect();
+            let (tiles, doubled) =
+                TileGrid::from_descs(descs).map_err(|e| anyhow!("layer {}: {}", l.name, e))?;
+            upgrade_warnings.extend(doubled.into_iter().map(|coords| {
+                Diagnostic::warning("more than one tile was here, only the last was kept".into())
+                    .at(Some(index), coords)
+            }));
+            layers.push(TileLayer {
+                tiles,
+                name: l.name,
+                overhead: l.overhead,
+                visible: l.visible,
+                opacity: l.opacity,
+            });
+        }
+
+        Ok(MapScreen {
+            format_version: CURRENT_FORMAT_VERSION,
+            map_name: ms.map_name,
+            map_id: ms.map_id,
+            tilesets: ms.tilesets,
+            tile_rows: ms.tile_rows,
+            tile_cols: ms.tile_cols,
+            neighbors: Neighbors::default(),
+            fog_of_war: false,
+            ambient_light: FULL_LIGHT,
+            lights: vec![],
+            changes: vec![],
+            tileset_images: vec![],
+            tileset_metas: HashMap::new(),
+            upgrade_warnings,
+            layers,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum VersionedMap {
+    V0(MapScreenV0),
+    V1(MapScreenV1),
+    V2(MapScreenV2),
+    V3(MapScreenV3),
+    V4(MapScreenV4),
+    Current(Box<MapScreen>),
+}
+
+pub fn detect_version(contents: &str) -> Result<u32> {
+    let probe: VersionProbe = ron::from_str(contents)?;
+    Ok(match probe.format_version {
+        Some(v) => v,
+        None if probe.data.is_some() => 0,
+        None => 1,
+    })
+}
+
+// parses a map in any known layout and walks it forward one version at a
+// time. returns the upgraded map, with every layer sized to the map, along
+// with the version it was stored as.
+pub fn upgrade(contents: &str) -> Result<(MapScreen, u32)> {
+    let version = detect_version(contents)?;
+    let mut doc = match version {
+        0 => {
+            let mut ms: MapScreenV0 = ron::from_str(contents)?;
+            ms.map_id = content_id(contents);
+            VersionedMap::V0(ms)
+        }
+        1 => VersionedMap::V1(ron::from_str(contents)?),
+        2 => VersionedMap::V2(ron::from_str(contents)?),
+        3 => VersionedMap::V3(ron::from_str(contents)?),
+        4 => VersionedMap::V4(ron::from_str(contents)?),
+        CURRENT_FORMAT_VERSION => VersionedMap::Current(Box::new(ron::from_str(contents)?)),
+        v => {
+            return Err(anyhow!(
+                "map format version {} is not supported (newest known is {})",
+                v,
+                CURRENT_FORMAT_VERSION
+            ))
+        }
+    };
+
+    loop {
+        doc = match doc {
+            VersionedMap::V0(ms) => VersionedMap::V1(ms.into()),
+            VersionedMap::V1(ms) => VersionedMap::V2(ms.into()),
+            VersionedMap::V2(ms) => VersionedMap::V3(ms.into()),
+            VersionedMap::V3(ms) => VersionedMap::V4(ms.into()),
+            VersionedMap::V4(ms) => VersionedMap::Current(Box::new(ms.try_into()?)),
+            VersionedMap::Current(mut ms) => {
+                let (cols, rows) = (ms.tile_cols, ms.tile_rows);
+                ms.resize(cols, rows)?;
+                return Ok((*ms, version));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::MapTile;
+    use super::*;
+
+    const V0_MAP: &str = r#"MapScreen(
+  tile_map: "tiles/forest1.png",
+  tile_rows: 16,
+  tile_cols: 16,
+  data: [
+    TileDesc(
+      tile_source: (1, 2),
+      screen_pos: (3, 17, 0),
+      metadata: Some(Wall),
+    ),
+    TileDesc(
+      tile_source: (0, 1),
+      screen_pos: (4, 17, 0)
+    ),
+  ]
+)"#;
+
+    const V1_MAP: &str = r#"MapScreen(
+  map_name: "forest",
+  map_id: "67e55044-10b1-426f-9247-bb680e5fe0c8",
+  tile_map: Some("tiles/forest1.png"),
+  tile_rows: 16,
+  tile_cols: 16,
+  tile_data: [
+    TileDesc(tile_index: 18, x: 3, y: 17, metadata: Some(Wall)),
+  ]
+)"#;
+
+    #[test]
+    fn detects_versions() -> Result<()> {
+        assert_eq!(detect_version(V0_MAP)?, 0);
+        assert_eq!(detect_version(V1_MAP)?, 1);
+        let current = ron::to_string(&MapScreen::default())?;
+        assert_eq!(detect_version(&current)?, CURRENT_FORMAT_VERSION);
+        Ok(())
+    }
+
+    #[test]
+    fn upgrades_v0() -> Result<()> {
+        let (ms, from) = upgrade(V0_MAP)?;
+        assert_eq!(from, 0);
+        assert_eq!(ms.format_version, CURRENT_FORMAT_VERSION);
+        assert_eq!(ms.tilesets.len(), 1);
+        assert_eq!(ms.tilesets[0].path, PathBuf::from("tiles/forest1.png"));
+        assert_eq!((ms.tilesets[0].columns, ms.tilesets[0].rows), (16, 16));
+        assert_eq!((ms.tile_cols, ms.tile_rows), (24, 18));
+        assert_eq!(ms.layers.len(), 1);
+        let tiles = &ms.layers[0].tiles;
+        assert_eq!(tiles.len(), 2);
+        assert_eq!((tiles.cols(), tiles.rows()), (24, 18));
+        let wall = tiles.get(TileCoords(3, 17)).expect("tile at 3,17");
+        assert_eq!(wall.tile_index, 18);
+        assert_eq!(wall.metadata, Some(TileType::Wall));
+        let plain = tiles.get(TileCoords(4, 17)).expect("tile at 4,17");
+        assert_eq!(plain.tile_index, 1);
+        assert_eq!(plain.metadata, None);
+
+        // the id comes from the file, so it's the same every time it loads
+        let (again, _) = upgrade(V0_MAP)?;
+        assert_eq!(again.map_id, ms.map_id);
+        assert_ne!(ms.map_id, uuid::Uuid::nil());
+        let (other, _) = upgrade(&V0_MAP.replace("(0, 1)", "(0, 2)"))?;
+        assert_ne!(other.map_id, ms.map_id);
+        Ok(())
+    }
+
+    #[test]
+    fn upgrades_v1() -> Result<()> {
+        let (ms, from) = upgrade(V1_MAP)?;
+        assert_eq!(from, 1);
+        assert_eq!(ms.map_name, "forest");
+        assert_eq!(
+            ms.map_id.to_string(),
+            "67e55044-10b1-426f-9247-bb680e5fe0c8"
+        );
+        assert_eq!(ms.layers[0].name, "ground");
+        assert_eq!(ms.layers[0].tiles.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn upgrades_v2() -> Result<()> {
+        let v2 = V1_MAP.replace("MapScreen(", "MapScreen(\n  format_version: 2,");
+        let (ms, from) = upgrade(&v2)?;
+        assert_eq!(from, 2);
+        assert_eq!(ms.layers.len(), 1);
+        assert!(ms.layers[0].visible);
+        assert_eq!(ms.get(0, TileCoords(3, 17)).map(|t| t.tile_index), Some(18));
+        Ok(())
+    }
+
+    #[test]
+    fn upgrades_v3() -> Result<()> {
+        let v3 = r#"MapScreen(
+  format_version: 3,
+  map_name: "forest",
+  map_id: "67e55044-10b1-426f-9247-bb680e5fe0c8",
+  tile_map: Some("tiles/forest1.png"),
+  tile_rows: 16,
+  tile_cols: 16,
+  layers: [
+    TileLayer(
+      name: "ground",
+      overhead: false,
+      visible: true,
+      opacity: 1.0,
+      tile_data: [
+        TileDesc(tile_index: 18, x: 30, y: 17, metadata: Some(Wall)),
+      ],
+    ),
+  ]
+)"#;
+        let (ms, from) = upgrade(v3)?;
+        assert_eq!(from, 3);
+        assert_eq!(ms.tilesets[0].id, 0);
+        assert_eq!((ms.tile_cols, ms.tile_rows), (31, 18));
+        let tile = ms.get(0, TileCoords(30, 17)).expect("tile at 30,17");
+        assert_eq!((tile.tileset_id, tile.tile_index), (0, 18));
+        Ok(())
+    }
+
+    #[test]
+    fn upgrades_v4_markers() -> Result<()> {
+        let v4 = r#"MapScreen(
+  format_version: 4,
+  map_name: "forest",
+  map_id: "67e55044-10b1-426f-9247-bb680e5fe0c8",
+  tilesets: [Tileset(id: 0, path: "tiles/forest1.png", columns: 16, rows: 16)],
+  tile_rows: 18,
+  tile_cols: 24,
+  layers: [
+    TileLayer(
+      name: "ground",
+      overhead: false,
+      visible: true,
+      opacity: 1.0,
+      tile_data: [
+        TileDesc(tileset_id: 0, tile_index: 18, x: 3, y: 17, metadata: Some(Wall)),
+        TileDesc(tileset_id: 0, tile_index: 19, x: 4, y: 17, metadata: Some(Door)),
+        TileDesc(tileset_id: 0, tile_index: 20, x: 5, y: 17, metadata: Some(Enemy)),
+      ],
+    ),
+  ]
+)"#;
+        let (ms, from) = upgrade(v4)?;
+        assert_eq!(from, 4);
+        let tiles: Vec<_> = ms.tiles().map(|(_, t)| t).collect();
+        assert_eq!(tiles[0].metadata, Some(TileType::Wall));
+        assert_eq!(
+            tiles[1].metadata,
+            Some(TileType::Door {
+                target_map: uuid::Uuid::nil(),
+                target: TileCoords(0, 0),
+            })
+        );
+        assert!(matches!(
+            tiles[2].metadata,
+            Some(TileType::Enemy { level: 0, .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn current_round_trips() -> Result<()> {
+        let (mut ms, _) = upgrade(V1_MAP)?;
+        let door = TileType::Door {
+            target_map: ms.map_id,
+            target: TileCoords(2, 3),
+        };
+        ms.set(
+            0,
+            TileCoords(1, 1),
+            MapTile {
+                tileset_id: 0,
+                tile_index: 4,
+                metadata: Some(door.clone()),
+            },
+        )?;
+        let saved = ron::to_string(&ms)?;
+        let (reloaded, from) = upgrade(&saved)?;
+        assert_eq!(from, CURRENT_FORMAT_VERSION);
+        assert_eq!(reloaded.map_id, ms.map_id);
+        assert_eq!(reloaded.metadata_at(TileCoords(1, 1)), Some(&door));
+        Ok(())
+    }
+
+    #[test]
+    fn upgrades_stacked_tiles() -> Result<()> {
+        let v4 = r#"MapScreen(
+  format_version: 4,
+  map_name: "forest",
+  map_id: "67e55044-10b1-426f-9247-bb680e5fe0c8",
+  tilesets: [],
+  tile_rows: 2,
+  tile_cols: 2,
+  layers: [
+    TileLayer(
+      name: "ground",
+      overhead: false,
+      visible: true,
+      opacity: 1.0,
+      tile_data: [
+        TileDesc(tileset_id: 0, tile_index: 18, x: 1, y: 1, metadata: Some(Wall)),
+        TileDesc(tileset_id: 0, tile_index: 19, x: 1, y: 1, metadata: None),
+      ],
+    ),
+  ]
+)"#;
+        let (ms, _) = upgrade(v4)?;
+        let tile = ms
+            .get(0, TileCoords(1, 1))
+            .ok_or_else(|| anyhow!("the stacked tile is gone"))?;
+        assert_eq!((tile.tile_index, tile.metadata.clone()), (19, None));
+        assert_eq!(ms.upgrade_warnings.len(), 1);
+        let warning = &ms.upgrade_warnings[0];
+        assert!(!warning.is_error());
+        assert_eq!(
+            (warning.layer, warning.coords),
+            (Some(0), Some(TileCoords(1, 1)))
+        );
+
+        // and it's reported with everything else
+        let settings = crate::settings::GameSettings::new_from_sf(&Default::default(), false);
+        assert!(ms
+            .validate(&settings, std::path::Path::new(""))
+            .contains(warning));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_tiles_outside_the_map() {
+        let v4 = r#"MapScreen(
+  format_version: 4,
+  map_name: "forest",
+  map_id: "67e55044-10b1-426f-9247-bb680e5fe0c8",
+  tilesets: [],
+  tile_rows: 2,
+  tile_cols: 2,
+  layers: [
+    TileLayer(
+      name: "ground",
+      overhead: false,
+      visible: true,
+      opacity: 1.0,
+      tile_data: [
+        TileDesc(tileset_id: 0, tile_index: 18, x: 3, y: 1, metadata: None),
+      ],
+    ),
+  ]
+)"#;
+        assert!(upgrade(v4).is_err());
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let newer = format!("MapScreen(format_version: {})", CURRENT_FORMAT_VERSION + 1);
+        assert!(upgrade(&newer).is_err());
+    }
+
+    #[test]
+    fn loads_checked_in_maps() -> Result<()> {
+        let (test_map, _) = upgrade(include_str!("../../../game/assets/data/test.map.ron"))?;
+        assert_eq!(test_map.tiles().count(), 21);
+        assert_eq!(test_map.map_name, "test");
+        let (map1, _) = upgrade(include_str!("../../../game/assets/data/map1.map.ron"))?;
+        assert_eq!(map1.tiles().count(), 432);
+        Ok(())
+    }
+}
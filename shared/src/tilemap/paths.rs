@@ -0,0 +1,72 @@
+use std::path::{Component, Path, PathBuf};
+
+// collapses `.` and `..` without touching the disk, so paths can be
+// compared against the assets root
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if matches!(out.components().next_back(), Some(Component::Normal(_))) {
+                    out.pop();
+                } else {
+                    out.push("..");
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// `path` as seen from `base`, for tools that store paths relative to the
+// file they're written in. both should be relative, or both absolute.
+pub fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let path = normalize(path);
+    let base = normalize(base);
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut out = PathBuf::new();
+    for _ in base.components().skip(common) {
+        out.push("..");
+    }
+    for c in path.components().skip(common) {
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_test() {
+        assert_eq!(
+            normalize(Path::new("assets/maps/../tiles/./a.png")),
+            PathBuf::from("assets/tiles/a.png")
+        );
+        assert_eq!(normalize(Path::new("../a.png")), PathBuf::from("../a.png"));
+    }
+
+    #[test]
+    fn relative_to_test() {
+        assert_eq!(
+            relative_to(Path::new("assets/tiles/a.png"), Path::new("assets/maps")),
+            PathBuf::from("../tiles/a.png")
+        );
+        assert_eq!(
+            relative_to(Path::new("assets/a.png"), Path::new("assets")),
+            PathBuf::from("a.png")
+        );
+        assert_eq!(
+            relative_to(Path::new("a.png"), Path::new("")),
+            PathBuf::from("a.png")
+        );
+    }
+}
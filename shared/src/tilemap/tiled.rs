@@ -0,0 +1,1318 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::paths::{normalize, relative_to};
+use super::{
+    MapScreen, MapTile, Neighbors, TileCoords, TileGrid, TileLayer, TileType, Tileset,
+    CURRENT_FORMAT_VERSION,
+};
+use crate::error::MapError;
+use crate::lighting::{Falloff, LightSource, FULL_LIGHT};
+use crate::tileset::TilesetMeta;
+
+// tiled keeps flip and rotation flags in the top bits of each gid
+const FLIP_FLAGS: u32 = 0xf000_0000;
+
+// a tiled map, read from either of its file formats
+#[derive(Debug, Default)]
+struct TiledMap {
+    width: u32,
+    height: u32,
+    tile_width: u32,
+    tile_height: u32,
+    properties: HashMap<String, String>,
+    tilesets: Vec<TiledTileset>,
+    layers: Vec<TiledLayer>,
+}
+
+#[derive(Debug)]
+struct TiledTileset {
+    first_gid: u32,
+    // resolved against the file that mentioned it
+    image: PathBuf,
+    columns: u32,
+    tile_count: u32,
+}
+
+#[derive(Debug)]
+enum TiledLayer {
+    Tiles {
+        name: String,
+        visible: bool,
+        opacity: f32,
+        properties: HashMap<String, String>,
+        gids: Vec<u32>,
+    },
+    Objects {
+        name: String,
+        objects: Vec<TiledObject>,
+    },
+}
+
+// positions are in pixels from the top left of the map
+#[derive(Debug, Default)]
+struct TiledObject {
+    name: String,
+    class: String,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    gid: Option<u32>,
+    properties: HashMap<String, String>,
+}
+
+// reads a map made in tiled, either .tmx or .tmj. tile layers become
+// layers, and objects with a type of wall, door, item, enemy or npc set
+// the metadata of the tile under them. tileset images inside
+// `assets_root` are stored relative to it, like the game expects.
+pub fn import(filename: &Path, assets_root: &Path) -> Result<MapScreen, MapError> {
+    let contents = fs::read_to_string(filename).map_err(|e| MapError::io(filename, e))?;
+    let dir = filename.parent().unwrap_or(Path::new(""));
+    let map = match extension(filename).as_str() {
+        "tmx" => read_tmx(&contents, dir),
+        "tmj" | "json" => read_tmj(&contents, dir),
+        other => Err(anyhow!("{} isn't a tiled map extension", other)),
+    }
+    .map_err(|e| MapError::from_load(filename, e))?;
+
+    let name = filename
+        .file_stem()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    map.into_map_screen(&name, assets_root)
+        .map_err(|e| MapError::from_load(filename, e))
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+impl TiledMap {
+    fn check(&self, orientation: &str, infinite: bool) -> Result<()> {
+        if infinite {
+            return Err(anyhow!(
+                "infinite maps aren't supported, turn off \"Infinite\" in the map properties"
+            ));
+        }
+        if orientation != "orthogonal" {
+            return Err(anyhow!("{} maps aren't supported", orientation));
+        }
+        Ok(())
+    }
+
+    fn into_map_screen(self, name: &str, assets_root: &Path) -> Result<MapScreen> {
+        let mut tilesets: Vec<_> = self.tilesets.iter().enumerate().collect();
+        tilesets.sort_by_key(|(_, ts)| ts.first_gid);
+
+        // gid -> (our tileset id, index in the sheet)
+        let lookup = |gid: u32| -> Result<(u32, u32)> {
+            let (id, ts) = tilesets
+                .iter()
+                .rev()
+                .find(|(_, ts)| ts.first_gid <= gid)
+                .ok_or_else(|| anyhow!("tile {} isn't in any tileset", gid))?;
+            let tile_index = gid - ts.first_gid;
+            if tile_index >= ts.tile_count {
+                return Err(anyhow!("tile {} isn't in any tileset", gid));
+            }
+            Ok((*id as u32, tile_index))
+        };
+
+        let root = normalize(assets_root);
+        let mut ms = MapScreen {
+            format_version: CURRENT_FORMAT_VERSION,
+            map_name: self
+                .properties
+                .get("map_name")
+                .cloned()
+                .unwrap_or_else(|| name.to_owned()),
+            map_id: match self.properties.get("map_id") {
+                Some(id) => id
+                    .parse()
+                    .map_err(|e| anyhow!("map_id {} isn't a uuid: {}", id, e))?,
+                None => uuid::Uuid::new_v4(),
+            },
+            tilesets: self
+                .tilesets
+                .iter()
+                .enumerate()
+                .map(|(id, ts)| Tileset {
+                    id: id as u32,
+                    path: ts
+                        .image
+                        .strip_prefix(&root)
+                        .map(Path::to_path_buf)
+                        .unwrap_or_else(|_| ts.image.clone()),
+                    columns: ts.columns,
+                    rows: ts.tile_count.div_ceil(ts.columns.max(1)),
+                })
+                .collect(),
+            tile_rows: self.height,
+            tile_cols: self.width,
+            neighbors: Neighbors::default(),
+            fog_of_war: self.properties.get("fog_of_war").map(String::as_str) == Some("true"),
+            ambient_light: match self.properties.get("ambient_light") {
+                Some(_) => property(&self.properties, "the map", "ambient_light")?,
+                None => FULL_LIGHT,
+            },
+            lights: vec![],
+            changes: vec![],
+            tileset_images: vec![],
+            tileset_metas: HashMap::new(),
+            upgrade_warnings: vec![],
+            layers: vec![],
+        };
+
+        let mut objects = vec![];
+        for layer in self.layers {
+            match layer {
+                TiledLayer::Tiles {
+                    name,
+                    visible,
+                    opacity,
+                    properties,
+                    gids,
+                } => {
+                    if gids.len() != (self.width * self.height) as usize {
+                        return Err(anyhow!(
+                            "layer {} has {} tiles, but the map is {}x{}",
+                            name,
+                            gids.len(),
+                            self.width,
+                            self.height
+                        ));
+                    }
+                    let mut tiles = TileGrid::new(self.width, self.height);
+                    for (i, gid) in gids.into_iter().enumerate() {
+                        if gid == 0 {
+                            continue;
+                        }
+                        // tiled counts rows down from the top
+                        let i = i as u32;
+                        let coords = TileCoords(
+                            (i % self.width) as i32,
+                            (self.height - 1 - i / self.width) as i32,
+                        );
+                        if gid & FLIP_FLAGS != 0 {
+                            return Err(anyhow!(
+                                "layer {} flips or rotates the tile at {}, which isn't supported",
+                                name,
+                                coords
+                            ));
+                        }
+                        let (tileset_id, tile_index) = lookup(gid)?;
+                        tiles.set(
+                            coords,
+                            MapTile {
+                                tileset_id,
+                                tile_index,
+                                metadata: None,
+                            },
+                        )?;
+                    }
+                    ms.layers.push(TileLayer {
+                        name,
+                        overhead: properties.get("overhead").map(String::as_str) == Some("true"),
+                        visible,
+                        opacity,
+                        tiles,
+                    });
+                }
+                TiledLayer::Objects { name, objects: o } => {
+                    objects.extend(o.into_iter().map(|o| (name.clone(), o)))
+                }
+            }
+        }
+
+        if ms.layers.is_empty() {
+            ms.add_layer("ground", false);
+        }
+
+        // objects are applied once every tile is in place, so an object
+        // layer can sit anywhere in the list
+        for (layer_name, object) in objects {
+            let label = object.label();
+            if object.class.eq_ignore_ascii_case("light") {
+                let light = object
+                    .light(self.tile_width, self.tile_height, self.height)
+                    .map_err(|e| anyhow!("{} in {}: {}", label, layer_name, e))?;
+                ms.lights.push(light);
+                continue;
+            }
+            let Some(tile_type) =
+                tile_type_from_properties(&object.class, &label, &object.properties)?
+            else {
+                continue;
+            };
+            for coords in object.cells(self.tile_width, self.tile_height, self.height) {
+                set_metadata(&mut ms, coords, &tile_type)
+                    .map_err(|e| anyhow!("{} in {} {}", label, layer_name, e))?;
+            }
+        }
+
+        Ok(ms)
+    }
+}
+
+impl TiledObject {
+    fn label(&self) -> String {
+        if self.name.is_empty() {
+            format!("object at {},{}", self.x, self.y)
+        } else {
+            format!("object {}", self.name)
+        }
+    }
+
+    // a light in the cell at the middle of the object
+    fn light(&self, tile_width: u32, tile_height: u32, map_height: u32) -> Result<LightSource> {
+        let top = match self.gid {
+            Some(_) => self.y - self.height,
+            None => self.y,
+        };
+        let coords = covered_cells(
+            (self.x + self.width / 2.0, top + self.height / 2.0),
+            (0.0, 0.0),
+            (tile_width, tile_height),
+            map_height,
+        )[0];
+        let falloff = match self.properties.get("falloff") {
+            Some(falloff) => falloff.parse()?,
+            None => Falloff::default(),
+        };
+        let color = match self.properties.get("color") {
+            Some(color) => parse_color(color)?,
+            None => (1.0, 1.0, 1.0),
+        };
+        Ok(LightSource {
+            coords,
+            radius: property(&self.properties, "light", "radius")?,
+            color,
+            falloff,
+        })
+    }
+
+    // every cell the object touches
+    fn cells(&self, tile_width: u32, tile_height: u32, map_height: u32) -> Vec<TileCoords> {
+        // tile objects hang up from their position rather than down
+        let top = match self.gid {
+            Some(_) => self.y - self.height,
+            None => self.y,
+        };
+        covered_cells(
+            (self.x, top),
+            (self.width, self.height),
+            (tile_width, tile_height),
+            map_height,
+        )
+    }
+}
+
+fn property<T: FromStr>(properties: &HashMap<String, String>, label: &str, name: &str) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    let value = properties
+        .get(name)
+        .ok_or_else(|| anyhow!("{} needs a {} property", label, name))?;
+    value
+        .parse()
+        .map_err(|e| anyhow!("{} on {}: {}", name, label, e))
+}
+
+// tiled writes colours as #aarrggbb, or #rrggbb without alpha. alpha
+// doesn't mean anything for a light, so it's dropped.
+fn parse_color(color: &str) -> Result<(f32, f32, f32)> {
+    let hex = color.trim_start_matches('#');
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(anyhow!("{} isn't a colour", color));
+    }
+    let rgb = match hex.len() {
+        6 => hex,
+        8 => &hex[2..],
+        _ => return Err(anyhow!("{} isn't a colour", color)),
+    };
+    let channel = |i: usize| {
+        u8::from_str_radix(&rgb[i..i + 2], 16)
+            .map(|c| c as f32 / 255.0)
+            .map_err(|_| anyhow!("{} isn't a colour", color))
+    };
+    Ok((channel(0)?, channel(2)?, channel(4)?))
+}
+
+fn color_to_hex(color: (f32, f32, f32)) -> String {
+    let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!(
+        "#ff{:02x}{:02x}{:02x}",
+        channel(color.0),
+        channel(color.1),
+        channel(color.2)
+    )
+}
+
+// the tile type named by `kind`, filled in from string properties the way
+// `tile_type_fields` writes them. an empty kind is no tile type at all.
+pub(super) fn tile_type_from_properties(
+    kind: &str,
+    label: &str,
+    properties: &HashMap<String, String>,
+) -> Result<Option<TileType>> {
+    let tile_type = match kind.to_lowercase().as_str() {
+        "" => return Ok(None),
+        "wall" => TileType::Wall,
+        "door" => TileType::Door {
+            target_map: property(properties, label, "target_map")?,
+            target: TileCoords(
+                property(properties, label, "target_x")?,
+                property(properties, label, "target_y")?,
+            ),
+        },
+        "item" => TileType::Item {
+            item_id: property(properties, label, "item_id")?,
+        },
+        "enemy" => TileType::Enemy {
+            kind: property(properties, label, "kind")?,
+            level: property(properties, label, "level")?,
+        },
+        "npc" => TileType::NPC {
+            npc_id: property(properties, label, "npc_id")?,
+        },
+        other => {
+            return Err(anyhow!(
+                "{} has type {}, which doesn't match a tile type",
+                label,
+                other
+            ))
+        }
+    };
+    Ok(Some(tile_type))
+}
+
+// every cell a box touches, in our coordinates. positions are in pixels
+// from the top left of the map, and a box with no size covers the cell
+// it's in.
+pub(super) fn covered_cells(
+    top_left: (f32, f32),
+    size: (f32, f32),
+    tile_size: (u32, u32),
+    map_height: u32,
+) -> Vec<TileCoords> {
+    let (tw, th) = (tile_size.0 as f32, tile_size.1 as f32);
+    let first_col = (top_left.0 / tw).floor() as i32;
+    let first_row = (top_left.1 / th).floor() as i32;
+    let last_col = (((top_left.0 + size.0) / tw).ceil() as i32 - 1).max(first_col);
+    let last_row = (((top_left.1 + size.1) / th).ceil() as i32 - 1).max(first_row);
+
+    let mut cells = vec![];
+    for row in first_row..=last_row {
+        for col in first_col..=last_col {
+            cells.push(TileCoords(col, map_height as i32 - 1 - row));
+        }
+    }
+    cells
+}
+
+// metadata belongs to the topmost tile in the cell
+pub(super) fn set_metadata(
+    ms: &mut MapScreen,
+    coords: TileCoords,
+    tile_type: &TileType,
+) -> Result<()> {
+    let tile = ms
+        .layers
+        .iter_mut()
+        .rev()
+        .find_map(|l| l.tiles.get_mut(coords))
+        .ok_or_else(|| anyhow!("covers {}, which has no tile", coords))?;
+    tile.metadata = Some(tile_type.clone());
+    Ok(())
+}
+
+// tile data in any of the encodings tiled writes, apart from zstd
+fn decode_gids(data: &str, encoding: Option<&str>, compression: Option<&str>) -> Result<Vec<u32>> {
+    match encoding {
+        Some("csv") => data
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse().map_err(|e| anyhow!("bad tile {}: {}", s, e)))
+            .collect(),
+        Some("base64") => {
+            let bytes = STANDARD.decode(data.trim())?;
+            let bytes = match compression {
+                None | Some("") => bytes,
+                Some("zlib") => {
+                    let mut out = vec![];
+                    ZlibDecoder::new(bytes.as_slice()).read_to_end(&mut out)?;
+                    out
+                }
+                Some("gzip") => {
+                    let mut out = vec![];
+                    GzDecoder::new(bytes.as_slice()).read_to_end(&mut out)?;
+                    out
+                }
+                Some(other) => return Err(anyhow!("{} compressed layers aren't supported", other)),
+            };
+            if bytes.len() % 4 != 0 {
+                return Err(anyhow!("tile data isn't a whole number of tiles"));
+            }
+            Ok(bytes
+                .chunks_exact(4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect())
+        }
+        Some(other) => Err(anyhow!("{} encoded layers aren't supported", other)),
+        None => Err(anyhow!("layer has no tile data")),
+    }
+}
+
+fn read_tmx(contents: &str, dir: &Path) -> Result<TiledMap> {
+    let doc = roxmltree::Document::parse(contents)?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "map" {
+        return Err(anyhow!(
+            "expected a <map>, found <{}>",
+            root.tag_name().name()
+        ));
+    }
+
+    let mut map = TiledMap {
+        width: attr(root, "width")?,
+        height: attr(root, "height")?,
+        tile_width: attr(root, "tilewidth")?,
+        tile_height: attr(root, "tileheight")?,
+        ..TiledMap::default()
+    };
+    map.check(
+        root.attribute("orientation").unwrap_or("orthogonal"),
+        root.attribute("infinite") == Some("1"),
+    )?;
+
+    for node in root.children().filter(|n| n.is_element()) {
+        match node.tag_name().name() {
+            "properties" => map.properties = tmx_properties(node),
+            "tileset" => map.tilesets.push(tmx_tileset(node, dir)?),
+            "layer" => map.layers.push(tmx_layer(node, map.width, map.height)?),
+            "objectgroup" => map.layers.push(tmx_objects(node)?),
+            "imagelayer" => return Err(anyhow!("image layers aren't supported")),
+            "group" => return Err(anyhow!("group layers aren't supported")),
+            _ => {}
+        }
+    }
+    Ok(map)
+}
+
+fn attr<T: FromStr>(node: roxmltree::Node, name: &str) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    let value = node
+        .attribute(name)
+        .ok_or_else(|| anyhow!("<{}> is missing {}", node.tag_name().name(), name))?;
+    value
+        .parse()
+        .map_err(|e| anyhow!("{} on <{}>: {}", name, node.tag_name().name(), e))
+}
+
+fn attr_or<T: FromStr>(node: roxmltree::Node, name: &str, default: T) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    match node.attribute(name) {
+        Some(_) => attr(node, name),
+        None => Ok(default),
+    }
+}
+
+fn child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+fn tmx_properties(node: roxmltree::Node) -> HashMap<String, String> {
+    node.children()
+        .filter(|n| n.has_tag_name("property"))
+        .filter_map(|p| {
+            // multi-line values are written as the element's text
+            let value = p.attribute("value").or_else(|| p.text())?;
+            Some((p.attribute("name")?.to_owned(), value.to_owned()))
+        })
+        .collect()
+}
+
+fn tmx_tileset(node: roxmltree::Node, dir: &Path) -> Result<TiledTileset> {
+    let first_gid = attr(node, "firstgid")?;
+    let Some(source) = node.attribute("source") else {
+        return tmx_tileset_body(node, dir, first_gid);
+    };
+
+    let path = dir.join(source);
+    let contents = fs::read_to_string(&path).map_err(|e| MapError::io(&path, e))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    // a parse error is somewhere in the tileset, not the map
+    let tileset = match extension(&path).as_str() {
+        "tsj" | "json" => {
+            let ts: TmjTileset = serde_json::from_str(&contents)
+                .map_err(|e| MapError::from_load(&path, e.into()))?;
+            ts.into_tileset(first_gid, dir)
+        }
+        _ => {
+            let doc = roxmltree::Document::parse(&contents)
+                .map_err(|e| MapError::from_load(&path, e.into()))?;
+            tmx_tileset_body(doc.root_element(), dir, first_gid)
+        }
+    };
+    tileset.map_err(|e| anyhow!("{}, {}", path.to_string_lossy(), e))
+}
+
+fn tmx_tileset_body(node: roxmltree::Node, dir: &Path, first_gid: u32) -> Result<TiledTileset> {
+    let image = child(node, "image").ok_or_else(|| {
+        anyhow!(
+            "tileset {} is a collection of images, which isn't supported",
+            node.attribute("name").unwrap_or_default()
+        )
+    })?;
+    Ok(TiledTileset {
+        first_gid,
+        image: normalize(&dir.join(attr::<String>(image, "source")?)),
+        columns: attr(node, "columns")?,
+        tile_count: attr(node, "tilecount")?,
+    })
+}
+
+fn tmx_layer(node: roxmltree::Node, width: u32, height: u32) -> Result<TiledLayer> {
+    let name: String = attr_or(node, "name", String::new())?;
+    let data = child(node, "data").ok_or_else(|| anyhow!("layer {} has no data", name))?;
+    if child(data, "chunk").is_some() {
+        return Err(anyhow!(
+            "layer {} is split into chunks, which isn't supported",
+            name
+        ));
+    }
+
+    let gids = match data.attribute("encoding") {
+        // no encoding means one <tile> per cell
+        None => data
+            .children()
+            .filter(|n| n.has_tag_name("tile"))
+            .map(|t| attr_or(t, "gid", 0))
+            .collect::<Result<_>>()?,
+        encoding => decode_gids(
+            data.text().unwrap_or_default(),
+            encoding,
+            data.attribute("compression"),
+        )
+        .map_err(|e| anyhow!("layer {}: {}", name, e))?,
+    };
+
+    let (layer_width, layer_height): (u32, u32) = (
+        attr_or(node, "width", width)?,
+        attr_or(node, "height", height)?,
+    );
+    if (layer_width, layer_height) != (width, height) {
+        return Err(anyhow!(
+            "layer {} is {}x{}, but the map is {}x{}",
+            name,
+            layer_width,
+            layer_height,
+            width,
+            height
+        ));
+    }
+    if attr_or(node, "offsetx", 0.)? != 0. || attr_or(node, "offsety", 0.)? != 0. {
+        return Err(anyhow!("layer {} is offset, which isn't supported", name));
+    }
+
+    Ok(TiledLayer::Tiles {
+        visible: attr_or(node, "visible", 1)? != 0,
+        opacity: attr_or(node, "opacity", 1.)?,
+        properties: child(node, "properties")
+            .map(tmx_properties)
+            .unwrap_or_default(),
+        gids,
+        name,
+    })
+}
+
+fn tmx_objects(node: roxmltree::Node) -> Result<TiledLayer> {
+    let objects = node
+        .children()
+        .filter(|n| n.has_tag_name("object"))
+        .map(|o| {
+            Ok(TiledObject {
+                name: attr_or(o, "name", String::new())?,
+                // tiled 1.9 wrote `class`, before and after that it's `type`
+                class: o
+                    .attribute("type")
+                    .or_else(|| o.attribute("class"))
+                    .unwrap_or_default()
+                    .to_owned(),
+                x: attr(o, "x")?,
+                y: attr(o, "y")?,
+                width: attr_or(o, "width", 0.)?,
+                height: attr_or(o, "height", 0.)?,
+                gid: o.attribute("gid").map(|_| attr(o, "gid")).transpose()?,
+                properties: child(o, "properties")
+                    .map(tmx_properties)
+                    .unwrap_or_default(),
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(TiledLayer::Objects {
+        name: attr_or(node, "name", String::new())?,
+        objects,
+    })
+}
+
+// the .tmj layout. only the parts we use are listed.
+#[derive(Debug, Deserialize)]
+struct TmjMap {
+    width: u32,
+    height: u32,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default = "orthogonal")]
+    orientation: String,
+    #[serde(default)]
+    properties: Vec<TmjProperty>,
+    #[serde(default)]
+    tilesets: Vec<TmjTilesetRef>,
+    #[serde(default)]
+    layers: Vec<TmjLayer>,
+}
+
+fn orthogonal() -> String {
+    "orthogonal".to_owned()
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn one() -> f32 {
+    1.
+}
+
+#[derive(Debug, Deserialize)]
+struct TmjProperty {
+    name: String,
+    value: serde_json::Value,
+}
+
+// either points at a .tsj/.tsx file or holds the tileset itself
+#[derive(Debug, Deserialize)]
+struct TmjTilesetRef {
+    firstgid: u32,
+    source: Option<String>,
+    #[serde(flatten)]
+    tileset: TmjTileset,
+}
+
+#[derive(Debug, Deserialize)]
+struct TmjTileset {
+    #[serde(default)]
+    name: String,
+    image: Option<String>,
+    #[serde(default)]
+    columns: u32,
+    #[serde(default)]
+    tilecount: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TmjData {
+    Gids(Vec<u32>),
+    Encoded(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct TmjLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default = "yes")]
+    visible: bool,
+    #[serde(default = "one")]
+    opacity: f32,
+    data: Option<TmjData>,
+    encoding: Option<String>,
+    compression: Option<String>,
+    chunks: Option<serde::de::IgnoredAny>,
+    #[serde(default)]
+    offsetx: f32,
+    #[serde(default)]
+    offsety: f32,
+    #[serde(default)]
+    objects: Vec<TmjObject>,
+    #[serde(default)]
+    properties: Vec<TmjProperty>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TmjObject {
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type", alias = "class")]
+    class: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    gid: Option<u32>,
+    #[serde(default)]
+    properties: Vec<TmjProperty>,
+}
+
+fn tmj_properties(properties: Vec<TmjProperty>) -> HashMap<String, String> {
+    properties
+        .into_iter()
+        .map(|p| {
+            let value = match p.value {
+                serde_json::Value::String(s) => s,
+                other => other.to_string(),
+            };
+            (p.name, value)
+        })
+        .collect()
+}
+
+impl TmjTileset {
+    fn into_tileset(self, first_gid: u32, dir: &Path) -> Result<TiledTileset> {
+        let image = self.image.ok_or_else(|| {
+            anyhow!(
+                "tileset {} is a collection of images, which isn't supported",
+                self.name
+            )
+        })?;
+        Ok(TiledTileset {
+            first_gid,
+            image: normalize(&dir.join(image)),
+            columns: self.columns,
+            tile_count: self.tilecount,
+        })
+    }
+}
+
+fn read_tmj(contents: &str, dir: &Path) -> Result<TiledMap> {
+    let tmj: TmjMap = serde_json::from_str(contents)?;
+    let mut map = TiledMap {
+        width: tmj.width,
+        height: tmj.height,
+        tile_width: tmj.tilewidth,
+        tile_height: tmj.tileheight,
+        properties: tmj_properties(tmj.properties),
+        ..TiledMap::default()
+    };
+    map.check(&tmj.orientation, tmj.infinite)?;
+
+    for ts in tmj.tilesets {
+        let tileset = match ts.source {
+            Some(source) => {
+                let path = dir.join(source);
+                let contents = fs::read_to_string(&path).map_err(|e| MapError::io(&path, e))?;
+                let ts_dir = path.parent().unwrap_or(Path::new(""));
+                let tileset = match extension(&path).as_str() {
+                    "tsx" => {
+                        let doc = roxmltree::Document::parse(&contents)
+                            .map_err(|e| MapError::from_load(&path, e.into()))?;
+                        tmx_tileset_body(doc.root_element(), ts_dir, ts.firstgid)
+                    }
+                    _ => serde_json::from_str::<TmjTileset>(&contents)
+                        .map_err(|e| MapError::from_load(&path, e.into()))?
+                        .into_tileset(ts.firstgid, ts_dir),
+                };
+                tileset.map_err(|e| anyhow!("{}, {}", path.to_string_lossy(), e))?
+            }
+            None => ts.tileset.into_tileset(ts.firstgid, dir)?,
+        };
+        map.tilesets.push(tileset);
+    }
+
+    for layer in tmj.layers {
+        match layer.kind.as_str() {
+            "tilelayer" => {
+                if layer.chunks.is_some() {
+                    return Err(anyhow!(
+                        "layer {} is split into chunks, which isn't supported",
+                        layer.name
+                    ));
+                }
+                if layer.offsetx != 0. || layer.offsety != 0. {
+                    return Err(anyhow!(
+                        "layer {} is offset, which isn't supported",
+                        layer.name
+                    ));
+                }
+                let gids = match layer.data {
+                    Some(TmjData::Gids(gids)) => gids,
+                    Some(TmjData::Encoded(data)) => decode_gids(
+                        &data,
+                        layer.encoding.as_deref(),
+                        layer.compression.as_deref(),
+                    )
+                    .map_err(|e| anyhow!("layer {}: {}", layer.name, e))?,
+                    None => return Err(anyhow!("layer {} has no data", layer.name)),
+                };
+                map.layers.push(TiledLayer::Tiles {
+                    name: layer.name,
+                    visible: layer.visible,
+                    opacity: layer.opacity,
+                    properties: tmj_properties(layer.properties),
+                    gids,
+                });
+            }
+            "objectgroup" => map.layers.push(TiledLayer::Objects {
+                name: layer.name,
+                objects: layer
+                    .objects
+                    .into_iter()
+                    .map(|o| TiledObject {
+                        name: o.name,
+                        class: o.class,
+                        x: o.x,
+                        y: o.y,
+                        width: o.width,
+                        height: o.height,
+                        gid: o.gid,
+                        properties: tmj_properties(o.properties),
+                    })
+                    .collect(),
+            }),
+            "imagelayer" => return Err(anyhow!("image layers aren't supported")),
+            "group" => return Err(anyhow!("group layers aren't supported")),
+            other => return Err(anyhow!("unknown layer type {}", other)),
+        }
+    }
+    Ok(map)
+}
+
+// the type name and custom properties `import` reads back into `tile_type`
+pub(super) fn tile_type_fields(tile_type: &TileType) -> (&'static str, Vec<(&'static str, Value)>) {
+    match tile_type {
+        TileType::Wall => ("wall", vec![]),
+        TileType::Door { target_map, target } => (
+            "door",
+            vec![
+                ("target_map", json!(target_map.to_string())),
+                ("target_x", json!(target.0)),
+                ("target_y", json!(target.1)),
+            ],
+        ),
+        TileType::Item { item_id } => ("item", vec![("item_id", json!(item_id))]),
+        TileType::Enemy { kind, level } => (
+            "enemy",
+            vec![("kind", json!(kind)), ("level", json!(level))],
+        ),
+        TileType::NPC { npc_id } => ("npc", vec![("npc_id", json!(npc_id))]),
+    }
+}
+
+fn tmj_property(name: &str, value: Value) -> Value {
+    let kind = match value {
+        Value::Bool(_) => "bool",
+        Value::Number(ref n) if n.is_f64() => "float",
+        Value::Number(_) => "int",
+        _ => "string",
+    };
+    json!({ "name": name, "type": kind, "value": value })
+}
+
+// writes the map as a tiled json map. tile metadata goes in an object
+// layer, one object per tile, using the types and properties `import`
+// reads, along with the lights. the map's name, id and lighting are kept
+// as map properties.
+pub fn export(
+    ms: &MapScreen,
+    metas: &HashMap<u32, TilesetMeta>,
+    tile_size: (u32, u32),
+    filename: &Path,
+    assets_root: &Path,
+) -> Result<(), MapError> {
+    let dir = filename.parent().unwrap_or(Path::new(""));
+    let tmj = to_tmj(ms, metas, tile_size, &relative_to(assets_root, dir));
+    let data =
+        serde_json::to_string_pretty(&tmj).map_err(|e| MapError::from_load(filename, e.into()))?;
+    fs::write(filename, data).map_err(|e| MapError::io(filename, e))
+}
+
+// `image_dir` is where tileset paths are relative to, as seen from the
+// map file
+fn to_tmj(
+    ms: &MapScreen,
+    metas: &HashMap<u32, TilesetMeta>,
+    tile_size: (u32, u32),
+    image_dir: &Path,
+) -> Value {
+    let (width, height) = (ms.tile_cols, ms.tile_rows);
+    let default_meta = TilesetMeta::default();
+
+    // gids run on from one tileset to the next
+    let mut first_gids = HashMap::new();
+    let mut next_gid = 1;
+    let mut tilesets = vec![];
+    for ts in &ms.tilesets {
+        let meta = metas.get(&ts.id).unwrap_or(&default_meta);
+        let (tw, th) = meta.tile_size_or(tile_size);
+        let sheet = |count: u32, tile: u32| {
+            meta.margin * 2 + count * tile + count.saturating_sub(1) * meta.spacing
+        };
+        let image = if ts.path.is_absolute() {
+            ts.path.clone()
+        } else {
+            normalize(&image_dir.join(&ts.path))
+        };
+        tilesets.push(json!({
+            "firstgid": next_gid,
+            "name": ts.path.file_stem().map(|n| n.to_string_lossy()).unwrap_or_default(),
+            "image": image.to_string_lossy().replace('\\', "/"),
+            "imagewidth": sheet(ts.columns, tw),
+            "imageheight": sheet(ts.rows, th),
+            "columns": ts.columns,
+            "tilecount": ts.columns * ts.rows,
+            "tilewidth": tw,
+            "tileheight": th,
+            "margin": meta.margin,
+            "spacing": meta.spacing,
+        }));
+        first_gids.insert(ts.id, next_gid);
+        next_gid += ts.columns * ts.rows;
+    }
+
+    let mut layers = vec![];
+    for (i, layer) in ms.layers.iter().enumerate() {
+        // tiled counts rows down from the top
+        let mut data = vec![0; (width * height) as usize];
+        for (TileCoords(x, y), tile) in layer.tiles.iter() {
+            let row = height - 1 - y as u32;
+            data[(row * width + x as u32) as usize] = first_gids
+                .get(&tile.tileset_id)
+                .map(|g| g + tile.tile_index)
+                .unwrap_or(0);
+        }
+        let mut properties = vec![];
+        if layer.overhead {
+            properties.push(tmj_property("overhead", json!(true)));
+        }
+        layers.push(json!({
+            "type": "tilelayer",
+            "id": i + 1,
+            "name": layer.name,
+            "x": 0,
+            "y": 0,
+            "width": width,
+            "height": height,
+            "opacity": layer.opacity,
+            "visible": layer.visible,
+            "properties": properties,
+            "data": data,
+        }));
+    }
+
+    let mut objects = vec![];
+    for y in (0..height as i32).rev() {
+        for x in 0..width as i32 {
+            let Some(tile_type) = ms.metadata_at(TileCoords(x, y)) else {
+                continue;
+            };
+            let (kind, fields) = tile_type_fields(tile_type);
+            objects.push(json!({
+                "id": objects.len() + 1,
+                "name": "",
+                "type": kind,
+                "x": x as u32 * tile_size.0,
+                "y": (height - 1 - y as u32) * tile_size.1,
+                "width": tile_size.0,
+                "height": tile_size.1,
+                "rotation": 0,
+                "visible": true,
+                "properties": fields
+                    .into_iter()
+                    .map(|(name, value)| tmj_property(name, value))
+                    .collect::<Vec<_>>(),
+            }));
+        }
+    }
+    for light in &ms.lights {
+        let TileCoords(x, y) = light.coords;
+        objects.push(json!({
+            "id": objects.len() + 1,
+            "name": "",
+            "type": "light",
+            "x": x * tile_size.0 as i32,
+            "y": (height as i32 - 1 - y) * tile_size.1 as i32,
+            "width": tile_size.0,
+            "height": tile_size.1,
+            "rotation": 0,
+            "visible": true,
+            "properties": [
+                tmj_property("radius", json!(light.radius)),
+                json!({ "name": "color", "type": "color", "value": color_to_hex(light.color) }),
+                tmj_property("falloff", json!(light.falloff.name())),
+            ],
+        }));
+    }
+    let next_object_id = objects.len() + 1;
+    layers.push(json!({
+        "type": "objectgroup",
+        "id": layers.len() + 1,
+        "name": "metadata",
+        "draworder": "topdown",
+        "x": 0,
+        "y": 0,
+        "opacity": 1,
+        "visible": true,
+        "objects": objects,
+    }));
+
+    json!({
+        "type": "map",
+        "version": "1.10",
+        "tiledversion": "1.10.2",
+        "orientation": "orthogonal",
+        "renderorder": "right-down",
+        "compressionlevel": -1,
+        "infinite": false,
+        "width": width,
+        "height": height,
+        "tilewidth": tile_size.0,
+        "tileheight": tile_size.1,
+        "nextlayerid": layers.len() + 1,
+        "nextobjectid": next_object_id,
+        "properties": [
+            tmj_property("map_name", json!(ms.map_name)),
+            tmj_property("map_id", json!(ms.map_id.to_string())),
+            tmj_property("fog_of_war", json!(ms.fog_of_war)),
+            tmj_property("ambient_light", json!(ms.ambient_light)),
+        ],
+        "tilesets": tilesets,
+        "layers": layers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TMX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" renderorder="right-down" width="3" height="2" tilewidth="16" tileheight="16" infinite="0">
+ <properties>
+  <property name="map_id" value="67e55044-10b1-426f-9247-bb680e5fe0c8"/>
+ </properties>
+ <tileset firstgid="1" name="forest" tilewidth="16" tileheight="16" tilecount="256" columns="16">
+  <image source="../tiles/forest1.png" width="256" height="256"/>
+ </tileset>
+ <layer id="1" name="ground" width="3" height="2">
+  <data encoding="csv">
+1,2,3,
+4,0,19
+</data>
+ </layer>
+ <layer id="2" name="canopy" width="3" height="2" visible="0">
+  <properties>
+   <property name="overhead" type="bool" value="true"/>
+  </properties>
+  <data>
+   <tile gid="5"/><tile/><tile/>
+   <tile/><tile/><tile/>
+  </data>
+ </layer>
+ <objectgroup id="3" name="meta">
+  <object id="1" type="wall" x="32" y="16" width="16" height="16"/>
+  <object id="2" name="exit" type="door" x="4" y="4">
+   <properties>
+    <property name="target_map" value="1ab7c0f5-3bb1-4f5c-9d5f-1f0a1b1c9e01"/>
+    <property name="target_x" type="int" value="5"/>
+    <property name="target_y" type="int" value="6"/>
+   </properties>
+   <point/>
+  </object>
+  <object id="3" name="label" x="20" y="20"/>
+ </objectgroup>
+</map>
+"#;
+
+    fn import_tmx(contents: &str) -> Result<MapScreen> {
+        read_tmx(contents, Path::new("assets/maps"))?.into_map_screen("forest", Path::new("assets"))
+    }
+
+    #[test]
+    fn imports_tmx_test() -> Result<()> {
+        let ms = import_tmx(TMX)?;
+        assert_eq!(ms.map_name, "forest");
+        assert_eq!(
+            ms.map_id.to_string(),
+            "67e55044-10b1-426f-9247-bb680e5fe0c8"
+        );
+        assert_eq!((ms.tile_cols, ms.tile_rows), (3, 2));
+        assert_eq!(ms.tilesets[0].path, PathBuf::from("tiles/forest1.png"));
+        assert_eq!((ms.tilesets[0].columns, ms.tilesets[0].rows), (16, 16));
+
+        // the top row in tiled is our top row too
+        let index = |layer, x, y| ms.get(layer, TileCoords(x, y)).map(|t| t.tile_index);
+        assert_eq!(index(0, 0, 1), Some(0));
+        assert_eq!(index(0, 2, 1), Some(2));
+        assert_eq!(index(0, 0, 0), Some(3));
+        assert_eq!(index(0, 1, 0), None);
+        assert_eq!(index(0, 2, 0), Some(18));
+        assert_eq!(index(1, 0, 1), Some(4));
+        assert!(ms.layers[1].overhead);
+        assert!(!ms.layers[1].visible);
+
+        assert_eq!(ms.metadata_at(TileCoords(2, 0)), Some(&TileType::Wall));
+        assert_eq!(
+            ms.door_at(TileCoords(0, 1)),
+            Some((
+                "1ab7c0f5-3bb1-4f5c-9d5f-1f0a1b1c9e01".parse()?,
+                TileCoords(5, 6)
+            ))
+        );
+        // the door lands on the canopy, the topmost tile there
+        assert_eq!(
+            ms.get(0, TileCoords(0, 1)).map(|t| &t.metadata),
+            Some(&None)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn imports_tmj_test() -> Result<()> {
+        let gids: Vec<u8> = [0u32, 18, 3, 0]
+            .iter()
+            .flat_map(|g| g.to_le_bytes())
+            .collect();
+        let tmj = format!(
+            r#"{{
+  "width": 2, "height": 2, "tilewidth": 16, "tileheight": 16,
+  "orientation": "orthogonal", "infinite": false,
+  "tilesets": [
+    {{"firstgid": 1, "name": "forest", "image": "../tiles/forest1.png", "columns": 16, "tilecount": 256}}
+  ],
+  "layers": [
+    {{"type": "tilelayer", "name": "ground", "width": 2, "height": 2,
+      "encoding": "base64", "data": "{}"}},
+    {{"type": "objectgroup", "name": "things", "objects": [
+      {{"type": "enemy", "x": 0, "y": 32, "width": 16, "height": 16, "gid": 4,
+        "properties": [
+          {{"name": "kind", "type": "string", "value": "slime"}},
+          {{"name": "level", "type": "int", "value": 3}}
+        ]}}
+    ]}}
+  ]
+}}"#,
+            STANDARD.encode(gids)
+        );
+        let ms = read_tmj(&tmj, Path::new("assets/maps"))?
+            .into_map_screen("forest", Path::new("assets"))?;
+        assert_eq!(ms.get(0, TileCoords(1, 1)).map(|t| t.tile_index), Some(17));
+        assert_eq!(ms.get(0, TileCoords(0, 0)).map(|t| t.tile_index), Some(2));
+        assert_eq!(
+            ms.metadata_at(TileCoords(0, 0)),
+            Some(&TileType::Enemy {
+                kind: "slime".to_owned(),
+                level: 3
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tmj_round_trip_test() -> Result<()> {
+        let mut ms = import_tmx(TMX)?;
+        ms.ambient_light = 0.25;
+        ms.lights.push(LightSource {
+            coords: TileCoords(2, 0),
+            radius: 4,
+            color: (1.0, 0.6, 0.2),
+            falloff: Falloff::Quadratic,
+        });
+        let tmj = to_tmj(&ms, &HashMap::new(), (16, 16), Path::new(".."));
+        assert_eq!(tmj["tilesets"][0]["image"], "../tiles/forest1.png");
+        assert_eq!(tmj["layers"][0]["data"], json!([1, 2, 3, 4, 0, 19]));
+
+        let reloaded = read_tmj(&tmj.to_string(), Path::new("assets/maps"))?
+            .into_map_screen("other", Path::new("assets"))?;
+        assert_eq!(reloaded.map_name, "forest");
+        assert_eq!(reloaded.map_id, ms.map_id);
+        assert_eq!(reloaded.tilesets, ms.tilesets);
+        assert_eq!(
+            reloaded.tiles().collect::<Vec<_>>(),
+            ms.tiles().collect::<Vec<_>>()
+        );
+        assert_eq!(reloaded.layers[1].overhead, ms.layers[1].overhead);
+        assert_eq!(reloaded.ambient_light, 0.25);
+        assert_eq!(reloaded.lights, ms.lights);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unsupported_test() {
+        let infinite = TMX.replace(r#"infinite="0""#, r#"infinite="1""#);
+        let err = import_tmx(&infinite).unwrap_err().to_string();
+        assert!(err.contains("infinite maps"), "{}", err);
+
+        let flipped = TMX.replace("4,0,19", "4,0,2147483667");
+        let err = import_tmx(&flipped).unwrap_err().to_string();
+        assert!(err.contains("flips or rotates"), "{}", err);
+
+        let iso = TMX.replace(r#"orientation="orthogonal""#, r#"orientation="isometric""#);
+        assert!(import_tmx(&iso).is_err());
+
+        let zstd = TMX.replace(
+            r#"<data encoding="csv">"#,
+            r#"<data encoding="base64" compression="zstd">"#,
+        );
+        assert!(import_tmx(&zstd).is_err());
+
+        let unknown = TMX.replace(r#"type="wall""#, r#"type="lava""#);
+        assert!(import_tmx(&unknown).is_err());
+
+        assert!(parse_color("#€€ab").is_err());
+        assert!(parse_color("#+1+2+3").is_err());
+        assert_eq!(parse_color("#ff000000").ok(), Some((0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn external_tileset_error_test() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("tiled-tsx-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let tmx = TMX.replace(
+            r#"<tileset firstgid="1" name="forest""#,
+            r#"<tileset firstgid="1" source="forest.tsx"/><tileset firstgid="300" name="other""#,
+        );
+        fs::write(dir.join("forest.tmx"), tmx)?;
+        fs::write(dir.join("forest.tsx"), "<tileset>\n<image =>")?;
+        let e = import(&dir.join("forest.tmx"), &dir)
+            .err()
+            .ok_or_else(|| anyhow!("a broken tileset imported"))?;
+        fs::remove_dir_all(&dir)?;
+        match e {
+            MapError::Parse { path, line, .. } => {
+                assert_eq!(path, dir.join("forest.tsx"));
+                assert_eq!(line, 2);
+            }
+            other => return Err(anyhow!("expected a parse error, got {}", other)),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,345 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs::File,
+    io::Read,
+    path::Path,
+};
+
+use super::{MapScreen, TileCoords, TileType};
+use crate::settings::GameSettings;
+use crate::tileset::TilesetMeta;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    // the map works, but probably not the way it was meant to
+    Warning,
+    // the map can't be played
+    Error,
+}
+
+// something wrong with a map. `layer` and `coords` say where, when it's
+// somewhere in particular.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub layer: Option<usize>,
+    pub coords: Option<TileCoords>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            layer: None,
+            coords: None,
+            message,
+        }
+    }
+
+    pub(super) fn warning(message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(message)
+        }
+    }
+
+    pub(super) fn at(self, layer: Option<usize>, coords: TileCoords) -> Self {
+        Diagnostic {
+            layer,
+            coords: Some(coords),
+            ..self
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning")?,
+            Severity::Error => write!(f, "error")?,
+        }
+        if let Some(layer) = self.layer {
+            write!(f, " on layer {}", layer)?;
+        }
+        if let Some(coords) = self.coords {
+            write!(f, " at {}", coords)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+// the size of a png from its header, without decoding it. None for
+// anything else.
+fn png_size(path: &Path) -> Option<(u32, u32)> {
+    let mut header = [0; 24];
+    File::open(path).ok()?.read_exact(&mut header).ok()?;
+    if &header[..8] != b"\x89PNG\r\n\x1a\n" || &header[12..16] != b"IHDR" {
+        return None;
+    }
+    let be = |b: &[u8]| u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+    Some((be(&header[16..20]), be(&header[20..24])))
+}
+
+// checks a map file that might not even load. anything that stops it
+// loading, like two tiles in one cell, is the only error.
+pub fn validate_file(
+    filename: &str,
+    settings: &GameSettings,
+    assets_root: &Path,
+) -> Vec<Diagnostic> {
+    match MapScreen::load_from_file(filename, false) {
+        Ok(ms) => ms.validate(settings, assets_root),
+        Err(e) => vec![Diagnostic::error(e.to_string())],
+    }
+}
+
+impl MapScreen {
+    // everything that can be checked about a map on its own, without the
+    // world it's in. errors first.
+    pub fn validate(&self, settings: &GameSettings, assets_root: &Path) -> Vec<Diagnostic> {
+        let mut found = self.upgrade_warnings.clone();
+        let tile_size = (settings.tile_width as u32, settings.tile_height as u32);
+
+        if self.tile_cols as f32 > settings.game_area_tile_x_max
+            || self.tile_rows as f32 > settings.game_area_tile_y_max
+        {
+            found.push(Diagnostic::warning(format!(
+                "the map is {}x{}, which is bigger than the {}x{} screen",
+                self.tile_cols,
+                self.tile_rows,
+                settings.game_area_tile_x_max,
+                settings.game_area_tile_y_max
+            )));
+        }
+
+        // how many tiles each tileset really has
+        let mut tile_counts = HashMap::new();
+        let mut ids = HashSet::new();
+        for ts in &self.tilesets {
+            let name = ts.path.to_string_lossy();
+            if !ids.insert(ts.id) {
+                found.push(Diagnostic::error(format!(
+                    "more than one tileset has id {}",
+                    ts.id
+                )));
+            }
+            let path = assets_root.join(&ts.path);
+            if !path.exists() {
+                found.push(Diagnostic::error(format!("tileset {} is missing", name)));
+                continue;
+            }
+            let meta = match TilesetMeta::load_for(&ts.path, assets_root) {
+                Ok(meta) => meta,
+                Err(e) => {
+                    found.push(Diagnostic::error(format!(
+                        "tileset {} has a sidecar that won't load: {}",
+                        name, e
+                    )));
+                    TilesetMeta::default()
+                }
+            };
+            let mut count = ts.columns * ts.rows;
+            if let Some(sheet) = png_size(&path) {
+                let (cols, rows) = meta.grid_size(sheet, meta.tile_size_or(tile_size));
+                if (cols, rows) != (ts.columns, ts.rows) {
+                    found.push(Diagnostic::warning(format!(
+                        "tileset {} is listed as {}x{} tiles, but the image has {}x{}",
+                        name, ts.columns, ts.rows, cols, rows
+                    )));
+                    count = count.min(cols * rows);
+                }
+            }
+            tile_counts.insert(ts.id, count);
+        }
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            if (layer.tiles.cols(), layer.tiles.rows()) != (self.tile_cols, self.tile_rows) {
+                found.push(Diagnostic::warning(format!(
+                    "layer {} is {}x{}, but the map is {}x{}",
+                    layer.name,
+                    layer.tiles.cols(),
+                    layer.tiles.rows(),
+                    self.tile_cols,
+                    self.tile_rows
+                )));
+            }
+            for (coords, tile) in layer.tiles.iter() {
+                let here = |d: Diagnostic| d.at(Some(i), coords);
+                if !self.contains(coords) {
+                    found.push(here(Diagnostic::error(
+                        "the tile is off the map".to_owned(),
+                    )));
+                }
+                match (
+                    self.get_tileset(tile.tileset_id),
+                    tile_counts.get(&tile.tileset_id),
+                ) {
+                    (None, _) => found.push(here(Diagnostic::error(format!(
+                        "the tile uses tileset {}, which the map doesn't have",
+                        tile.tileset_id
+                    )))),
+                    (Some(ts), Some(count)) if tile.tile_index >= *count => {
+                        found.push(here(Diagnostic::error(format!(
+                            "tile {} isn't in {}, which has {}",
+                            tile.tile_index,
+                            ts.path.to_string_lossy(),
+                            count
+                        ))))
+                    }
+                    _ => (),
+                }
+                if let Some(TileType::Door { target, .. }) = &tile.metadata {
+                    if target.0 < 0 || target.1 < 0 {
+                        found.push(here(Diagnostic::error(format!(
+                            "the door leads to {}, which is off any map",
+                            target
+                        ))));
+                    }
+                }
+            }
+        }
+
+        for light in &self.lights {
+            if !self.contains(light.coords) {
+                found.push(
+                    Diagnostic::warning("the light is off the map".to_owned())
+                        .at(None, light.coords),
+                );
+            }
+        }
+        if !(0.0..=1.0).contains(&self.ambient_light) {
+            found.push(Diagnostic::warning(format!(
+                "ambient light is {}, but should be from 0 to 1",
+                self.ambient_light
+            )));
+        }
+        for edge in [
+            self.neighbors.north,
+            self.neighbors.south,
+            self.neighbors.east,
+            self.neighbors.west,
+        ] {
+            if edge == Some(self.map_id) {
+                found.push(Diagnostic::warning(
+                    "the map is its own neighbour".to_owned(),
+                ));
+            }
+        }
+
+        found.sort_by_key(|d| std::cmp::Reverse(d.severity));
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::lighting::LightSource;
+    use crate::settings::SettingsFile;
+    use crate::tilemap::{MapTile, TileGrid, TileLayer};
+
+    fn settings() -> GameSettings {
+        GameSettings::new_from_sf(
+            &SettingsFile {
+                scale: 1.,
+                x_max: 24.,
+                y_max: 18.,
+                input_debounce: 0.,
+                tile_width: 16.,
+                tile_height: 16.,
+                tile_z: 0.,
+                game_z: 1.,
+            },
+            false,
+        )
+    }
+
+    fn tile(tileset_id: u32, tile_index: u32) -> MapTile {
+        MapTile {
+            tileset_id,
+            tile_index,
+            metadata: None,
+        }
+    }
+
+    // just enough of a png for its size to be read
+    fn fake_png(path: &Path, width: u32, height: u32) -> Result<()> {
+        let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        data.extend(width.to_be_bytes());
+        data.extend(height.to_be_bytes());
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+
+    #[test]
+    fn validate_test() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("validate-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("tiles"))?;
+        fake_png(&dir.join("tiles/forest1.png"), 256, 256)?;
+        let assets = dir.as_path();
+        let mut ms = MapScreen::new(18, 24, None);
+        // 16x16 tiles
+        ms.add_tileset("tiles/forest1.png".into(), 16, 16);
+        ms.set(0, TileCoords(0, 0), tile(0, 255))?;
+        assert_eq!(ms.validate(&settings(), assets), vec![]);
+
+        ms.set(0, TileCoords(1, 0), tile(0, 256))?;
+        ms.set(0, TileCoords(2, 0), tile(3, 0))?;
+        ms.add_tileset("tiles/nowhere.png".into(), 1, 1);
+        ms.lights.push(LightSource {
+            coords: TileCoords(30, 0),
+            radius: 1,
+            color: (1., 1., 1.),
+            falloff: Default::default(),
+        });
+        // a layer that's been made too big, with a tile past the edge
+        let mut big = TileGrid::new(30, 30);
+        big.set(TileCoords(29, 29), tile(0, 1))?;
+        ms.layers.push(TileLayer {
+            tiles: big,
+            ..TileLayer::new("big", false, 1, 1)
+        });
+
+        let found = ms.validate(&settings(), assets);
+        let errors: Vec<String> = found
+            .iter()
+            .filter(|d| d.is_error())
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "error: tileset tiles/nowhere.png is missing",
+                "error on layer 0 at 1,0: tile 256 isn't in tiles/forest1.png, which has 256",
+                "error on layer 0 at 2,0: the tile uses tileset 3, which the map doesn't have",
+                "error on layer 1 at 29,29: the tile is off the map",
+            ]
+        );
+        assert_eq!(found.iter().filter(|d| !d.is_error()).count(), 2);
+
+        // the image is smaller than the map says
+        fake_png(&dir.join("tiles/forest1.png"), 256, 128)?;
+        let found = ms.validate(&settings(), assets);
+        assert!(found.iter().any(|d| d
+            .to_string()
+            .contains("tile 255 isn't in tiles/forest1.png, which has 128")));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn validate_file_test() -> Result<()> {
+        let found = validate_file("nowhere.ron", &settings(), Path::new("assets"));
+        assert_eq!(found.len(), 1);
+        assert!(found[0].is_error());
+        Ok(())
+    }
+}
@@ -0,0 +1,525 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use bevy::math::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::error::MapError;
+use crate::tilemap::TileType;
+
+// extra information about a tileset that isn't in the image itself. it
+// lives next to the sheet, so `tiles/forest1.png` is described by
+// `tiles/forest1.tileset.ron`. a sheet without one is just pictures, cut
+// up using the game's tile size.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct TilesetMeta {
+    // in pixels, when it isn't the game's tile size
+    #[serde(default)]
+    pub tile_size: Option<(u32, u32)>,
+    // pixels around the outside of the sheet, and between tiles
+    #[serde(default)]
+    pub margin: u32,
+    #[serde(default)]
+    pub spacing: u32,
+    #[serde(default)]
+    pub tiles: HashMap<u32, TileMeta>,
+    // brushes that pick their own tile, keyed by name. kept in name order,
+    // so a tile in two terrains always belongs to the same one.
+    #[serde(default)]
+    pub terrains: BTreeMap<String, Terrain>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct TileMeta {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // given to the tile when it's painted, so every tree doesn't have to be
+    // marked as a wall by hand
+    #[serde(default)]
+    pub tile_type: Option<TileType>,
+    #[serde(default)]
+    pub collision: Option<CollisionShape>,
+    // frames to cycle through in place of this tile, in order
+    #[serde(default)]
+    pub animation: Vec<AnimationFrame>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationFrame {
+    pub tile_index: u32,
+    pub duration_ms: u32,
+}
+
+// the tile showing `elapsed_ms` into a looping animation, or None if
+// there's nothing to play
+pub fn frame_at(frames: &[AnimationFrame], elapsed_ms: u64) -> Option<u32> {
+    let total: u64 = frames.iter().map(|f| f.duration_ms as u64).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut t = elapsed_ms % total;
+    for frame in frames {
+        if t < frame.duration_ms as u64 {
+            return Some(frame.tile_index);
+        }
+        t -= frame.duration_ms as u64;
+    }
+    None
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+// part of a tile, measured in tiles with (0, 0) in the bottom left
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct TileRect {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl TileRect {
+    pub const FULL: TileRect = TileRect {
+        x: 0.,
+        y: 0.,
+        w: 1.,
+        h: 1.,
+    };
+
+    // touching edges don't count
+    pub fn overlaps(&self, min: Vec2, max: Vec2) -> bool {
+        min.x < self.x + self.w && max.x > self.x && min.y < self.y + self.h && max.y > self.y
+    }
+}
+
+// the solid part of a tile
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum CollisionShape {
+    Full,
+    Half(Side),
+    Quarter(Corner),
+    Rects(Vec<TileRect>),
+    // a triangle filling the corner, cut from the two corners next to it
+    Diagonal(Corner),
+}
+
+impl CollisionShape {
+    // whether a box, in the tile's own units, hits the solid part
+    pub fn overlaps(&self, min: Vec2, max: Vec2) -> bool {
+        match self {
+            CollisionShape::Full => TileRect::FULL.overlaps(min, max),
+            CollisionShape::Half(side) => {
+                let rect = match side {
+                    Side::Top => TileRect {
+                        y: 0.5,
+                        h: 0.5,
+                        ..TileRect::FULL
+                    },
+                    Side::Bottom => TileRect {
+                        h: 0.5,
+                        ..TileRect::FULL
+                    },
+                    Side::Left => TileRect {
+                        w: 0.5,
+                        ..TileRect::FULL
+                    },
+                    Side::Right => TileRect {
+                        x: 0.5,
+                        w: 0.5,
+                        ..TileRect::FULL
+                    },
+                };
+                rect.overlaps(min, max)
+            }
+            CollisionShape::Quarter(corner) => {
+                let (x, y) = match corner {
+                    Corner::TopLeft => (0., 0.5),
+                    Corner::TopRight => (0.5, 0.5),
+                    Corner::BottomLeft => (0., 0.),
+                    Corner::BottomRight => (0.5, 0.),
+                };
+                TileRect {
+                    x,
+                    y,
+                    w: 0.5,
+                    h: 0.5,
+                }
+                .overlaps(min, max)
+            }
+            CollisionShape::Rects(rects) => rects.iter().any(|r| r.overlaps(min, max)),
+            CollisionShape::Diagonal(corner) => {
+                if !TileRect::FULL.overlaps(min, max) {
+                    return false;
+                }
+                // the part of the box inside the tile, then the point of it
+                // closest to the solid corner
+                let min = min.max(Vec2::ZERO);
+                let max = max.min(Vec2::ONE);
+                let nearest = match corner {
+                    Corner::BottomLeft => min.x + min.y,
+                    Corner::TopRight => (1. - max.x) + (1. - max.y),
+                    Corner::BottomRight => (1. - max.x) + min.y,
+                    Corner::TopLeft => min.x + (1. - max.y),
+                };
+                nearest < 1.
+            }
+        }
+    }
+}
+
+// how a terrain tile looks at its neighbours. four bit sets only care
+// about the sides, so 16 tiles cover every case. blob sets care about the
+// corners too, but only when both sides next to the corner match, which
+// leaves 47 tiles.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum AutotileKind {
+    FourBit,
+    Blob,
+}
+
+// neighbours, as bits of a mask
+pub const NORTH: u8 = 1;
+pub const NORTH_EAST: u8 = 2;
+pub const EAST: u8 = 4;
+pub const SOUTH_EAST: u8 = 8;
+pub const SOUTH: u8 = 16;
+pub const SOUTH_WEST: u8 = 32;
+pub const WEST: u8 = 64;
+pub const NORTH_WEST: u8 = 128;
+
+const FOUR_BIT_MASKS: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+// the masks `reduce` leaves alone: corners only count between two sides
+const BLOB_MASKS: [u8; 47] = [
+    0, 1, 4, 5, 7, 16, 17, 20, 21, 23, 28, 29, 31, 64, 65, 68, 69, 71, 80, 81, 84, 85, 87, 92, 93,
+    95, 112, 113, 116, 117, 119, 124, 125, 127, 193, 197, 199, 209, 213, 215, 221, 223, 241, 245,
+    247, 253, 255,
+];
+
+impl AutotileKind {
+    // the mask a tile is looked up by, from which of the eight neighbours
+    // match. four bit masks are renumbered north 1, east 2, south 4, west 8,
+    // which is how most sheets are laid out.
+    pub fn reduce(self, neighbours: u8) -> u8 {
+        let has = |bit: u8| neighbours & bit != 0;
+        match self {
+            AutotileKind::FourBit => [NORTH, EAST, SOUTH, WEST]
+                .iter()
+                .enumerate()
+                .filter(|(_, bit)| has(**bit))
+                .map(|(i, _)| 1 << i)
+                .sum(),
+            AutotileKind::Blob => {
+                let mut mask = neighbours;
+                for (corner, a, b) in [
+                    (NORTH_EAST, NORTH, EAST),
+                    (SOUTH_EAST, SOUTH, EAST),
+                    (SOUTH_WEST, SOUTH, WEST),
+                    (NORTH_WEST, NORTH, WEST),
+                ] {
+                    if !has(a) || !has(b) {
+                        mask &= !corner;
+                    }
+                }
+                mask
+            }
+        }
+    }
+
+    // every mask a set of this kind has a tile for, smallest first
+    pub fn masks(self) -> &'static [u8] {
+        match self {
+            AutotileKind::FourBit => &FOUR_BIT_MASKS,
+            AutotileKind::Blob => &BLOB_MASKS,
+        }
+    }
+
+    // the mask for a tile with matching neighbours all round
+    pub fn full(self) -> u8 {
+        match self {
+            AutotileKind::FourBit => 15,
+            AutotileKind::Blob => 255,
+        }
+    }
+}
+
+// a set of tiles that join up with each other
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Terrain {
+    pub kind: AutotileKind,
+    // where the set starts in the sheet, when its tiles run in mask order
+    #[serde(default)]
+    pub first_tile: Option<u32>,
+    // mask -> tile index, for sheets laid out some other way. these win
+    // over `first_tile`.
+    #[serde(default)]
+    pub tiles: HashMap<u8, u32>,
+}
+
+impl Terrain {
+    pub fn tile_for(&self, mask: u8) -> Option<u32> {
+        if let Some(tile_index) = self.tiles.get(&mask) {
+            return Some(*tile_index);
+        }
+        let first = self.first_tile?;
+        let offset = self.kind.masks().iter().position(|m| *m == mask)?;
+        Some(first + offset as u32)
+    }
+
+    pub fn contains(&self, tile_index: u32) -> bool {
+        let in_run = self.first_tile.is_some_and(|first| {
+            tile_index >= first && tile_index < first + self.kind.masks().len() as u32
+        });
+        in_run || self.tiles.values().any(|t| *t == tile_index)
+    }
+}
+
+impl TilesetMeta {
+    pub fn path_for(tileset_path: &Path) -> PathBuf {
+        tileset_path.with_extension("tileset.ron")
+    }
+
+    pub fn new_from_file(filename: &str) -> Result<Self, MapError> {
+        let file_data = fs::read_to_string(filename).map_err(|e| MapError::io(filename, e))?;
+        ron::from_str(&file_data).map_err(|e| MapError::parse(filename, e))
+    }
+
+    // the sidecar for a tileset, or an empty one if there isn't any.
+    // `tileset_path` is relative to `assets_root`, like it is in maps.
+    pub fn load_for(tileset_path: &Path, assets_root: &Path) -> Result<Self, MapError> {
+        let path = assets_root.join(Self::path_for(tileset_path));
+        if !path.exists() {
+            return Ok(TilesetMeta::default());
+        }
+        Self::new_from_file(&path.to_string_lossy())
+    }
+
+    pub fn tile_size_or(&self, default: (u32, u32)) -> (u32, u32) {
+        self.tile_size.unwrap_or(default)
+    }
+
+    // how many whole tiles fit across and down a sheet of the given size
+    pub fn grid_size(&self, sheet: (u32, u32), tile_size: (u32, u32)) -> (u32, u32) {
+        let fit = |sheet: u32, tile: u32| {
+            (sheet.saturating_sub(self.margin * 2) + self.spacing) / (tile + self.spacing)
+        };
+        (fit(sheet.0, tile_size.0), fit(sheet.1, tile_size.1))
+    }
+
+    // pixel position of a tile's top left corner in the sheet
+    pub fn tile_origin(&self, col: u32, row: u32, tile_size: (u32, u32)) -> (u32, u32) {
+        (
+            self.margin + col * (tile_size.0 + self.spacing),
+            self.margin + row * (tile_size.1 + self.spacing),
+        )
+    }
+
+    pub fn tile(&self, tile_index: u32) -> Option<&TileMeta> {
+        self.tiles.get(&tile_index)
+    }
+
+    pub fn name(&self, tile_index: u32) -> Option<&str> {
+        self.tile(tile_index).and_then(|t| t.name.as_deref())
+    }
+
+    pub fn tile_type(&self, tile_index: u32) -> Option<TileType> {
+        self.tile(tile_index).and_then(|t| t.tile_type.clone())
+    }
+
+    pub fn animation(&self, tile_index: u32) -> &[AnimationFrame] {
+        self.tile(tile_index)
+            .map(|t| t.animation.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn collision(&self, tile_index: u32) -> Option<&CollisionShape> {
+        self.tile(tile_index).and_then(|t| t.collision.as_ref())
+    }
+
+    // the terrain a tile belongs to, if any
+    pub fn terrain_of(&self, tile_index: u32) -> Option<(&str, &Terrain)> {
+        self.terrains
+            .iter()
+            .find(|(_, t)| t.contains(tile_index))
+            .map(|(name, t)| (name.as_str(), t))
+    }
+
+    pub fn tagged<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = u32> + 'a {
+        self.tiles
+            .iter()
+            .filter(move |(_, meta)| meta.tags.iter().any(|t| t == tag))
+            .map(|(i, _)| *i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const META: &str = r#"TilesetMeta(
+  tile_size: Some((16, 16)),
+  margin: 1,
+  spacing: 2,
+  tiles: {
+    3: TileMeta(
+      name: Some("fence"),
+      tags: ["fence", "wood"],
+      collision: Some(Half(Bottom)),
+    ),
+    7: TileMeta(
+      name: Some("tree trunk"),
+      tags: ["tree"],
+      tile_type: Some(Wall),
+      collision: Some(Rects([TileRect(x: 0.375, y: 0.0, w: 0.25, h: 0.5)])),
+    ),
+    9: TileMeta(
+      name: Some("water"),
+      animation: [
+        (tile_index: 9, duration_ms: 200),
+        (tile_index: 10, duration_ms: 100),
+      ],
+    ),
+  },
+  terrains: {
+    "path": Terrain(kind: FourBit, first_tile: Some(16)),
+    "forest": Terrain(kind: Blob, tiles: {255: 40, 0: 41}),
+  },
+)"#;
+
+    #[test]
+    fn parses_meta_test() -> Result<()> {
+        let meta: TilesetMeta = ron::from_str(META)?;
+        assert_eq!(meta.collision(3), Some(&CollisionShape::Half(Side::Bottom)));
+        assert!(matches!(meta.collision(7), Some(CollisionShape::Rects(r)) if r.len() == 1));
+        assert_eq!(meta.collision(9), None);
+        assert_eq!(meta.collision(100), None);
+        assert_eq!(meta.name(3), Some("fence"));
+        assert_eq!(meta.tile_type(7), Some(TileType::Wall));
+        assert_eq!(meta.tile_type(3), None);
+        assert_eq!(meta.tagged("tree").collect::<Vec<_>>(), vec![7]);
+        Ok(())
+    }
+
+    #[test]
+    fn sheet_layout_test() -> Result<()> {
+        let meta: TilesetMeta = ron::from_str(META)?;
+        let tile_size = meta.tile_size_or((8, 8));
+        // 1 + 16 * 4 + 2 * 3 + 1 across, and a bit left over
+        assert_eq!(meta.grid_size((72, 80), tile_size), (4, 4));
+        assert_eq!(meta.tile_origin(2, 1, tile_size), (37, 19));
+        assert_eq!(
+            TilesetMeta::default().grid_size((256, 256), tile_size),
+            (16, 16)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn animation_test() -> Result<()> {
+        let meta: TilesetMeta = ron::from_str(META)?;
+        let water = meta.animation(9);
+        assert_eq!(frame_at(water, 0), Some(9));
+        assert_eq!(frame_at(water, 199), Some(9));
+        assert_eq!(frame_at(water, 200), Some(10));
+        // loops back round
+        assert_eq!(frame_at(water, 300), Some(9));
+        assert_eq!(frame_at(meta.animation(3), 300), None);
+        assert_eq!(
+            frame_at(
+                &[AnimationFrame {
+                    tile_index: 1,
+                    duration_ms: 0
+                }],
+                10
+            ),
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn terrain_test() -> Result<()> {
+        let meta: TilesetMeta = ron::from_str(META)?;
+        let path = &meta.terrains["path"];
+        assert_eq!(path.tile_for(0), Some(16));
+        assert_eq!(path.tile_for(15), Some(31));
+        assert!(path.contains(31));
+        assert!(!path.contains(32));
+        assert_eq!(meta.terrain_of(41).map(|(n, _)| n), Some("forest"));
+        assert_eq!(meta.terrain_of(3), None);
+        assert_eq!(meta.terrains["forest"].tile_for(NORTH), None);
+
+        // a tile in two terrains goes to the first by name, every time
+        let mut meta = meta;
+        let mut grass = meta.terrains["path"].clone();
+        grass.first_tile = Some(20);
+        meta.terrains.insert("grass".into(), grass);
+        assert_eq!(meta.terrain_of(25).map(|(n, _)| n), Some("grass"));
+        assert_eq!(meta.terrain_of(17).map(|(n, _)| n), Some("path"));
+        Ok(())
+    }
+
+    #[test]
+    fn autotile_masks_test() {
+        assert_eq!(AutotileKind::FourBit.masks().len(), 16);
+        assert_eq!(AutotileKind::Blob.masks().len(), 47);
+        let reduced: Vec<u8> = (0..=255)
+            .filter(|m| AutotileKind::Blob.reduce(*m) == *m)
+            .collect();
+        assert_eq!(AutotileKind::Blob.masks(), reduced.as_slice());
+        // a lone corner doesn't count
+        assert_eq!(AutotileKind::Blob.reduce(NORTH | NORTH_EAST), NORTH);
+        assert_eq!(
+            AutotileKind::Blob.reduce(NORTH | NORTH_EAST | EAST),
+            NORTH | NORTH_EAST | EAST
+        );
+        assert_eq!(AutotileKind::FourBit.reduce(NORTH | EAST | NORTH_EAST), 3);
+        assert_eq!(AutotileKind::FourBit.reduce(WEST | SOUTH_WEST), 8);
+    }
+
+    #[test]
+    fn path_for_test() {
+        assert_eq!(
+            TilesetMeta::path_for(Path::new("tiles/forest1.png")),
+            PathBuf::from("tiles/forest1.tileset.ron")
+        );
+    }
+
+    #[test]
+    fn shape_overlap_test() {
+        let v = Vec2::new;
+        // a box on the bottom half of the tile
+        let (min, max) = (v(0.25, 0.), v(0.75, 0.4));
+        assert!(CollisionShape::Full.overlaps(min, max));
+        assert!(CollisionShape::Half(Side::Bottom).overlaps(min, max));
+        assert!(!CollisionShape::Half(Side::Top).overlaps(min, max));
+        assert!(!CollisionShape::Quarter(Corner::TopRight).overlaps(min, max));
+        assert!(CollisionShape::Quarter(Corner::BottomRight).overlaps(min, max));
+        // a box in the bottom left corner
+        let (min, max) = (v(0., 0.), v(0.4, 0.4));
+        assert!(CollisionShape::Diagonal(Corner::BottomLeft).overlaps(min, max));
+        assert!(CollisionShape::Diagonal(Corner::TopLeft).overlaps(min, max));
+        assert!(!CollisionShape::Diagonal(Corner::TopRight).overlaps(min, max));
+        // only touching the edge of the tile
+        assert!(!CollisionShape::Full.overlaps(v(1., 0.), v(2., 1.)));
+    }
+}
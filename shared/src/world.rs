@@ -0,0 +1,350 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::MapError;
+use crate::tilemap::{MapScreen, TileCoords};
+
+// ties map screens together. maps are listed by id, and connections say
+// where the hero goes when they walk through a door or off a screen edge.
+#[derive(Debug, Clone, Serialize, Deserialize, Asset, TypePath)]
+pub struct World {
+    pub name: String,
+    pub start_map: uuid::Uuid,
+    pub start: TileCoords,
+    pub maps: Vec<WorldMap>,
+    pub connections: Vec<Connection>,
+    // map paths are relative to the world file
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldMap {
+    pub map_id: uuid::Uuid,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    North,
+    South,
+    East,
+    West,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum Connection {
+    Door {
+        from_map: uuid::Uuid,
+        at: TileCoords,
+        to_map: uuid::Uuid,
+        target: TileCoords,
+    },
+    // the hero keeps their row (or column) and comes in on the opposite
+    // edge. neighbours set on the map itself take priority.
+    Edge {
+        from_map: uuid::Uuid,
+        edge: Edge,
+        to_map: uuid::Uuid,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub to_map: uuid::Uuid,
+    pub target: TileCoords,
+    // set when the hero walked off the screen rather than through a door
+    pub edge: Option<Edge>,
+}
+
+impl World {
+    pub fn new_from_file(filename: &str) -> Result<Self, MapError> {
+        let file_data = fs::read_to_string(filename).map_err(|e| MapError::io(filename, e))?;
+        let mut world: World =
+            ron::from_str(&file_data).map_err(|e| MapError::parse(filename, e))?;
+        world.base_dir = Path::new(filename)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        Ok(world)
+    }
+
+    pub fn get_map_path(&self, map_id: uuid::Uuid) -> Option<PathBuf> {
+        self.maps
+            .iter()
+            .find(|m| m.map_id == map_id)
+            .map(|m| self.base_dir.join(&m.path))
+    }
+
+    pub fn load_map(&self, map_id: uuid::Uuid) -> Result<MapScreen, MapError> {
+        let path = self.get_map_path(map_id).ok_or_else(|| MapError::Invalid {
+            path: self.base_dir.clone(),
+            message: format!("map {} is not part of world {}", map_id, self.name),
+        })?;
+        let ms = MapScreen::new_from_file(&path.to_string_lossy())?;
+        if ms.map_id != map_id {
+            return Err(MapError::Invalid {
+                message: format!("has id {}, but the world lists it as {}", ms.map_id, map_id),
+                path,
+            });
+        }
+        Ok(ms)
+    }
+
+    // where the hero ends up if they step onto `coords` in `map`. doors and
+    // neighbours set on the map itself win over connections listed in the
+    // world, and anything outside the map's bounds is an edge exit.
+    pub fn exit_at(&self, map: &MapScreen, coords: TileCoords) -> Option<Transition> {
+        if let Some((to_map, target)) = map.door_at(coords) {
+            return Some(Transition {
+                to_map,
+                target,
+                edge: None,
+            });
+        }
+
+        let edge = Self::edge_for(map, coords);
+        if let Some((edge, to_map)) = edge.and_then(|e| map.neighbors.get(e).map(|id| (e, id))) {
+            return Some(Transition {
+                to_map,
+                target: Self::enter_from(map, coords, edge),
+                edge: Some(edge),
+            });
+        }
+
+        self.connections.iter().find_map(|c| match c {
+            Connection::Door {
+                from_map,
+                at,
+                to_map,
+                target,
+            } if *from_map == map.map_id && *at == coords => Some(Transition {
+                to_map: *to_map,
+                target: *target,
+                edge: None,
+            }),
+            Connection::Edge {
+                from_map,
+                edge: exit_edge,
+                to_map,
+            } if *from_map == map.map_id && Some(*exit_edge) == edge => Some(Transition {
+                to_map: *to_map,
+                target: Self::enter_from(map, coords, *exit_edge),
+                edge: Some(*exit_edge),
+            }),
+            _ => None,
+        })
+    }
+
+    pub fn edge_for(map: &MapScreen, coords: TileCoords) -> Option<Edge> {
+        let TileCoords(x, y) = coords;
+        if x < 0 {
+            Some(Edge::West)
+        } else if x >= map.tile_cols as i32 {
+            Some(Edge::East)
+        } else if y < 0 {
+            Some(Edge::South)
+        } else if y >= map.tile_rows as i32 {
+            Some(Edge::North)
+        } else {
+            None
+        }
+    }
+
+    // screens are all the same size, so the far edge of `map` is also the
+    // far edge of the map being entered
+    fn enter_from(map: &MapScreen, coords: TileCoords, edge: Edge) -> TileCoords {
+        let TileCoords(x, y) = coords;
+        match edge {
+            Edge::East => TileCoords(0, y),
+            Edge::West => TileCoords(map.tile_cols as i32 - 1, y),
+            Edge::North => TileCoords(x, 0),
+            Edge::South => TileCoords(x, map.tile_rows as i32 - 1),
+        }
+    }
+}
+
+// reads worlds through the asset server. map paths come out relative to
+// the assets folder, so they can be loaded the same way.
+#[derive(Default)]
+pub struct WorldLoader;
+
+impl AssetLoader for WorldLoader {
+    type Asset = World;
+    type Settings = ();
+    type Error = MapError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<World, MapError>> {
+        Box::pin(async move {
+            let path = load_context.path().to_path_buf();
+            let mut contents = String::new();
+            reader
+                .read_to_string(&mut contents)
+                .await
+                .map_err(|e| MapError::io(&path, e))?;
+            let mut world: World =
+                ron::from_str(&contents).map_err(|e| MapError::from_load(&path, e.into()))?;
+            world.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            Ok(world)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["world.ron"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORLD: &str = r#"World(
+  name: "forest",
+  start_map: "67e55044-10b1-426f-9247-bb680e5fe0c8",
+  start: (1, 16),
+  maps: [
+    WorldMap(map_id: "67e55044-10b1-426f-9247-bb680e5fe0c8", path: "test.map.ron"),
+    WorldMap(map_id: "1ab7c0f5-3bb1-4f5c-9d5f-1f0a1b1c9e01", path: "map1.map.ron"),
+  ],
+  connections: [
+    Door(
+      from_map: "67e55044-10b1-426f-9247-bb680e5fe0c8",
+      at: (3, 3),
+      to_map: "1ab7c0f5-3bb1-4f5c-9d5f-1f0a1b1c9e01",
+      target: (10, 10),
+    ),
+    Edge(
+      from_map: "67e55044-10b1-426f-9247-bb680e5fe0c8",
+      edge: East,
+      to_map: "1ab7c0f5-3bb1-4f5c-9d5f-1f0a1b1c9e01",
+    ),
+  ],
+)"#;
+
+    fn start_map(world: &World) -> MapScreen {
+        MapScreen {
+            map_id: world.start_map,
+            ..MapScreen::default()
+        }
+    }
+
+    #[test]
+    fn door_connection_test() -> Result<()> {
+        let world: World = ron::from_str(WORLD)?;
+        let ms = start_map(&world);
+        let t = world.exit_at(&ms, TileCoords(3, 3));
+        assert_eq!(
+            t,
+            Some(Transition {
+                to_map: world.maps[1].map_id,
+                target: TileCoords(10, 10),
+                edge: None,
+            })
+        );
+        assert_eq!(world.exit_at(&ms, TileCoords(3, 4)), None);
+        Ok(())
+    }
+
+    #[test]
+    fn edge_connection_test() -> Result<()> {
+        let world: World = ron::from_str(WORLD)?;
+        let ms = start_map(&world);
+        let t = world.exit_at(&ms, TileCoords(ms.tile_cols as i32, 7));
+        assert_eq!(
+            t,
+            Some(Transition {
+                to_map: world.maps[1].map_id,
+                target: TileCoords(0, 7),
+                edge: Some(Edge::East),
+            })
+        );
+        // no exit to the west
+        assert_eq!(world.exit_at(&ms, TileCoords(-1, 7)), None);
+        Ok(())
+    }
+
+    #[test]
+    fn map_neighbor_test() -> Result<()> {
+        let world: World = ron::from_str(WORLD)?;
+        let mut ms = start_map(&world);
+        ms.neighbors.west = Some(world.maps[1].map_id);
+        ms.neighbors.east = Some(uuid::Uuid::nil());
+        assert_eq!(
+            world.exit_at(&ms, TileCoords(-1, 7)),
+            Some(Transition {
+                to_map: world.maps[1].map_id,
+                target: TileCoords(ms.tile_cols as i32 - 1, 7),
+                edge: Some(Edge::West),
+            })
+        );
+        // the map's own neighbour wins over the world's connection
+        assert_eq!(
+            world
+                .exit_at(&ms, TileCoords(ms.tile_cols as i32, 7))
+                .map(|t| t.to_map),
+            Some(uuid::Uuid::nil())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn map_path_test() -> Result<()> {
+        let mut world: World = ron::from_str(WORLD)?;
+        world.base_dir = PathBuf::from("assets/data");
+        assert_eq!(
+            world.get_map_path(world.maps[1].map_id),
+            Some(PathBuf::from("assets/data/map1.map.ron"))
+        );
+        assert_eq!(world.get_map_path(uuid::Uuid::nil()), None);
+        Ok(())
+    }
+
+    #[test]
+    fn checked_in_world_test() -> Result<()> {
+        let world: World = ron::from_str(include_str!("../../game/assets/data/forest.world.ron"))?;
+        assert!(world.maps.iter().any(|m| m.map_id == world.start_map));
+        Ok(())
+    }
+
+    #[test]
+    fn load_error_test() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("world-error-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("broken.world.ron");
+        fs::write(&path, "World(\n  name: 3,\n)")?;
+        let e = World::new_from_file(&path.to_string_lossy())
+            .err()
+            .ok_or_else(|| anyhow::anyhow!("a broken world loaded"))?;
+        assert!(matches!(e, MapError::Parse { line: 2, .. }));
+
+        let e = World::new_from_file(&dir.join("missing.world.ron").to_string_lossy())
+            .err()
+            .ok_or_else(|| anyhow::anyhow!("a missing world loaded"))?;
+        assert!(matches!(e, MapError::Io { .. }));
+
+        // a map that isn't in the world isn't loaded from anywhere
+        let world: World = ron::from_str(WORLD)?;
+        let e = world
+            .load_map(uuid::Uuid::nil())
+            .err()
+            .ok_or_else(|| anyhow::anyhow!("a stray map loaded"))?;
+        assert!(matches!(e, MapError::Invalid { .. }));
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}